@@ -39,9 +39,9 @@ impl<'a> Iterator for CharsReader<'a>  {
             Err(_) => return None
         };
 
-        self.cache = helpers::from_utf8_forced(&buffer).chars();
+        self.cache = helpers::from_utf8_forced(buffer).chars();
 
-        return match self.cache.next() {
+        match self.cache.next() {
             Some(it) => {
                 self.stream.consume(it.len_utf8());
                 Some(it)
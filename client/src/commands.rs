@@ -2,44 +2,41 @@ use std::iter::{Peekable};
 use std::path::{Path};
 
 use crate::chars_reader::{CharsReader};
+use crate::tokenizer::{tokenize};
 
-use super::{ArsonClientSession};
+use super::{AnyClientSession};
 
-use shared::communication::{DEFAULT_PORT};
-use shared::connection::messages::{MAXIMUM_TEXT_SIZE, MAXIMUM_NAME_SIZE, MAXIMUM_FILE_NAME_SIZE};
+use shared::communication::{DEFAULT_PORT, DEFAULT_TLS_PORT};
+use shared::connection::messages::{BanTarget};
 
 pub enum Command {
     Nothing,
     End,
     Text { text: String },
     Rename { new_name: String },
+    Register { name: String, password: String },
+    Login { name: String, password: String },
     Connect { address: String },
     UploadFile { name: String, path: String },
     DownloadFile { name: String, path: String },
+    ListUsers,
+    Promote { name: String },
+    Kick { name: String },
+    Ban { target: BanTarget, duration_seconds: Option<u64> },
+    Mute { name: String },
+    Unmute { name: String },
 }
 
 pub enum CommandProcessing {
     Proceed,
     Stop,
-    Connect(ArsonClientSession)
-}
-
-fn is_blank(symbol: char) -> bool {
-    symbol == '\r' ||
-    symbol == '\n' ||
-    symbol == '\t' ||
-    symbol == ' '
+    Connect(AnyClientSession)
 }
 
 fn parse_rename(words: &[String]) -> Command {
     if words.len() >= 2 {
-        if words[1].len() > MAXIMUM_NAME_SIZE {
-            println!("(Console) No way, sorry, this is way too long");
-            Command::Nothing
-        } else {
-            Command::Rename {
-                new_name: words[1].clone(),
-            }
+        Command::Rename {
+            new_name: words[1].clone(),
         }
     } else {
         println!("(Console) Rename to who? Vasya, Petia - who exactly?");
@@ -47,18 +44,138 @@ fn parse_rename(words: &[String]) -> Command {
     }
 }
 
+fn check_credentials(words: &[String]) -> Option<(String, String)> {
+    if words.len() < 3 {
+        println!("(Console) I need both a name and a password, in this very order");
+        return None;
+    }
+
+    Some((words[1].clone(), words[2].clone()))
+}
+
+fn parse_register(words: &[String]) -> Command {
+    match check_credentials(words) {
+        Some((name, password)) => Command::Register { name, password },
+        None => Command::Nothing,
+    }
+}
+
+fn parse_login(words: &[String]) -> Command {
+    match check_credentials(words) {
+        Some((name, password)) => Command::Login { name, password },
+        None => Command::Nothing,
+    }
+}
+
+fn check_target(words: &[String]) -> Option<String> {
+    if words.len() < 2 {
+        println!("(Console) And who's the lucky one?");
+        return None;
+    }
+
+    Some(words[1].clone())
+}
+
+fn parse_promote(words: &[String]) -> Command {
+    match check_target(words) {
+        Some(name) => Command::Promote { name },
+        None => Command::Nothing,
+    }
+}
+
+fn parse_kick(words: &[String]) -> Command {
+    match check_target(words) {
+        Some(name) => Command::Kick { name },
+        None => Command::Nothing,
+    }
+}
+
+fn parse_mute(words: &[String]) -> Command {
+    match check_target(words) {
+        Some(name) => Command::Mute { name },
+        None => Command::Nothing,
+    }
+}
+
+fn parse_unmute(words: &[String]) -> Command {
+    match check_target(words) {
+        Some(name) => Command::Unmute { name },
+        None => Command::Nothing,
+    }
+}
+
+// Understands 90, 90s, 15m, 2h and 7d
+fn parse_duration(word: &str) -> Option<u64> {
+    let (number, multiplier) = match word.chars().last()? {
+        's' => (&word[..word.len() - 1], 1),
+        'm' => (&word[..word.len() - 1], 60),
+        'h' => (&word[..word.len() - 1], 60 * 60),
+        'd' => (&word[..word.len() - 1], 24 * 60 * 60),
+        _ => (word, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn parse_ban(words: &[String]) -> Command {
+    if words.len() < 3 {
+        println!("(Console) Ban what? Try '/ban name|session|ip <target> [duration]'");
+        return Command::Nothing;
+    }
+
+    let target = match words[1].as_str() {
+        "name" => BanTarget::Name(words[2].clone()),
+        "session" => BanTarget::Session(words[2].clone()),
+        "ip" => BanTarget::Ip(words[2].clone()),
+        _ => {
+            println!("(Console) I can only ban a name, a session or an ip");
+            return Command::Nothing;
+        }
+    };
+
+    let duration_seconds = if words.len() >= 4 {
+        match parse_duration(&words[3]) {
+            Some(it) => Some(it),
+            None => {
+                println!("(Console) How long is '{}'? Try something like 30m or 2d", &words[3]);
+                return Command::Nothing;
+            }
+        }
+    } else {
+        None
+    };
+
+    Command::Ban { target, duration_seconds }
+}
+
 fn parse_connect(words: &[String]) -> Command {
-    if words.len() >= 3 {
+    let is_tls = words.iter().any(|it| it == "--tls");
+
+    let words: Vec<&String> = words.iter()
+        .filter(|it| *it != "--tls")
+        .collect();
+
+    let (prefix, default_port) = if is_tls {
+        ("tls:", DEFAULT_TLS_PORT)
+    } else {
+        ("", DEFAULT_PORT)
+    };
+
+    if words.len() >= 2 && words[1].starts_with("unix:") {
         Command::Connect {
-            address: format!("{}:{}", words[1], words[2])
+            address: words[1].clone(),
+        }
+    } else if words.len() >= 3 {
+        Command::Connect {
+            address: format!("{}{}:{}", prefix, words[1], words[2])
         }
     } else if words.len() >= 2 {
         Command::Connect {
-            address: format!("{}:{}", words[1], DEFAULT_PORT),
+            address: format!("{}{}:{}", prefix, words[1], default_port),
         }
     } else {
         Command::Connect {
-            address: format!("localhost:{}", DEFAULT_PORT),
+            address: format!("{}localhost:{}", prefix, default_port),
         }
     }
 }
@@ -69,11 +186,6 @@ fn check_upload(path: String, name: String) -> Command {
         return Command::Nothing;
     }
 
-    if name.len() > MAXIMUM_FILE_NAME_SIZE {
-        println!("(Console) No, this file name is way too long");
-        return Command::Nothing;
-    }
-
     Command::UploadFile { path, name }
 }
 
@@ -100,11 +212,6 @@ fn check_download(path: String, name: String) -> Command {
         return Command::Nothing;
     }
 
-    if name.len() > MAXIMUM_FILE_NAME_SIZE {
-        println!("(Console) No, this file name is way too long");
-        return Command::Nothing;
-    }
-
     Command::DownloadFile { path, name }
 }
 
@@ -125,29 +232,16 @@ fn parse_download(words: &[String]) -> Command {
     }
 }
 
-fn parse_words<'a>(input: &mut Peekable<CharsReader<'a>>) -> Vec<String> {
-    let mut words = vec!["".to_owned()];
+fn parse_command<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
+    let line = read_line(input).unwrap_or_default();
 
-    while let Some(it) = input.next() {
-        if it == '\r' {
-            // ignore
-        } else if it == '\n' {
-            break
-        } else if is_blank(it) {
-            if words[words.len() - 1].len() != 0 {
-                words.push("".to_owned());
-            }
-        } else {
-            let last_index = words.len() - 1;
-            words[last_index].push(it);
+    let words = match tokenize(&line) {
+        Ok(it) => it,
+        Err(error) => {
+            println!("(Console) {}", error);
+            return Command::Nothing;
         }
-    }
-
-    words
-}
-
-fn parse_command<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
-    let words = parse_words(input);
+    };
 
     if words[0] == "/" {
         println!("(Console) Nooooooo, you can't just put a blank symbol after the '/'!!!!!!");
@@ -156,22 +250,44 @@ fn parse_command<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
         Command::End
     } else if words[0] == "/rename" || words[0] == "/r" {
         parse_rename(&words)
+    } else if words[0] == "/register" {
+        parse_register(&words)
+    } else if words[0] == "/login" {
+        parse_login(&words)
+    } else if words[0] == "/users" {
+        Command::ListUsers
+    } else if words[0] == "/op" {
+        parse_promote(&words)
+    } else if words[0] == "/kick" {
+        parse_kick(&words)
+    } else if words[0] == "/ban" {
+        parse_ban(&words)
+    } else if words[0] == "/mute" {
+        parse_mute(&words)
+    } else if words[0] == "/unmute" {
+        parse_unmute(&words)
     } else if words[0] == "/connect" || words[0] == "/c" {
         parse_connect(&words)
     } else if words[0] == "/upload" || words[0] == "/u" {
         parse_upload(&words)
     } else if words[0] == "/download" || words[0] == "/d" {
         parse_download(&words)
+    } else if words[0] == "/multi" || words[0] == "/m" {
+        parse_multi(input)
     } else {
         println!("(Console) Well, yea, you issued a command, but I missed it, sorry...");
         Command::Nothing
     }
 }
 
-fn parse_text<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
+// None once there's
+// nothing left at all
+fn read_line<'a>(input: &mut Peekable<CharsReader<'a>>) -> Option<String> {
+    input.peek()?;
+
     let mut line = String::new();
 
-    while let Some(it) = input.next() {
+    for it in input.by_ref() {
         if it == '\r' {
             // ignore
         } else if it != '\n' {
@@ -181,22 +297,62 @@ fn parse_text<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
         }
     }
 
-    if line.is_empty() {
-        Command::Nothing
-    } else if line.len() > MAXIMUM_TEXT_SIZE {
-        println!("(Console) No way, sorry, this is way too long");
+    Some(line)
+}
+
+fn to_text(text: String) -> Command {
+    if text.is_empty() {
         Command::Nothing
     } else {
         Command::Text {
-            text: line,
+            text: text,
+        }
+    }
+}
+
+// A trailing backslash means the
+// text goes on the next line
+fn parse_text<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
+    let mut text = String::new();
+
+    while let Some(line) = read_line(input) {
+        match line.strip_suffix('\\') {
+            Some(it) => {
+                text.push_str(it);
+                text.push('\n');
+            }
+            None => {
+                text.push_str(&line);
+                break
+            }
         }
     }
+
+    to_text(text)
+}
+
+// Everything up to a line with just
+// /end goes as it is, e.g. some code
+fn parse_multi<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
+    println!("(Console) Go on, and put /end on a line of its own once you're done");
+
+    let mut lines = vec![];
+
+    while let Some(line) = read_line(input) {
+        if line.trim() == "/end" {
+            break
+        }
+
+        lines.push(line);
+    }
+
+    to_text(lines.join("\n"))
 }
 
 pub fn parse<'a>(input: &mut Peekable<CharsReader<'a>>) -> Command {
     if input.peek() == Some(&'/') {
         parse_command(input)
-    } else if let Some(_) = input.peek() {
+    } else if input.peek().is_some() {
         parse_text(input)
     } else {
         Command::End
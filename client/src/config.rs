@@ -0,0 +1,53 @@
+use std::path::{Path};
+use std::fs::{File};
+use std::time::{Duration};
+
+use shared::{Result};
+use shared::connection::heartbeat::{Heartbeat};
+use shared::logging::{LoggingConfig};
+use shared::communication::{WireFormat};
+
+use serde::{Deserialize};
+
+pub const CONFIG_FILE: &str = "client.json";
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ClientConfig {
+    pub heartbeat_interval_seconds: u64,
+    pub heartbeat_timeout_seconds: u64,
+    // Where the TLS server
+    // fingerprints are kept
+    pub known_hosts_file: String,
+    pub wire_format: WireFormat,
+    pub logging: LoggingConfig,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            heartbeat_interval_seconds: 10,
+            heartbeat_timeout_seconds: 30,
+            known_hosts_file: "known_hosts.json".to_owned(),
+            wire_format: WireFormat::Arson,
+            logging: LoggingConfig::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn load(path: &str) -> Result<ClientConfig> {
+        if !Path::new(path).exists() {
+            return Ok(ClientConfig::default())
+        }
+
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        Heartbeat::new(
+            Duration::from_secs(self.heartbeat_interval_seconds),
+            Duration::from_secs(self.heartbeat_timeout_seconds),
+        )
+    }
+}
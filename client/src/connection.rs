@@ -1,26 +1,30 @@
-use std::net::{TcpStream, SocketAddr};
 use std::collections::{HashMap};
-use std::fs::{File};
+use std::io::{Write};
 
 use shared::{Result};
 use shared::shared::{Shared, IntoShared};
+use shared::transport::{BoxedTransport, split};
 
 use shared::communication::{
     ReadMessage,
     WriteMessage,
+    WireFormat,
 };
 
 use shared::communication::arson::{ArsonScanner, ArsonWriter};
+use shared::communication::json::{JsonScanner, JsonWriter};
+use shared::communication::binary::{BinaryScanner, BinaryWriter};
 
 use shared::connection::messages::{
     CommonMessage,
     ClientMessage,
     ServerMessage,
-    MAXIMUM_MESSAGE_SIZE,
 };
 
 use shared::connection::{Context, Connection, WithConnection};
+use shared::connection::session::{Session, Codec, BoxedCodec};
 use shared::connection::sharers::{FileSharer, FileSharers};
+use shared::connection::limits::{Limits, SharedLimits, DEFAULT_MESSAGE_SIZE};
 
 pub struct ClientContext {
     common: Context,
@@ -28,15 +32,17 @@ pub struct ClientContext {
 
 impl ClientContext {
     pub fn new(
-        stream: Shared<TcpStream>,
+        transport: Shared<BoxedTransport>,
         reading_sharers: FileSharers,
         writing_sharers: Shared<Vec<FileSharer>>,
+        limits: SharedLimits,
     ) -> ClientContext {
         ClientContext {
             common: Context::new(
-                stream,
+                transport,
                 reading_sharers,
-                writing_sharers
+                writing_sharers,
+                limits
             ),
         }
     }
@@ -54,154 +60,123 @@ impl WithConnection for ClientContext {
 
 pub trait ClientConnection: Connection {}
 
-impl<'a> ClientConnection for ClientContext {}
-
-pub trait WithClientConnection: WithConnection + ReadMessage<ServerMessage> + WriteMessage<ClientMessage> {
-    fn client_connection(&self) -> &dyn ClientConnection;
-    fn client_connection_mut(&mut self) -> &mut dyn ClientConnection;
-}
-
-impl<W: WithClientConnection> ClientConnection for W {}
+impl ClientConnection for ClientContext {}
 
 impl<T: ClientConnection> ClientConnection for Shared<T> {}
 
-#[derive(Clone)]
-pub struct ArsonClientSession {
-    context: Shared<ClientContext>,
-    reader: Shared<ArsonScanner<Shared<TcpStream>>>,
-    writer: Shared<ArsonWriter<Shared<TcpStream>>>,
-}
+pub type ServerCodec = BoxedCodec<ServerMessage, ClientMessage>;
+pub type ServerReader = <ServerCodec as Codec>::Reader;
+pub type ServerWriter = <ServerCodec as Codec>::Writer;
 
-impl ArsonClientSession {
-    pub fn new(
-        context: Shared<ClientContext>,
-        reader: Shared<ArsonScanner<Shared<TcpStream>>>,
-        writer: Shared<ArsonWriter<Shared<TcpStream>>>,
-    ) -> ArsonClientSession {
-        ArsonClientSession {
-            context: context,
-            reader: reader,
-            writer: writer,
-        }
-    }
-}
+// Whatever the format is
+pub type AnyClientSession = Session<ClientContext, ServerCodec>;
 
-impl ReadMessage<ServerMessage> for ArsonClientSession {
-    fn read_message(&mut self) -> Result<ServerMessage> {
-        self.reader.read_message()
-    }
-}
+impl<K: Codec> ClientConnection for Session<ClientContext, K> {}
 
-impl WriteMessage<ClientMessage> for ArsonClientSession {
-    fn write_message(&mut self, message: &ClientMessage) -> Result<()> {
-        self.writer.write_message(message)
-    }
-}
-
-impl WriteMessage<CommonMessage> for ArsonClientSession {
-    fn write_message(&mut self, message: &CommonMessage) -> Result<()> {
-        let wrapped = ClientMessage::Common {
-            common: message.clone()
-        };
-
-        self.writer.write_message(&wrapped)
-    }
-}
-
-impl Connection for ArsonClientSession {
-    fn remote_address(&self) -> Result<SocketAddr> {
-        self.context.remote_address()
-    }
-
-    fn free_id(&mut self) -> Result<usize> {
-        self.context.free_id()
-    }
-
-    fn prepare_sharer(
-        &mut self,
-        path: &str,
-        file: File,
-        name: &str,
-    ) -> Result<()> {
-        self.context.prepare_sharer(path, file, name)
-    }
-
-    fn promote_sharer(
-        &mut self,
-        name: &str,
-        size: usize,
-        id: usize,
-    ) -> Result<()> {
-        self.context.promote_sharer(name, size, id)
-    }
+pub trait ClientSession: ClientConnection
+    + ReadMessage<ServerMessage>
+    + WriteMessage<ClientMessage>
+    + WriteMessage<CommonMessage>
+    + Clone + Send + Sync {}
 
-    fn accept_chunk(
-        &mut self,
-        data: &[u8],
-        id: usize,
-    ) -> Result<bool> {
-        self.context.accept_chunk(data, id)
-    }
+impl<K> ClientSession for Session<ClientContext, K>
+where
+    K: Codec<Incoming = ServerMessage, Outgoing = ClientMessage>,
+{}
 
-    fn remove_unpromoted_sharer(&mut self, name: &str) -> Result<Option<FileSharer>> {
-        self.context.remove_unpromoted_sharer(name)
-    }
+impl<T: ClientSession> ClientSession for Shared<T> {}
 
-    fn remove_sharer(&mut self, id: usize) -> Result<Option<FileSharer>> {
-        self.context.remove_sharer(id)
-    }
+// Refuses what the server would
+// disconnect us for, before any
+// of it reaches the socket
+pub struct CheckedWriter {
+    backend: ServerWriter,
+    limits: SharedLimits,
+}
 
-    fn enqueu_sending_sharer(&mut self, sharer: FileSharer) -> Result<()> {
-        self.context.enqueu_sending_sharer(sharer)
+impl CheckedWriter {
+    pub fn new(backend: ServerWriter, limits: SharedLimits) -> CheckedWriter {
+        CheckedWriter {
+            backend: backend,
+            limits: limits,
+        }
     }
+}
 
-    fn sending_sharers_queue(&self) -> Result<Shared<Vec<FileSharer>>> {
-        self.context.sending_sharers_queue()
+impl WriteMessage<ClientMessage> for CheckedWriter {
+    fn write_message(&mut self, message: &ClientMessage) -> Result<()> {
+        self.limits.get()?.check(message)?;
+        self.backend.write_message(message)
     }
 }
 
-impl ClientConnection for ArsonClientSession {}
-
-pub trait ClientSession: ClientConnection
-    + ReadMessage<ServerMessage>
-    + WriteMessage<ClientMessage>
-    + WriteMessage<CommonMessage>
-    + Clone + Send + Sync {}
-
-impl ClientSession for ArsonClientSession {}
-
-impl<T: ClientSession> ClientSession for Shared<T> {}
+fn build_codec(
+    reading_stream: Shared<BoxedTransport>,
+    writing_stream: Shared<BoxedTransport>,
+    format: WireFormat,
+    limits: &SharedLimits,
+) -> (ServerReader, ServerWriter) {
+    let capacity = limits.capacity();
+
+    let (reader, writer): (ServerReader, ServerWriter) = match format {
+        WireFormat::Arson => (
+            Box::new(ArsonScanner::new(reading_stream, capacity.clone())),
+            Box::new(ArsonWriter::new(writing_stream, capacity)),
+        ),
+        WireFormat::Json => (
+            Box::new(JsonScanner::new(reading_stream, capacity.clone())),
+            Box::new(JsonWriter::new(writing_stream, capacity)),
+        ),
+        WireFormat::Binary => (
+            Box::new(BinaryScanner::new(reading_stream, capacity.clone())),
+            Box::new(BinaryWriter::new(writing_stream, capacity)),
+        ),
+    };
+
+    (reader, Box::new(CheckedWriter::new(writer, limits.clone())))
+}
 
 pub fn build_connection(
-    stream: TcpStream
-) -> Result<(ArsonClientSession, ArsonClientSession)> {
-    stream.set_nonblocking(true)?;
+    mut transport: BoxedTransport,
+    format: WireFormat,
+) -> Result<(AnyClientSession, AnyClientSession)> {
+    // Tells the server what to
+    // expect from now on
+    transport.write_all(format.preamble())?;
+    transport.flush()?;
+
+    transport.set_nonblocking(true)?;
     // stream.set_nodelay(true)?;
 
-    let reading_stream = stream.try_clone()?.to_shared();
-    let writing_stream = stream.to_shared();
+    let (reading_stream, writing_stream) = split(transport)?;
+
+    // Until the server tells otherwise
+    let limits = SharedLimits::new(Limits::new(format, DEFAULT_MESSAGE_SIZE));
 
-    let reader = ArsonScanner::new(reading_stream.clone(), MAXIMUM_MESSAGE_SIZE).to_shared();
-    let writer = ArsonWriter::new(writing_stream.clone()).to_shared();
+    let (reader, writer) = build_codec(reading_stream.clone(), writing_stream.clone(), format, &limits);
+    let reader = reader.to_shared();
+    let writer = writer.to_shared();
 
     let reading_sharers = HashMap::new().to_shared();
     let writing_sharers = vec![].to_shared();
 
-    let reader_context = ArsonClientSession::new(
+    let reader_context = AnyClientSession::new(
         ClientContext::new(
             reading_stream,
             reading_sharers.clone(),
             writing_sharers.clone(),
+            limits.clone(),
         ).to_shared(),
         reader.clone(),
         writer.clone(),
     );
 
-    let writer_context = ArsonClientSession::new(
+    let writer_context = AnyClientSession::new(
         ClientContext::new(
             writing_stream,
             reading_sharers.clone(),
             writing_sharers.clone(),
+            limits,
         ).to_shared(),
         reader.clone(),
         writer.clone(),
@@ -0,0 +1,70 @@
+use std::collections::{HashMap};
+use std::path::{Path};
+use std::fs::{File};
+
+use shared::{Result};
+
+// The certificate fingerprints of the
+// TLS servers seen before, by `host:port`
+pub struct KnownHosts {
+    path: String,
+    fingerprints: HashMap<String, String>,
+}
+
+impl KnownHosts {
+    pub fn load(path: &str) -> Result<KnownHosts> {
+        let fingerprints = if Path::new(path).exists() {
+            serde_json::from_reader(File::open(path)?)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(KnownHosts {
+            path: path.to_owned(),
+            fingerprints: fingerprints,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = File::create(&self.path)?;
+        serde_json::to_writer_pretty(file, &self.fingerprints)?;
+        Ok(())
+    }
+
+    pub fn fingerprint_of(&self, host: &str) -> Option<&String> {
+        self.fingerprints.get(host)
+    }
+
+    pub fn remember(&mut self, host: &str, fingerprint: &str) -> Result<()> {
+        self.fingerprints.insert(host.to_owned(), fingerprint.to_owned());
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KnownHosts};
+
+    struct Guard(String);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn fingerprints_are_remembered_across_runs() {
+        let path = std::env::temp_dir().join(format!("known-hosts-{}.json", std::process::id()));
+        let guard = Guard(path.to_string_lossy().into_owned());
+
+        let mut hosts = KnownHosts::load(&guard.0).unwrap();
+        assert_eq!(hosts.fingerprint_of("localhost:7000"), None);
+
+        hosts.remember("localhost:7000", "ab12").unwrap();
+
+        let hosts = KnownHosts::load(&guard.0).unwrap();
+        assert_eq!(hosts.fingerprint_of("localhost:7000").map(|it| it.as_str()), Some("ab12"));
+        assert_eq!(hosts.fingerprint_of("localhost:7001"), None);
+    }
+}
@@ -1,20 +1,27 @@
 mod chars_reader;
 mod connection;
 mod commands;
+mod reconnection;
+mod config;
+mod known_hosts;
+mod tokenizer;
 
 use std::fs::{File};
+use std::path::{Path};
 use std::io::{BufRead};
-use std::net::{TcpStream};
+use std::net::{TcpStream, ToSocketAddrs};
 use std::time::{Duration};
-use std::sync::mpsc::{channel, Sender};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
 
 use connection::{
-    ArsonClientSession,
+    AnyClientSession,
     ClientSession,
     build_connection,
 };
 
-use shared::{Result, with_error_report, is_would_block_error};
+use shared::{Result, ErrorKind, with_error_report, is_would_block_error, is_size_exceeded_error};
+use shared::transport::{BoxedTransport, Transport};
+use shared::transport::tls::{TlsTransport};
 
 use shared::connection::messages::{
     CommonMessage,
@@ -32,8 +39,15 @@ use shared::connection::helpers::{
     process_sending_sharers,
 };
 
+use shared::connection::heartbeat::{Heartbeat};
+use shared::connection::fragments::{Assembler, split_text, next_id};
+use shared::connection::limits::{Limits, field_size};
+
 use chars_reader::{IntoCharsReader};
 use commands::{Command, CommandProcessing};
+use reconnection::{Reconnection};
+use config::{ClientConfig, CONFIG_FILE};
+use known_hosts::{KnownHosts};
 
 fn handle_server_chunk(
     connection: &mut (impl ClientSession + 'static),
@@ -62,11 +76,28 @@ fn handle_server_common_message(
 ) -> Result<MessageProcessing> {
     match message {
         CommonMessage::Chunk { data, id } => {
-            handle_server_chunk(connection, &data, id.clone())
+            handle_server_chunk(connection, data, *id)
+        }
+        CommonMessage::Ping => {
+            connection.write_message(&CommonMessage::Pong)?;
+            Ok(MessageProcessing::Proceed)
+        }
+        CommonMessage::Pong => {
+            Ok(MessageProcessing::Proceed)
+        }
+        CommonMessage::Unknown { tag } => {
+            handle_server_unknown(tag)
         }
     }
 }
 
+// A newer server, most likely, so
+// there's no reason to disconnect
+fn handle_server_unknown(tag: &str) -> Result<MessageProcessing> {
+    log::warn!("Unknown message {}, skipped", tag);
+    Ok(MessageProcessing::Proceed)
+}
+
 fn handle_server_agree_file_upload(
     connection: &mut (impl ClientSession + 'static),
     id: usize,
@@ -92,7 +123,7 @@ fn handle_server_decline_file_upload(
         return Ok(MessageProcessing::Proceed)
     };
 
-    println!("(Server) Nah, wait with your #{}. {}", &sharer.name, &reason.clone());
+    println!("(Server) Nah, wait with your #{}. {}", &sharer.name, &reason);
     Ok(MessageProcessing::Proceed)
 }
 
@@ -102,7 +133,7 @@ fn handle_server_agree_file_download(
     size: usize,
     id: usize,
 ) -> Result<MessageProcessing> {
-    connection.promote_sharer(&name, size, id)?;
+    connection.promote_sharer(name, size, id)?;
 
     let response = ClientMessage::AgreeFileDownload {
         id: id,
@@ -117,7 +148,7 @@ fn handle_server_decline_file_download(
     name: &str,
     reason: &str,
 ) -> Result<MessageProcessing> {
-    connection.remove_unpromoted_sharer(&name)?;
+    connection.remove_unpromoted_sharer(name)?;
     println!("(Server) Nah, I won't give you {}. {}", &name, &reason);
     Ok(MessageProcessing::Proceed)
 }
@@ -128,19 +159,22 @@ fn handle_server_message(
 ) -> Result<MessageProcessing> {
     match message {
         ServerMessage::Common { common } => {
-            handle_server_common_message(connection, &common)
+            handle_server_common_message(connection, common)
         }
         ServerMessage::AgreeFileUpload { id } => {
-            handle_server_agree_file_upload(connection, id.clone())
+            handle_server_agree_file_upload(connection, *id)
         }
         ServerMessage::DeclineFileUpload { id, reason } => {
-            handle_server_decline_file_upload(connection, id.clone(), &reason)
+            handle_server_decline_file_upload(connection, *id, reason)
         }
         ServerMessage::AgreeFileDownload { name, size, id } => {
-            handle_server_agree_file_download(connection, &name, size.clone(), id.clone())
+            handle_server_agree_file_download(connection, name, *size, *id)
         }
         ServerMessage::DeclineFileDownload { name, reason } => {
-            handle_server_decline_file_download(connection, &name, &reason)
+            handle_server_decline_file_download(connection, name, reason)
+        }
+        ServerMessage::Unknown { tag, .. } => {
+            handle_server_unknown(tag)
         }
         _ => {
             println!("{}", message);
@@ -149,22 +183,91 @@ fn handle_server_message(
     }
 }
 
+fn check_heartbeat(
+    connection: &mut (impl ClientSession + 'static),
+    heartbeat: &mut Heartbeat,
+) -> Result<MessageProcessing> {
+    if heartbeat.is_dead() {
+        println!("(Console) The server has stopped answering");
+        return Ok(MessageProcessing::Stop)
+    }
+
+    if heartbeat.should_ping() {
+        connection.write_message(&CommonMessage::Ping)?;
+    }
+
+    Ok(MessageProcessing::ProceedButWaiting)
+}
+
+// Shown once all the pieces are here,
+// as if it were a single message
+fn handle_server_text_fragment(
+    texts: &mut Assembler,
+    message: ServerMessage,
+) -> Result<MessageProcessing> {
+    let (text, name, time, id, index, last) = match message {
+        ServerMessage::TextFragment { text, name, time, id, index, last } => (text, name, time, id, index, last),
+        _ => return Ok(MessageProcessing::Proceed),
+    };
+
+    match texts.push(id, index, &text, last) {
+        Ok(Some(whole)) => {
+            println!("{}", ServerMessage::Text { text: whole, name, time });
+        }
+        Ok(None) => {}
+        Err(error) => {
+            log::warn!("Dropped a long text > {}", explain_common_error(&error));
+        }
+    }
+
+    Ok(MessageProcessing::Proceed)
+}
+
 fn read_and_handle_server_message(
-    connection: &mut (impl ClientSession + 'static)
+    connection: &mut (impl ClientSession + 'static),
+    resume_token: &mut Option<String>,
+    heartbeat: &mut Heartbeat,
+    is_unwelcome: &mut bool,
+    texts: &mut Assembler,
 ) -> Result<MessageProcessing> {
     let message = match connection.read_message() {
         Ok(it) => it,
         Err(error) => {
             if is_would_block_error(&error) {
-                return Ok(MessageProcessing::ProceedButWaiting)
+                return check_heartbeat(connection, heartbeat)
             }
 
-            let explaination = explain_common_error(&error);
-            println!("(Server) Error > {}", &explaination);
+            log::error!("Server > {}", explain_common_error(&error));
             return Ok(MessageProcessing::Stop)
         }
     };
 
+    heartbeat.heard();
+
+    if let ServerMessage::ResumeToken { token } = message {
+        *resume_token = Some(token);
+        return Ok(MessageProcessing::Proceed)
+    }
+
+    if let ServerMessage::TextFragment { .. } = message {
+        return handle_server_text_fragment(texts, message)
+    }
+
+    // The others' long texts are held
+    // to the same limits as ours
+    if let ServerMessage::Limits { limits } = message {
+        *texts = Assembler::new(limits.long_text_size, limits.text_fragments);
+        connection.set_limits(limits)?;
+        return Ok(MessageProcessing::Proceed)
+    }
+
+    if let ServerMessage::Goodbye { .. } = message {
+        println!("{}", message);
+        *resume_token = None;
+        *is_unwelcome = true;
+        return Ok(MessageProcessing::Stop)
+    }
+
     handle_server_message(connection, &message)
 }
 
@@ -172,11 +275,39 @@ fn perform_text(
     connection: &mut impl ClientSession,
     text: &str,
 ) -> Result<CommandProcessing> {
-    let message = ClientMessage::Text {
-        text: text.to_owned(),
-    };
+    let limits = connection.limits()?;
+
+    if field_size(text) <= limits.text_size {
+        let message = ClientMessage::Text {
+            text: text.to_owned(),
+        };
+
+        connection.write_message(&message)?;
+        return Ok(CommandProcessing::Proceed)
+    }
+
+    // Too long for a single message, so it
+    // goes in pieces the server glues back
+    let pieces = split_text(text, limits.fragment_size);
+
+    if text.len() > limits.long_text_size || pieces.len() > limits.text_fragments {
+        return Err(ErrorKind::MessageSizeExceeded.into())
+    }
+
+    let count = pieces.len();
+    let id = next_id();
+
+    for (index, piece) in pieces.into_iter().enumerate() {
+        let message = ClientMessage::TextFragment {
+            text: piece,
+            id: id,
+            index: index,
+            last: index + 1 == count,
+        };
+
+        connection.write_message(&message)?;
+    }
 
-    connection.write_message(&message)?;
     Ok(CommandProcessing::Proceed)
 }
 
@@ -192,6 +323,28 @@ fn perform_rename(
     Ok(CommandProcessing::Proceed)
 }
 
+fn perform_moderation(
+    connection: &mut impl ClientSession,
+    message: ClientMessage,
+) -> Result<CommandProcessing> {
+    connection.write_message(&message)?;
+    Ok(CommandProcessing::Proceed)
+}
+
+fn perform_register(
+    connection: &mut impl ClientSession,
+    name: &str,
+    password: &str,
+) -> Result<CommandProcessing> {
+    let message = ClientMessage::Register {
+        name: name.to_owned(),
+        password: password.to_owned(),
+    };
+
+    connection.write_message(&message)?;
+    Ok(CommandProcessing::Proceed)
+}
+
 fn perform_upload_file(
     connection: &mut impl ClientSession,
     name: &str,
@@ -208,10 +361,13 @@ fn perform_upload_file(
         id: id,
     };
 
+    // The answer is read on this same thread,
+    // so the sharer is ready in time, and no
+    // sharer is left behind if it can't be sent
+    connection.write_message(&request)?;
+
     connection.prepare_sharer(path, file, name)?;
     connection.promote_sharer(name, size, id)?;
-
-    connection.write_message(&request)?;
     Ok(CommandProcessing::Proceed)
 }
 
@@ -220,13 +376,26 @@ fn perform_download_file(
     name: &str,
     path: &str,
 ) -> Result<CommandProcessing> {
-    connection.prepare_sharer(path, File::create(path)?, name)?;
-
     let inner = ClientMessage::RequestFileDownload {
         name: name.to_owned(),
     };
 
-    connection.write_message(&inner)?;
+    // The server isn't bothered if the file
+    // can't be written here anyway, and no
+    // empty file is left behind if the
+    // request can't be sent
+    let existed = Path::new(path).exists();
+    let file = File::create(path)?;
+
+    if let Err(error) = connection.write_message(&inner) {
+        if !existed {
+            let _ = std::fs::remove_file(path);
+        }
+
+        return Err(error)
+    }
+
+    connection.prepare_sharer(path, file, name)?;
     Ok(CommandProcessing::Proceed)
 }
 
@@ -236,16 +405,43 @@ fn match_user_command_with_connection(
 ) -> Result<CommandProcessing> {
     match command {
         Command::Text { text } => {
-            perform_text(connection, &text)
+            perform_text(connection, text)
         }
         Command::Rename { new_name } => {
-            perform_rename(connection, &new_name)
+            perform_rename(connection, new_name)
+        }
+        Command::Register { name, password } => {
+            perform_register(connection, name, password)
         }
         Command::UploadFile { name, path } => {
-            perform_upload_file(connection, &name, &path)
+            perform_upload_file(connection, name, path)
         }
         Command::DownloadFile { name, path } => {
-            perform_download_file(connection, &name, &path)
+            perform_download_file(connection, name, path)
+        }
+        Command::ListUsers => {
+            connection.write_message(&ClientMessage::ListUsers)?;
+            Ok(CommandProcessing::Proceed)
+        }
+        Command::Promote { name } => {
+            perform_moderation(connection, ClientMessage::Promote { name: name.clone() })
+        }
+        Command::Kick { name } => {
+            perform_moderation(connection, ClientMessage::Kick { name: name.clone() })
+        }
+        Command::Ban { target, duration_seconds } => {
+            let message = ClientMessage::Ban {
+                target: target.clone(),
+                duration_seconds: *duration_seconds,
+            };
+
+            perform_moderation(connection, message)
+        }
+        Command::Mute { name } => {
+            perform_moderation(connection, ClientMessage::Mute { name: name.clone() })
+        }
+        Command::Unmute { name } => {
+            perform_moderation(connection, ClientMessage::Unmute { name: name.clone() })
         }
         _ => {
             Ok(CommandProcessing::Proceed)
@@ -253,26 +449,170 @@ fn match_user_command_with_connection(
     }
 }
 
+#[derive(Clone)]
+struct Credentials {
+    name: String,
+    password: String,
+}
+
+struct ClientState {
+    config: ClientConfig,
+    heartbeat: Heartbeat,
+    connection: Option<AnyClientSession>,
+    credentials: Option<Credentials>,
+    resume_token: Option<String>,
+    address: Option<String>,
+    reconnection: Option<Reconnection>,
+    // The attempt in progress, it runs
+    // aside so that the user can still
+    // type in the meantime
+    reconnecting: Option<Receiver<Result<AnyClientSession>>>,
+    // The server has said goodbye,
+    // so there's no point in reconnecting
+    is_unwelcome: bool,
+    // The long texts of the others
+    texts: Assembler,
+}
+
+fn perform_handshake(
+    connection: &mut impl ClientSession,
+    credentials: &Option<Credentials>,
+    resume_token: &Option<String>,
+) -> Result<()> {
+    let message = if let Some(token) = resume_token {
+        ClientMessage::Resume {
+            token: token.clone(),
+        }
+    } else if let Some(it) = credentials {
+        ClientMessage::Authenticate {
+            name: it.name.clone(),
+            password: it.password.clone(),
+        }
+    } else {
+        ClientMessage::Join
+    };
+
+    connection.write_message(&message)
+}
+
+fn connect_tcp(address: &str) -> Result<TcpStream> {
+    let the_address = match address.to_socket_addrs()?.next() {
+        Some(it) => it,
+        None => return Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable).into())
+    };
+
+    let stream = TcpStream::connect_timeout(
+        &the_address,
+        Duration::from_millis(CONNECTION_TIMEOUT_MILLIS)
+    )?;
+
+    Ok(stream)
+}
+
+// The certificate is trusted the first time,
+// and must stay the same afterwards
+fn connect_tls(address: &str, config: &ClientConfig) -> Result<TlsTransport> {
+    let stream = connect_tcp(address)?;
+    let host = address.rsplit_once(':').map(|(it, _)| it).unwrap_or(address);
+
+    let mut known_hosts = KnownHosts::load(&config.known_hosts_file)?;
+    let expected = known_hosts.fingerprint_of(address).cloned();
+
+    // The server may never answer
+    let timeout = Duration::from_millis(CONNECTION_TIMEOUT_MILLIS);
+    stream.set_read_timeout(Some(timeout))?;
+
+    let (transport, fingerprint) = TlsTransport::connect(stream, host, expected.clone())?;
+    transport.set_read_timeout(None)?;
+
+    if expected.is_none() {
+        println!("(Console) First time seeing {}, trusting its certificate {}", address, &fingerprint);
+        known_hosts.remember(address, &fingerprint)?;
+    }
+
+    Ok(transport)
+}
+
+// Either `host:port`, `tls:host:port`
+// or `unix:<path>`
+fn connect(address: &str, config: &ClientConfig) -> Result<BoxedTransport> {
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix("unix:") {
+        let transport = shared::transport::unix::UnixTransport::connect(path)?;
+        return Ok(Box::new(transport))
+    }
+
+    if let Some(rest) = address.strip_prefix("tls:") {
+        return Ok(Box::new(connect_tls(rest, config)?))
+    }
+
+    Ok(Box::new(connect_tcp(address)?))
+}
+
+fn establish_connection(
+    address: &str,
+    config: &ClientConfig,
+    credentials: &Option<Credentials>,
+    resume_token: &Option<String>,
+) -> Result<AnyClientSession> {
+    let (
+        _,
+        mut writing_connection
+    ) = build_connection(connect(address, config)?, config.wire_format)?;
+
+    perform_handshake(&mut writing_connection, credentials, resume_token)?;
+    Ok(writing_connection)
+}
+
+fn perform_login(
+    state: &mut ClientState,
+    name: &str,
+    password: &str,
+) -> Result<CommandProcessing> {
+    let the_credentials = Credentials {
+        name: name.to_owned(),
+        password: password.to_owned(),
+    };
+
+    if let Some(it) = &mut state.connection {
+        let message = ClientMessage::Authenticate {
+            name: name.to_owned(),
+            password: password.to_owned(),
+        };
+
+        it.write_message(&message)?;
+    } else {
+        println!("(Console) Got it, I'll log you in as soon as we connect");
+    }
+
+    state.credentials = Some(the_credentials);
+    Ok(CommandProcessing::Proceed)
+}
+
 fn handle_user_command(
     command: &Command,
-    connection: &mut Option<impl ClientSession>,
+    state: &mut ClientState,
 ) -> Result<CommandProcessing> {
     match command {
         Command::End => {
             return Ok(CommandProcessing::Stop)
         }
         Command::Connect { address } => {
-            let (
-                _,
-                writing_connection
-            ) = build_connection(
-                TcpStream::connect(address)?
-            )?;
-
-            return Ok(CommandProcessing::Connect(writing_connection))
+            // A brand new session
+            state.resume_token = None;
+            state.reconnection = None;
+            state.reconnecting = None;
+            state.is_unwelcome = false;
+            state.address = Some(address.clone());
+
+            let connection = establish_connection(address, &state.config, &state.credentials, &None)?;
+            return Ok(CommandProcessing::Connect(connection))
+        }
+        Command::Login { name, password } => {
+            return perform_login(state, name, password)
         }
         Command::Nothing => {}
-        other => match connection {
+        other => match &mut state.connection {
             Some(it) => {
                 match_user_command_with_connection(other, it)?;
             }
@@ -298,10 +638,91 @@ fn read_user_command(
     }
 }
 
+fn start_reconnecting(state: &mut ClientState) -> bool {
+    let reconnection = match &state.reconnection {
+        Some(it) if it.is_due() => it,
+        _ => return false
+    };
+
+    let address = reconnection.address.clone();
+    let config = state.config.clone();
+    let credentials = state.credentials.clone();
+    let resume_token = state.resume_token.clone();
+
+    let (send_result, read_result) = channel();
+    log::info!("Reconnecting to {}", &address);
+
+    std::thread::spawn(move || {
+        let result = establish_connection(&address, &config, &credentials, &resume_token);
+        // Nobody's waiting if the user
+        // has connected somewhere else
+        let _ = send_result.send(result);
+    });
+
+    state.reconnecting = Some(read_result);
+    true
+}
+
+fn try_reconnect(state: &mut ClientState) -> Result<bool> {
+    let result = match &state.reconnecting {
+        Some(it) => match it.try_recv() {
+            Ok(it) => it,
+            Err(TryRecvError::Empty) => return Ok(false),
+            Err(TryRecvError::Disconnected) => Err(ErrorKind::NothingToRead.into()),
+        }
+        None => return Ok(start_reconnecting(state)),
+    };
+
+    state.reconnecting = None;
+
+    let reconnection = match &mut state.reconnection {
+        Some(it) => it,
+        None => return Ok(true)
+    };
+
+    match result {
+        Ok(it) => {
+            println!("(Console) We're back");
+            state.heartbeat = state.config.heartbeat();
+            state.connection = Some(it);
+            state.reconnection = None;
+        }
+        Err(error) => match reconnection.postpone() {
+            Some(delay) => {
+                log::warn!("Couldn't reconnect > {} > Retrying in {:.1}s", error, delay.as_secs_f32());
+            }
+            None => {
+                println!("(Console) I give up, use /connect when the server is back");
+                state.reconnection = None;
+            }
+        }
+    }
+
+    Ok(true)
+}
+
 const WAITING_DELAY_MILLIS: u64 = 16;
+const CONNECTION_TIMEOUT_MILLIS: u64 = 5000;
 
 fn handle_connection() -> Result<()> {
-    let mut connection: Option<ArsonClientSession> = None;
+    let config = ClientConfig::load(CONFIG_FILE)?;
+    shared::logging::init(&config.logging)?;
+
+    // Until the server tells its own
+    let limits = Limits::default();
+
+    let mut state = ClientState {
+        heartbeat: config.heartbeat(),
+        config: config,
+        connection: None,
+        credentials: None,
+        resume_token: None,
+        address: None,
+        reconnection: None,
+        reconnecting: None,
+        is_unwelcome: false,
+        texts: Assembler::new(limits.long_text_size, limits.text_fragments),
+    };
 
     let (
         send_command,
@@ -317,25 +738,52 @@ fn handle_connection() -> Result<()> {
 
         if let Ok(command) = read_command.try_recv() {
             did_something = true;
-            let result = handle_user_command(&command, &mut connection)?;
+            let result = match handle_user_command(&command, &mut state) {
+                Ok(it) => it,
+                Err(error) if is_size_exceeded_error(&error) => {
+                    println!("(Console) No way, sorry, this is way too long");
+                    continue
+                }
+                Err(error) => {
+                    println!("(Console) Error > {}", error);
+                    continue
+                }
+            };
 
             if let CommandProcessing::Stop = &result {
                 break
             } else if let CommandProcessing::Connect(it) = result {
-                connection = Some(it);
+                state.heartbeat = state.config.heartbeat();
+                state.connection = Some(it);
             }
         }
 
-        if let Some(the_connection) = &mut connection {
-            let result = read_and_handle_server_message(the_connection)?;
+        if let Some(the_connection) = &mut state.connection {
+            let result = read_and_handle_server_message(
+                the_connection,
+                &mut state.resume_token,
+                &mut state.heartbeat,
+                &mut state.is_unwelcome,
+                &mut state.texts,
+            )?;
 
             if let MessageProcessing::Stop = &result {
-                connection = None;
-                break
+                if state.is_unwelcome {
+                    println!("(Console) The server doesn't want us back, use /connect to try again");
+                    state.connection = None;
+                    continue
+                }
+
+                println!("(Console) Lost the connection, trying to get it back");
+                state.connection = None;
+                state.reconnection = state.address.as_deref().map(Reconnection::new);
+                continue
             }
 
             did_something |= !matches!(&result, MessageProcessing::ProceedButWaiting);
             did_something |= process_sending_sharers(the_connection)?;
+        } else {
+            did_something |= try_reconnect(&mut state)?;
         }
 
         if !did_something {
@@ -343,7 +791,7 @@ fn handle_connection() -> Result<()> {
         }
     }
 
-    if let Some(it) = &mut connection {
+    if let Some(it) = &mut state.connection {
         it.write_message(&ClientMessage::Leave)?;
     }
 
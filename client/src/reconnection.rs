@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+
+const BASE_DELAY_MILLIS: u64 = 1000;
+const MAXIMUM_DELAY_MILLIS: u64 = 30000;
+const MAXIMUM_ATTEMPTS: u32 = 10;
+
+pub struct Reconnection {
+    pub address: String,
+    attempt: u32,
+    next_attempt: Instant,
+}
+
+impl Reconnection {
+    pub fn new(address: &str) -> Reconnection {
+        Reconnection {
+            address: address.to_owned(),
+            attempt: 0,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.next_attempt
+    }
+
+    // Returns the delay before the next attempt
+    // or None if it's time to give up
+    pub fn postpone(&mut self) -> Option<Duration> {
+        self.attempt += 1;
+
+        if self.attempt >= MAXIMUM_ATTEMPTS {
+            return None
+        }
+
+        // The first retry waits exactly
+        // the base delay, then it doubles
+        let factor = 1u64 << std::cmp::min(self.attempt - 1, 16);
+        let delay = std::cmp::min(BASE_DELAY_MILLIS * factor, MAXIMUM_DELAY_MILLIS);
+        let delay = Duration::from_millis(delay);
+
+        self.next_attempt = Instant::now() + delay;
+        Some(delay)
+    }
+}
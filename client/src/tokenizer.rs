@@ -0,0 +1,145 @@
+use std::fmt::{Display, Formatter};
+
+// Splits a command the way a shell would:
+// blanks separate the words, quotes keep
+// them together, and a backslash takes
+// the next symbol as it is
+#[derive(Debug, PartialEq)]
+pub enum TokenizeError {
+    UnterminatedQuote { quote: char },
+    DanglingEscape,
+}
+
+impl Display for TokenizeError {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenizeError::UnterminatedQuote { quote } => {
+                write!(formatter, "You've opened a {} quote, but never closed it", quote)
+            }
+            TokenizeError::DanglingEscape => {
+                write!(formatter, "There's nothing after the last backslash to escape")
+            }
+        }
+    }
+}
+
+fn is_blank(symbol: char) -> bool {
+    symbol == '\r' ||
+    symbol == '\n' ||
+    symbol == '\t' ||
+    symbol == ' '
+}
+
+pub fn tokenize(line: &str) -> Result<Vec<String>, TokenizeError> {
+    let mut words = vec![];
+    let mut word = String::new();
+    // So that "" is a word too,
+    // even though an empty one
+    let mut is_in_word = false;
+    let mut symbols = line.chars();
+
+    while let Some(it) = symbols.next() {
+        match it {
+            '\\' => {
+                word.push(symbols.next().ok_or(TokenizeError::DanglingEscape)?);
+                is_in_word = true;
+            }
+            // Everything up to the closing
+            // one is taken literally
+            '\'' => {
+                loop {
+                    match symbols.next() {
+                        Some('\'') => break,
+                        Some(other) => word.push(other),
+                        None => return Err(TokenizeError::UnterminatedQuote { quote: '\'' }),
+                    }
+                }
+
+                is_in_word = true;
+            }
+            // Only the quote itself and the backslash
+            // can be escaped here, so that the Windows
+            // paths don't need any doubling
+            '"' => {
+                loop {
+                    match symbols.next() {
+                        Some('"') => break,
+                        Some('\\') => match symbols.next() {
+                            Some(escaped @ ('"' | '\\')) => word.push(escaped),
+                            Some(other) => {
+                                word.push('\\');
+                                word.push(other);
+                            }
+                            None => return Err(TokenizeError::UnterminatedQuote { quote: '"' }),
+                        }
+                        Some(other) => word.push(other),
+                        None => return Err(TokenizeError::UnterminatedQuote { quote: '"' }),
+                    }
+                }
+
+                is_in_word = true;
+            }
+            blank if is_blank(blank) => {
+                if is_in_word {
+                    words.push(std::mem::take(&mut word));
+                    is_in_word = false;
+                }
+            }
+            other => {
+                word.push(other);
+                is_in_word = true;
+            }
+        }
+    }
+
+    if is_in_word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, TokenizeError};
+
+    fn words(line: &str) -> Vec<String> {
+        tokenize(line).unwrap()
+    }
+
+    #[test]
+    fn blanks_separate_the_words() {
+        assert_eq!(words("/upload  a.txt\tb.txt "), vec!["/upload", "a.txt", "b.txt"]);
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn quotes_keep_the_words_together() {
+        assert_eq!(words(r#"/upload "my report.pdf" 'the report.pdf'"#), vec!["/upload", "my report.pdf", "the report.pdf"]);
+        assert_eq!(words(r#"/rename "John "'Smith'"#), vec!["/rename", "John Smith"]);
+        assert_eq!(words(r#"a"b c"d"#), vec!["ab cd"]);
+    }
+
+    #[test]
+    fn empty_quotes_are_words() {
+        assert_eq!(words(r#"/login "" ''"#), vec!["/login", "", ""]);
+    }
+
+    #[test]
+    fn backslashes_escape() {
+        assert_eq!(words(r"my\ report.pdf \'a\' \\"), vec!["my report.pdf", "'a'", "\\"]);
+        // Only the quote and the backslash
+        // inside the double quotes
+        assert_eq!(words(r#""say \"hi\" \\ C:\Users""#), vec![r#"say "hi" \ C:\Users"#]);
+        // And nothing inside the single ones
+        assert_eq!(words(r"'C:\Users\'"), vec![r"C:\Users\"]);
+    }
+
+    #[test]
+    fn unterminated_quotes_are_errors() {
+        assert_eq!(tokenize(r#"/upload "my report.pdf"#), Err(TokenizeError::UnterminatedQuote { quote: '"' }));
+        assert_eq!(tokenize("/rename 'John"), Err(TokenizeError::UnterminatedQuote { quote: '\'' }));
+        assert_eq!(tokenize(r#"/rename "John\"#), Err(TokenizeError::UnterminatedQuote { quote: '"' }));
+        assert_eq!(tokenize(r"/rename John\"), Err(TokenizeError::DanglingEscape));
+    }
+}
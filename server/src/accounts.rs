@@ -0,0 +1,116 @@
+use std::collections::{HashMap};
+use std::path::{Path};
+use std::fs::{File};
+
+use shared::{Result};
+use shared::shared::{Shared};
+
+use serde::{Serialize, Deserialize};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{SaltString};
+use rand::rngs::{OsRng};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Account {
+    // The PHC string, it carries
+    // the salt and the parameters too
+    pub hash: String,
+}
+
+impl Account {
+    // Slow on purpose, so better
+    // not call it under a lock
+    pub fn new(password: &str) -> Result<Account> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|it| std::io::Error::other(it.to_string()))?
+            .to_string();
+
+        Ok(Account { hash: hash })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        match PasswordHash::new(&self.hash) {
+            Ok(it) => Argon2::default().verify_password(password.as_bytes(), &it).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+pub struct AccountsStorage {
+    path: String,
+    accounts: HashMap<String, Account>,
+    // address -> account name
+    logins: HashMap<String, String>,
+}
+
+impl AccountsStorage {
+    pub fn load(path: &str) -> Result<AccountsStorage> {
+        let mut accounts: HashMap<String, Account> = if Path::new(path).exists() {
+            serde_json::from_reader(File::open(path)?)?
+        } else {
+            HashMap::new()
+        };
+
+        // The old iterated SHA-256 hashes can't
+        // be checked anymore, so those names are freed
+        accounts.retain(|name, account| {
+            let is_valid = PasswordHash::new(&account.hash).is_ok();
+
+            if !is_valid {
+                log::warn!("Dropping the account {} with an outdated password hash", name);
+            }
+
+            is_valid
+        });
+
+        let storage = AccountsStorage {
+            path: path.to_owned(),
+            accounts: accounts,
+            logins: HashMap::new(),
+        };
+
+        Ok(storage)
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = File::create(&self.path)?;
+        serde_json::to_writer_pretty(file, &self.accounts)?;
+        Ok(())
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    pub fn register(&mut self, name: &str, account: Account) -> Result<()> {
+        self.accounts.insert(name.to_owned(), account);
+        self.save()
+    }
+
+    pub fn account(&self, name: &str) -> Option<Account> {
+        self.accounts.get(name).cloned()
+    }
+
+    pub fn log_in(&mut self, address: &str, name: &str) {
+        self.logins.insert(address.to_owned(), name.to_owned());
+    }
+
+    pub fn log_out(&mut self, address: &str) {
+        self.logins.remove(address);
+    }
+
+    pub fn move_login(&mut self, old_address: &str, new_address: &str) {
+        if let Some(it) = self.logins.remove(old_address) {
+            self.logins.insert(new_address.to_owned(), it);
+        }
+    }
+
+    pub fn account_of(&self, address: &str) -> Option<&String> {
+        self.logins.get(address)
+    }
+}
+
+pub type Accounts = Shared<AccountsStorage>;
@@ -0,0 +1,175 @@
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration};
+use std::thread;
+
+use shared::{Result, with_error_report};
+use shared::helpers::{equals_in_constant_time};
+
+use shared::communication::{WireFormat};
+use shared::connection::messages::{ServerMessage};
+use shared::connection::limits::{field_size};
+
+use serde::{Deserialize};
+use serde_json::{json, Value};
+
+use crate::connection::{ServerState, broadcast, explain_bad_name};
+use crate::http::{Request, read_request, write_response};
+use crate::{online_names, find_online};
+
+const DEFAULT_HISTORY_SIZE: usize = 20;
+
+struct Response {
+    status: &'static str,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: "200 OK", body }
+    }
+
+    fn error(status: &'static str, reason: &str) -> Response {
+        Response { status, body: json!({ "error": reason }) }
+    }
+}
+
+#[derive(Deserialize)]
+struct PostedText {
+    name: String,
+    text: String,
+}
+
+fn write_json(stream: &mut TcpStream, response: &Response) -> Result<()> {
+    write_response(stream, response.status, "application/json", &response.body.to_string())
+}
+
+fn post_message(state: &ServerState, body: &[u8]) -> Result<Response> {
+    let posted: PostedText = match serde_json::from_slice(body) {
+        Ok(it) => it,
+        Err(_) => return Ok(Response::error("400 Bad Request", "Expected {\"name\": ..., \"text\": ...}")),
+    };
+
+    let limits = state.config.read()?.limits(WireFormat::default());
+
+    if posted.name.is_empty() || field_size(&posted.name) > limits.name_size || field_size(&posted.text) > limits.text_size {
+        return Ok(Response::error("413 Payload Too Large", "The name or the text is too long"))
+    }
+
+    if let Some(reason) = explain_bad_name(&posted.name) {
+        return Ok(Response::error("400 Bad Request", reason))
+    }
+
+    // Bots can't pretend to be
+    // someone else
+    if state.moderation.read()?.is_name_banned(&posted.name) {
+        return Ok(Response::error("403 Forbidden", "This name is banned"))
+    }
+
+    let is_taken = find_online(&state.names, &state.clients, &posted.name)?.is_some()
+        || state.accounts.read()?.is_registered(&posted.name);
+
+    if is_taken {
+        return Ok(Response::error("409 Conflict", "This name belongs to someone else"))
+    }
+
+    let time = chrono::Utc::now();
+    log::info!("Message > {} (bot) > {}", &posted.name, &posted.text);
+
+    let message = ServerMessage::Text {
+        name: posted.name,
+        text: posted.text,
+        time: time.into(),
+    };
+
+    broadcast(state, &message)?;
+    Ok(Response::ok(json!({ "ok": true })))
+}
+
+fn history_size(query: &str) -> usize {
+    query.split('&')
+        .filter_map(|it| it.split_once('='))
+        .find(|(key, _)| *key == "limit")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_HISTORY_SIZE)
+}
+
+fn route(state: &ServerState, request: &Request) -> Result<Response> {
+    let expected = state.config.read()?.api_token.clone();
+
+    let token = request.header("Authorization")
+        .and_then(|it| it.strip_prefix("Bearer "))
+        .map(|it| it.trim());
+
+    // No token configured means
+    // nobody gets in
+    let is_authorized = match (&expected, token) {
+        (Some(expected), Some(token)) => equals_in_constant_time(expected.as_bytes(), token.as_bytes()),
+        _ => false,
+    };
+
+    if !is_authorized {
+        return Ok(Response::error("401 Unauthorized", "Wrong or missing token"))
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/messages") => {
+            post_message(state, &request.body)
+        }
+        ("GET", "/users") => {
+            let users = online_names(&state.names, &state.clients)?;
+            Ok(Response::ok(json!({ "users": users })))
+        }
+        ("GET", "/files") => {
+            let directory = state.config.read()?.uploads_directory.clone();
+            Ok(Response::ok(json!({ "files": uploaded_files(&directory)? })))
+        }
+        ("GET", "/history") => {
+            let messages = state.history.read()?.recent(history_size(&request.query));
+            Ok(Response::ok(json!({ "messages": messages })))
+        }
+        _ => {
+            Ok(Response::error("404 Not Found", "No such route"))
+        }
+    }
+}
+
+// Whatever the clients can download,
+// the same way the transfers see it
+fn uploaded_files(directory: &str) -> Result<Vec<String>> {
+    let mut files = vec![];
+
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_file() {
+            files.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn handle_request(mut stream: TcpStream, state: ServerState) -> Result<()> {
+    let patience = Duration::from_secs(state.config.read()?.message_deadline_seconds);
+    stream.set_read_timeout(Some(patience))?;
+
+    let response = match read_request(&mut stream, patience) {
+        Ok(request) => route(&state, &request)?,
+        Err(_) => Response::error("400 Bad Request", "Couldn't read the request"),
+    };
+
+    write_json(&mut stream, &response)
+}
+
+pub fn listen(listener: TcpListener, state: ServerState) -> Result<()> {
+    for incomming in listener.incoming() {
+        let the_state = state.clone();
+
+        thread::spawn(move || {
+            with_error_report(|| handle_request(incomming?, the_state))
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use std::path::{Path};
+
+use shared::{Result};
+
+// So that TLS works out of the box, the
+// clients trust it on first use anyway
+pub fn ensure_self_signed(certificate: &str, key: &str) -> Result<()> {
+    if Path::new(certificate).exists() || Path::new(key).exists() {
+        return Ok(())
+    }
+
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()])
+        .map_err(std::io::Error::other)?;
+
+    std::fs::write(certificate, generated.cert.pem())?;
+    std::fs::write(key, generated.key_pair.serialize_pem())?;
+
+    log::info!("Generated a self-signed certificate in {}", certificate);
+    Ok(())
+}
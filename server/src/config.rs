@@ -0,0 +1,183 @@
+use std::path::{Path, PathBuf};
+use std::fs::{File};
+use std::time::{Duration};
+
+use shared::{Result};
+use shared::shared::{Shared};
+use shared::communication::{ReadDeadlines, WireFormat};
+use shared::connection::limits::{Limits, DEFAULT_MESSAGE_SIZE, DEFAULT_LONG_TEXT_SIZE, DEFAULT_TEXT_FRAGMENTS};
+use shared::logging::{LoggingConfig};
+
+use serde::{Deserialize};
+
+pub const CONFIG_FILE: &str = "server.json";
+
+// A token bucket: up to `burst` actions
+// at once, refilled at `per_second`
+#[derive(Deserialize, Clone)]
+pub struct RateLimit {
+    pub burst: f64,
+    pub per_second: f64,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub accounts_file: String,
+    pub bans_file: String,
+    // The only place the transfers reach, so
+    // none of the server's own files may be there
+    pub uploads_directory: String,
+    // Registered accounts that are
+    // operators once logged in
+    pub operators: Vec<String>,
+    pub resume_grace_period_seconds: u64,
+    pub heartbeat_interval_seconds: u64,
+    pub heartbeat_timeout_seconds: u64,
+    pub message_deadline_seconds: u64,
+    pub idle_deadline_seconds: u64,
+    // Nobody answers the pings there,
+    // and reading isn't typing
+    pub plain_idle_deadline_seconds: u64,
+    // The rest of the limits
+    // are derived from it
+    pub maximum_message_size: usize,
+    // For the long texts sent in pieces,
+    // all the unfinished ones of a client
+    // count together
+    pub maximum_long_text_size: usize,
+    pub maximum_text_fragments: usize,
+    pub text_rate: RateLimit,
+    // Every piece of a long text,
+    // the first one is a text too
+    pub fragment_rate: RateLimit,
+    pub rename_rate: RateLimit,
+    pub transfer_rate: RateLimit,
+    pub flood_tolerance: RateLimit,
+    // The gateways are off unless
+    // the port is given
+    pub irc_port: Option<u16>,
+    pub irc_channel: String,
+    pub websocket_port: Option<u16>,
+    pub plain_port: Option<u16>,
+    // Same as the main port, but
+    // for the local tools
+    pub unix_socket: Option<String>,
+    // Off unless the port is given, the
+    // files are generated if both are missing
+    pub tls_port: Option<u16>,
+    pub tls_certificate: String,
+    pub tls_key: String,
+    // The REST API needs both
+    pub api_port: Option<u16>,
+    pub api_token: Option<String>,
+    pub history_size: usize,
+    pub metrics_port: Option<u16>,
+    // Only read at startup
+    pub logging: LoggingConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            accounts_file: "accounts.json".to_owned(),
+            bans_file: "bans.json".to_owned(),
+            uploads_directory: "uploads".to_owned(),
+            operators: vec![],
+            resume_grace_period_seconds: 30,
+            heartbeat_interval_seconds: 10,
+            heartbeat_timeout_seconds: 30,
+            message_deadline_seconds: 15,
+            idle_deadline_seconds: 300,
+            plain_idle_deadline_seconds: 3600,
+            maximum_message_size: DEFAULT_MESSAGE_SIZE,
+            maximum_long_text_size: DEFAULT_LONG_TEXT_SIZE,
+            maximum_text_fragments: DEFAULT_TEXT_FRAGMENTS,
+            text_rate: RateLimit { burst: 10.0, per_second: 2.0 },
+            fragment_rate: RateLimit { burst: DEFAULT_TEXT_FRAGMENTS as f64, per_second: 16.0 },
+            rename_rate: RateLimit { burst: 3.0, per_second: 0.1 },
+            transfer_rate: RateLimit { burst: 5.0, per_second: 0.5 },
+            flood_tolerance: RateLimit { burst: 10.0, per_second: 0.1 },
+            irc_port: None,
+            irc_channel: "#chat".to_owned(),
+            websocket_port: None,
+            plain_port: None,
+            unix_socket: None,
+            tls_port: None,
+            tls_certificate: "server.crt".to_owned(),
+            tls_key: "server.key".to_owned(),
+            api_port: None,
+            api_token: None,
+            history_size: 100,
+            metrics_port: None,
+            logging: LoggingConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &str) -> Result<ServerConfig> {
+        if !Path::new(path).exists() {
+            return Ok(ServerConfig::default())
+        }
+
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub fn upload_path(&self, name: &str) -> PathBuf {
+        Path::new(&self.uploads_directory).join(name)
+    }
+
+    // The file names can't leave the uploads
+    // directory, so it's enough to keep the
+    // server's own files out of it
+    pub fn check_private_files(&self) -> Result<()> {
+        let uploads = Path::new(&self.uploads_directory).canonicalize()?;
+
+        let files = [
+            CONFIG_FILE,
+            &self.accounts_file,
+            &self.bans_file,
+            // Otherwise anyone could
+            // take or plant the key
+            &self.tls_certificate,
+            &self.tls_key,
+        ];
+
+        for file in files {
+            let directory = match Path::new(file).parent() {
+                Some(it) if !it.as_os_str().is_empty() => it,
+                _ => Path::new("."),
+            };
+
+            // Missing ones can't be the uploads
+            let is_inside = directory.canonicalize()
+                .map(|it| it.starts_with(&uploads))
+                .unwrap_or(false);
+
+            if is_inside {
+                let message = format!("{} can't be in the uploads directory", file);
+                return Err(std::io::Error::other(message).into())
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_deadlines(&self) -> ReadDeadlines {
+        ReadDeadlines {
+            message: Some(Duration::from_secs(self.message_deadline_seconds)),
+            idle: Some(Duration::from_secs(self.idle_deadline_seconds)),
+        }
+    }
+
+    pub fn limits(&self, format: WireFormat) -> Limits {
+        Limits {
+            long_text_size: self.maximum_long_text_size,
+            text_fragments: self.maximum_text_fragments,
+            ..Limits::new(format, self.maximum_message_size)
+        }
+    }
+}
+
+pub type Config = Shared<ServerConfig>;
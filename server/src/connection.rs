@@ -1,54 +1,199 @@
-use std::net::{TcpStream, SocketAddr};
 use std::collections::{HashMap};
-use std::fs::{File};
+use std::io::{Read, Cursor};
+use std::time::{Instant, Duration};
+use std::path::{Path};
 
-use shared::{Result};
+use shared::{Result, is_would_block_error};
 use shared::shared::map::{SharedMap};
 use shared::shared::{Shared, IntoShared};
+use shared::transport::{BoxedTransport, split};
 
 use shared::communication::{
     ReadMessage,
     WriteMessage,
+    WireFormat,
+    PREAMBLE_SIZE,
 };
 
 use shared::communication::arson::{ArsonReader, ArsonWriter};
+use shared::communication::json::{JsonReader, JsonWriter};
+use shared::communication::binary::{BinaryReader, BinaryWriter};
 
 use shared::connection::messages::{
     CommonMessage,
     ClientMessage,
     ServerMessage,
-    MAXIMUM_MESSAGE_SIZE,
 };
 
 use shared::connection::{Context, Connection, WithConnection};
+use shared::connection::session::{Session, Codec, BoxedCodec};
 use shared::connection::sharers::{FileSharer, FileSharers};
+use shared::connection::limits::{SharedLimits, field_size};
+use shared::connection::fragments::{split_text, next_id};
+
+use crate::accounts::{Accounts, Account};
+use crate::sessions::{Sessions, SuspendedSession};
+use crate::config::{Config, ServerConfig};
+use crate::moderation::{Moderation};
+use crate::history::{History};
+use crate::transfers::{Transfers};
+use crate::metrics::{Metrics, MeteredReader, MeteredWriter};
+use crate::streams::{fill};
 
 pub type NamesMap = SharedMap<String, String>;
-pub type Clients = SharedMap<String, Shared<ArsonServerSession>>;
+pub type Clients = SharedMap<String, Shared<AnyServerSession>>;
+
+// Everything the clients
+// share with each other
+#[derive(Clone)]
+pub struct ServerState {
+    pub names: NamesMap,
+    pub clients: Clients,
+    pub accounts: Accounts,
+    pub sessions: Sessions,
+    pub moderation: Moderation,
+    pub history: History,
+    pub transfers: Transfers,
+    pub metrics: Metrics,
+    pub config: Config,
+}
+
+// Names like these could be mistaken
+// for the addresses of the guests
+pub fn explain_bad_name(name: &str) -> Option<&'static str> {
+    if name.contains('.') || name.contains(':') {
+        return Some("Your name can't contain '.'s or ':'s")
+    }
+
+    // Otherwise it could break the
+    // lines of the text gateways
+    if name.chars().any(char::is_control) {
+        return Some("Your name can't contain control characters")
+    }
+
+    None
+}
+
+// Transfers only reach the files
+// right inside the uploads directory
+pub fn explain_bad_file_name(name: &str) -> Option<&'static str> {
+    let is_bad = name.is_empty()
+        || name.contains('/')
+        || name.contains('\\')
+        || name.contains("..")
+        || Path::new(name).is_absolute();
+
+    if is_bad {
+        return Some("File names can't be empty or contain '/'s, '\\'s or '..'s")
+    }
+
+    None
+}
 
 pub struct ServerContext {
     common: Context,
     names: NamesMap,
     clients: Clients,
+    accounts: Accounts,
+    sessions: Sessions,
+    moderation: Moderation,
+    history: History,
+    transfers: Transfers,
+    metrics: Metrics,
+    config: Config,
 }
 
 impl ServerContext {
     pub fn new(
-        stream: Shared<TcpStream>,
+        transport: Shared<BoxedTransport>,
         reading_sharers: FileSharers,
         writing_sharers: Shared<Vec<FileSharer>>,
-        names: NamesMap,
-        clients: Clients,
+        limits: SharedLimits,
+        state: ServerState,
     ) -> ServerContext {
         ServerContext {
             common: Context::new(
-                stream,
+                transport,
                 reading_sharers,
-                writing_sharers
+                writing_sharers,
+                limits
             ),
-            names: names,
-            clients: clients,
+            names: state.names,
+            clients: state.clients,
+            accounts: state.accounts,
+            sessions: state.sessions,
+            moderation: state.moderation,
+            history: state.history,
+            transfers: state.transfers,
+            metrics: state.metrics,
+            config: state.config,
+        }
+    }
+
+    fn check_name_format(&self, new_name: &str) -> Option<RenameResult> {
+        explain_bad_name(new_name).map(|it| RenameResult::Failure { reason: it.to_owned() })
+    }
+
+    fn take_name(&self, address: &str, new_name: &str) -> Result<RenameResult> {
+        if self.moderation.read()?.is_name_banned(new_name) {
+            let message = RenameResult::Failure {
+                reason: "This name is banned".to_owned()
+            };
+
+            return Ok(message)
+        }
+
+        let cloned_names = self.names.clone();
+        let mut the_names = cloned_names.write()?;
+
+        if the_names.iter().any(|(key, it)| it == new_name && key != address) {
+            let message = RenameResult::Failure {
+                reason: "This name has already been taken, choose another one".to_owned()
+            };
+
+            return Ok(message)
         }
+
+        let old_name = if let Some(it) = the_names.get(address) {
+            it.clone()
+        } else {
+            address.to_owned()
+        };
+
+        the_names.insert(address.to_owned(), new_name.to_owned());
+        self.moderation.write()?.rename(&old_name, new_name)?;
+
+        let response = RenameResult::Success {
+            old_name: old_name,
+            new_name: new_name.to_owned(),
+        };
+
+        Ok(response)
+    }
+
+    // The old connection is still there,
+    // but the client says it's dead
+    fn take_over(&self, token: &str, address: &str) -> Result<Option<SuspendedSession>> {
+        let old_address = match self.sessions.write()?.take_over(token, address) {
+            Some(it) => it,
+            None => return Ok(None)
+        };
+
+        let name = self.names.get_clone(&old_address)?.unwrap_or_else(|| old_address.clone());
+
+        // Its reader stops quietly
+        // once it's not a client
+        if let Some(mut it) = self.clients.remove(&old_address)? {
+            let _ = it.close();
+        }
+
+        let session = SuspendedSession {
+            address: old_address,
+            name: name,
+            pending: vec![],
+        };
+
+        Ok(Some(session))
     }
 }
 
@@ -62,11 +207,39 @@ impl WithConnection for ServerContext {
     }
 }
 
-pub fn broadcast(clients: Clients, message: &ServerMessage) -> Result<()> {
-    for (_, connection) in clients.write()?.iter_mut() {
-        connection.write_message(message)?;
+pub fn broadcast(
+    state: &ServerState,
+    message: &ServerMessage,
+) -> Result<()> {
+    let started = Instant::now();
+
+    state.sessions.write()?.enqueue(message);
+    state.history.write()?.record(message);
+
+    for (address, connection) in state.clients.write()?.iter_mut() {
+        // A single broken client shouldn't
+        // prevent the others from hearing
+        if let Err(error) = connection.write_message(message) {
+            log::warn!("{} > {}", address, error);
+        }
     }
 
+    state.metrics.write()?.broadcast_took(started.elapsed());
+    Ok(())
+}
+
+// Forgets everything about the client
+// at the given address, it may be
+// someone else than the connection
+pub fn remove_client<C: ServerConnection + ?Sized>(
+    connection: &C,
+    address: &str,
+) -> Result<()> {
+    connection.clients()?.remove(address)?;
+    connection.names()?.remove(address)?;
+    connection.accounts()?.write()?.log_out(address);
+    connection.sessions()?.write()?.forget(address);
+    connection.state()?.transfers.write()?.forget(address);
     Ok(())
 }
 
@@ -79,14 +252,26 @@ pub trait ServerConnection: Connection {
     fn name(&self) -> Result<String>;
     fn names(&self) -> Result<NamesMap>;
     fn clients(&self) -> Result<Clients>;
+    fn accounts(&self) -> Result<Accounts>;
+    fn sessions(&self) -> Result<Sessions>;
+    fn moderation(&self) -> Result<Moderation>;
+    // All of the above at once
+    fn state(&self) -> Result<ServerState>;
+    fn config(&self) -> Result<ServerConfig>;
     fn broadcast(&mut self, message: &ServerMessage) -> Result<()>;
     fn rename(&mut self, new_name: &str) -> Result<RenameResult>;
+    fn register(&mut self, name: &str, password: &str) -> Result<RenameResult>;
+    fn authenticate(&mut self, name: &str, password: &str) -> Result<RenameResult>;
+    fn issue_token(&mut self) -> Result<String>;
+    fn suspend(&mut self) -> Result<Option<String>>;
+    fn resume(&mut self, token: &str) -> Result<Option<SuspendedSession>>;
+    fn expire(&mut self, token: &str) -> Result<Option<SuspendedSession>>;
     fn remove_from_clients(&mut self) -> Result<()>;
 }
 
 impl ServerConnection for ServerContext {
     fn name(&self) -> Result<String> {
-        let address = self.remote_address()?.to_string();
+        let address = self.remote_address()?;
 
         let proper = if let Some(it) = self.names.get_clone(&address)? {
             it
@@ -105,55 +290,171 @@ impl ServerConnection for ServerContext {
         Ok(self.clients.clone())
     }
 
+    fn accounts(&self) -> Result<Accounts> {
+        Ok(self.accounts.clone())
+    }
+
+    fn sessions(&self) -> Result<Sessions> {
+        Ok(self.sessions.clone())
+    }
+
+    fn moderation(&self) -> Result<Moderation> {
+        Ok(self.moderation.clone())
+    }
+
+    fn state(&self) -> Result<ServerState> {
+        Ok(ServerState {
+            names: self.names.clone(),
+            clients: self.clients.clone(),
+            accounts: self.accounts.clone(),
+            sessions: self.sessions.clone(),
+            moderation: self.moderation.clone(),
+            history: self.history.clone(),
+            transfers: self.transfers.clone(),
+            metrics: self.metrics.clone(),
+            config: self.config.clone(),
+        })
+    }
+
+    fn config(&self) -> Result<ServerConfig> {
+        Ok(self.config.read()?.clone())
+    }
+
     fn broadcast(&mut self, message: &ServerMessage) -> Result<()> {
-        broadcast(self.clients()?, message)
+        broadcast(&self.state()?, message)
     }
 
     fn rename(&mut self, new_name: &str) -> Result<RenameResult> {
-        if new_name.contains('.') || new_name.contains(':') {
+        if let Some(failure) = self.check_name_format(new_name) {
+            return Ok(failure)
+        }
+
+        let address = self.remote_address()?;
+        let the_accounts = self.accounts.read()?;
+
+        let is_owner = the_accounts.account_of(&address).map(|it| it.as_str()) == Some(new_name);
+
+        if the_accounts.is_registered(new_name) && !is_owner {
             let message = RenameResult::Failure {
-                reason: "Your name can't contain '.'s or ':'s".to_owned()
+                reason: "This name belongs to a registered user, use /login if it's you".to_owned()
             };
 
             return Ok(message)
         }
 
-        let cloned_names = self.names.clone();
-        let mut the_names = cloned_names.write()?;
+        self.take_name(&address, new_name)
+    }
+
+    fn register(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        if let Some(failure) = self.check_name_format(name) {
+            return Ok(failure)
+        }
+
+        let address = self.remote_address()?;
+        let taken = RenameResult::Failure {
+            reason: "This name has already been registered".to_owned()
+        };
 
-        if the_names.values().any(|it| it == &new_name) {
+        if self.accounts.read()?.is_registered(name) {
+            return Ok(taken)
+        }
+
+        // Hashing takes a while, so it's done
+        // before locking, and the name is checked
+        // again in case someone else got it meanwhile
+        let account = Account::new(password)?;
+        let mut the_accounts = self.accounts.write()?;
+
+        if the_accounts.is_registered(name) {
+            return Ok(taken)
+        }
+
+        let result = self.take_name(&address, name)?;
+
+        if let RenameResult::Success { .. } = &result {
+            the_accounts.register(name, account)?;
+            the_accounts.log_in(&address, name);
+        }
+
+        Ok(result)
+    }
+
+    fn authenticate(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        let address = self.remote_address()?;
+        let account = self.accounts.read()?.account(name);
+
+        // Verified without holding the lock
+        if !account.map(|it| it.verify(password)).unwrap_or(false) {
             let message = RenameResult::Failure {
-                reason: "This name has already been taken, choose another one".to_owned()
+                reason: "Wrong name or password".to_owned()
             };
 
             return Ok(message)
         }
 
-        let address = self.remote_address()?.to_string();
+        let mut the_accounts = self.accounts.write()?;
+        let result = self.take_name(&address, name)?;
 
-        let old_name = if let Some(it) = the_names.get(&address) {
-            it.clone()
-        } else {
-            address.clone()
-        };
+        if let RenameResult::Success { .. } = &result {
+            the_accounts.log_in(&address, name);
+        }
 
-        the_names.insert(address.clone(), new_name.to_owned());
+        Ok(result)
+    }
 
-        let response = RenameResult::Success {
-            old_name: old_name,
-            new_name: new_name.to_owned(),
+    fn issue_token(&mut self) -> Result<String> {
+        let address = self.remote_address()?;
+        Ok(self.sessions.write()?.issue_token(&address))
+    }
+
+    fn suspend(&mut self) -> Result<Option<String>> {
+        let address = self.remote_address()?;
+        let name = self.name()?;
+
+        // The name and the login stay
+        // reserved until the session expires
+        self.clients.remove(&address)?;
+        Ok(self.sessions.write()?.suspend(&address, &name))
+    }
+
+    fn resume(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        let address = self.remote_address()?;
+
+        let suspended = self.sessions.write()?.resume(token, &address);
+
+        let session = match suspended {
+            Some(it) => it,
+            None => match self.take_over(token, &address)? {
+                Some(it) => it,
+                None => return Ok(None)
+            }
         };
 
-        Ok(response)
+        self.names.remove(&session.address)?;
+        self.names.insert(address.clone(), session.name.clone())?;
+        self.accounts.write()?.move_login(&session.address, &address);
+        // Those went with the old connection
+        self.transfers.write()?.forget(&session.address);
+
+        Ok(Some(session))
     }
 
-    fn remove_from_clients(&mut self) -> Result<()> {
-        let address = self.remote_address()?.to_string();
+    fn expire(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        let session = match self.sessions.write()?.expire(token) {
+            Some(it) => it,
+            None => return Ok(None)
+        };
 
-        self.clients.remove(&address)?;
-        self.names.remove(&address)?;
+        self.names.remove(&session.address)?;
+        self.accounts.write()?.log_out(&session.address);
+        self.transfers.write()?.forget(&session.address);
 
-        Ok(())
+        Ok(Some(session))
+    }
+
+    fn remove_from_clients(&mut self) -> Result<()> {
+        let address = self.remote_address()?;
+        remove_client(self, &address)
     }
 }
 
@@ -175,6 +476,26 @@ impl<W: WithServerConnection> ServerConnection for W {
         self.server_connection().clients()
     }
 
+    fn accounts(&self) -> Result<Accounts> {
+        self.server_connection().accounts()
+    }
+
+    fn sessions(&self) -> Result<Sessions> {
+        self.server_connection().sessions()
+    }
+
+    fn moderation(&self) -> Result<Moderation> {
+        self.server_connection().moderation()
+    }
+
+    fn state(&self) -> Result<ServerState> {
+        self.server_connection().state()
+    }
+
+    fn config(&self) -> Result<ServerConfig> {
+        self.server_connection().config()
+    }
+
     fn broadcast(&mut self, message: &ServerMessage) -> Result<()> {
         self.server_connection_mut().broadcast(message)
     }
@@ -183,6 +504,30 @@ impl<W: WithServerConnection> ServerConnection for W {
         self.server_connection_mut().rename(new_name)
     }
 
+    fn register(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        self.server_connection_mut().register(name, password)
+    }
+
+    fn authenticate(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        self.server_connection_mut().authenticate(name, password)
+    }
+
+    fn issue_token(&mut self) -> Result<String> {
+        self.server_connection_mut().issue_token()
+    }
+
+    fn suspend(&mut self) -> Result<Option<String>> {
+        self.server_connection_mut().suspend()
+    }
+
+    fn resume(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        self.server_connection_mut().resume(token)
+    }
+
+    fn expire(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        self.server_connection_mut().expire(token)
+    }
+
     fn remove_from_clients(&mut self) -> Result<()> {
         self.server_connection_mut().remove_from_clients()
     }
@@ -201,145 +546,146 @@ impl<T: ServerConnection> ServerConnection for Shared<T> {
         self.inner.read()?.clients()
     }
 
+    fn accounts(&self) -> Result<Accounts> {
+        self.inner.read()?.accounts()
+    }
+
+    fn sessions(&self) -> Result<Sessions> {
+        self.inner.read()?.sessions()
+    }
+
+    fn moderation(&self) -> Result<Moderation> {
+        self.inner.read()?.moderation()
+    }
+
+    fn state(&self) -> Result<ServerState> {
+        self.inner.read()?.state()
+    }
+
+    fn config(&self) -> Result<ServerConfig> {
+        self.inner.read()?.config()
+    }
+
     fn broadcast(&mut self, message: &ServerMessage) -> Result<()> {
         // Prevents the deadlock:
         // self.inner is no longer
         // locked after taking the
         // clients.
-        broadcast(self.clients()?, message)
+        broadcast(&self.state()?, message)
     }
 
     fn rename(&mut self, new_name: &str) -> Result<RenameResult> {
         self.inner.write()?.rename(new_name)
     }
 
-    fn remove_from_clients(&mut self) -> Result<()> {
-        // Prevents the deadlock
-        let address = self.remote_address()?.to_string();
-        self.clients()?.remove(&address)?;
-        self.names()?.remove(&address)?;
-        Ok(())
+    fn register(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        self.inner.write()?.register(name, password)
     }
-}
 
-#[derive(Clone)]
-pub struct ArsonServerSession {
-    context: Shared<ServerContext>,
-    reader: Shared<ArsonReader<Shared<TcpStream>>>,
-    writer: Shared<ArsonWriter<Shared<TcpStream>>>,
-}
+    fn authenticate(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        self.inner.write()?.authenticate(name, password)
+    }
 
-impl ArsonServerSession {
-    pub fn new(
-        context: Shared<ServerContext>,
-        reader: Shared<ArsonReader<Shared<TcpStream>>>,
-        writer: Shared<ArsonWriter<Shared<TcpStream>>>,
-    ) -> ArsonServerSession {
-        ArsonServerSession {
-            context: context,
-            reader: reader,
-            writer: writer,
-        }
+    fn issue_token(&mut self) -> Result<String> {
+        self.inner.write()?.issue_token()
     }
-}
 
-impl ReadMessage<ClientMessage> for ArsonServerSession {
-    fn read_message(&mut self) -> Result<ClientMessage> {
-        self.reader.read_message()
+    fn suspend(&mut self) -> Result<Option<String>> {
+        self.inner.write()?.suspend()
     }
-}
 
-impl WriteMessage<ServerMessage> for ArsonServerSession {
-    fn write_message(&mut self, message: &ServerMessage) -> Result<()> {
-        self.writer.write_message(message)
+    fn resume(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        self.inner.write()?.resume(token)
     }
-}
 
-impl WriteMessage<CommonMessage> for ArsonServerSession {
-    fn write_message(&mut self, message: &CommonMessage) -> Result<()> {
-        let wrapped = ServerMessage::Common {
-            common: message.clone()
-        };
+    fn expire(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        self.inner.write()?.expire(token)
+    }
 
-        self.writer.write_message(&wrapped)
+    fn remove_from_clients(&mut self) -> Result<()> {
+        // Prevents the deadlock
+        let address = self.remote_address()?;
+        remove_client(self, &address)
     }
 }
 
-impl Connection for ArsonServerSession {
-    fn remote_address(&self) -> Result<SocketAddr> {
-        self.context.remote_address()
+pub type ClientCodec = BoxedCodec<ClientMessage, ServerMessage>;
+pub type ClientReader = <ClientCodec as Codec>::Reader;
+pub type ClientWriter = <ClientCodec as Codec>::Writer;
+
+// Talks to the client in whatever format
+// the reader and the writer understand,
+// so that the clients of all kinds end up
+// in the same room
+pub type AnyServerSession = Session<ServerContext, ClientCodec>;
+
+impl<K: Codec> ServerConnection for Session<ServerContext, K> {
+    fn name(&self) -> Result<String> {
+        self.context().name()
     }
 
-    fn free_id(&mut self) -> Result<usize> {
-        self.context.free_id()
+    fn names(&self) -> Result<NamesMap> {
+        self.context().names()
     }
 
-    fn prepare_sharer(
-        &mut self,
-        path: &str,
-        file: File,
-        name: &str,
-    ) -> Result<()> {
-        self.context.prepare_sharer(path, file, name)
+    fn clients(&self) -> Result<Clients> {
+        self.context().clients()
     }
 
-    fn promote_sharer(
-        &mut self,
-        name: &str,
-        size: usize,
-        id: usize,
-    ) -> Result<()> {
-        self.context.promote_sharer(name, size, id)
+    fn accounts(&self) -> Result<Accounts> {
+        self.context().accounts()
     }
 
-    fn accept_chunk(
-        &mut self,
-        data: &[u8],
-        id: usize,
-    ) -> Result<bool> {
-        self.context.accept_chunk(data, id)
+    fn sessions(&self) -> Result<Sessions> {
+        self.context().sessions()
     }
 
-    fn remove_unpromoted_sharer(&mut self, name: &str) -> Result<Option<FileSharer>> {
-        self.context.remove_unpromoted_sharer(name)
+    fn moderation(&self) -> Result<Moderation> {
+        self.context().moderation()
     }
 
-    fn remove_sharer(&mut self, id: usize) -> Result<Option<FileSharer>> {
-        self.context.remove_sharer(id)
+    fn state(&self) -> Result<ServerState> {
+        self.context().state()
     }
 
-    fn enqueu_sending_sharer(&mut self, sharer: FileSharer) -> Result<()> {
-        self.context.enqueu_sending_sharer(sharer)
+    fn config(&self) -> Result<ServerConfig> {
+        self.context().config()
     }
 
-    fn sending_sharers_queue(&self) -> Result<Shared<Vec<FileSharer>>> {
-        self.context.sending_sharers_queue()
+    fn broadcast(&mut self, message: &ServerMessage) -> Result<()> {
+        self.context_mut().broadcast(message)
     }
-}
 
-impl ServerConnection for ArsonServerSession {
-    fn name(&self) -> Result<String> {
-        self.context.name()
+    fn rename(&mut self, new_name: &str) -> Result<RenameResult> {
+        self.context_mut().rename(new_name)
     }
 
-    fn names(&self) -> Result<NamesMap> {
-        self.context.names()
+    fn register(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        self.context_mut().register(name, password)
     }
 
-    fn clients(&self) -> Result<Clients> {
-        self.context.clients()
+    fn authenticate(&mut self, name: &str, password: &str) -> Result<RenameResult> {
+        self.context_mut().authenticate(name, password)
     }
 
-    fn broadcast(&mut self, message: &ServerMessage) -> Result<()> {
-        self.context.broadcast(message)
+    fn issue_token(&mut self) -> Result<String> {
+        self.context_mut().issue_token()
     }
 
-    fn rename(&mut self, new_name: &str) -> Result<RenameResult> {
-        self.context.rename(new_name)
+    fn suspend(&mut self) -> Result<Option<String>> {
+        self.context_mut().suspend()
+    }
+
+    fn resume(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        self.context_mut().resume(token)
+    }
+
+    fn expire(&mut self, token: &str) -> Result<Option<SuspendedSession>> {
+        self.context_mut().expire(token)
     }
 
     fn remove_from_clients(&mut self) -> Result<()> {
-        self.context.remove_from_clients()
+        self.context_mut().remove_from_clients()
     }
 }
 
@@ -349,43 +695,167 @@ pub trait ServerSession: ServerConnection
     + WriteMessage<CommonMessage>
     + Clone + Send + Sync {}
 
-impl ServerSession for ArsonServerSession {}
+impl<K> ServerSession for Session<ServerContext, K>
+where
+    K: Codec<Incoming = ClientMessage, Outgoing = ServerMessage>,
+{}
 
 impl<T: ServerSession> ServerSession for Shared<T> {}
 
+// Builds the reader and the writer
+// out of the reading and the writing
+// halves of the transport, along with
+// the limits the reader is capped at
+pub type CodecBuilder = fn(
+    Shared<BoxedTransport>,
+    Shared<BoxedTransport>,
+    &ServerState,
+) -> Result<(ClientReader, ClientWriter, SharedLimits)>;
+
+// Texts too long for a single message
+// go in pieces, the client glues them
+// back, the rest goes as it is
+pub struct FragmentingWriter {
+    backend: ClientWriter,
+    limits: SharedLimits,
+}
+
+impl FragmentingWriter {
+    pub fn new(backend: ClientWriter, limits: SharedLimits) -> FragmentingWriter {
+        FragmentingWriter {
+            backend: backend,
+            limits: limits,
+        }
+    }
+}
+
+impl WriteMessage<ServerMessage> for FragmentingWriter {
+    fn write_message(&mut self, message: &ServerMessage) -> Result<()> {
+        let limits = self.limits.get()?;
+
+        let (text, name, time) = match message {
+            ServerMessage::Text { text, name, time } if field_size(text) > limits.text_size => (text, name, time),
+            _ => return self.backend.write_message(message),
+        };
+
+        let pieces = split_text(text, limits.fragment_size);
+        let count = pieces.len();
+        let id = next_id();
+
+        for (index, piece) in pieces.into_iter().enumerate() {
+            let fragment = ServerMessage::TextFragment {
+                text: piece,
+                name: name.clone(),
+                time: *time,
+                id: id,
+                index: index,
+                last: index + 1 == count,
+            };
+
+            self.backend.write_message(&fragment)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Waits for the first bytes to see which
+// format the client speaks. Whatever else
+// has arrived is already the handshake
+fn read_preamble(stream: &mut impl Read, patience: Duration) -> Result<(WireFormat, Vec<u8>)> {
+    let started = Instant::now();
+    let mut buffer = vec![];
+
+    while buffer.len() < PREAMBLE_SIZE {
+        match fill(stream, &mut buffer) {
+            Err(error) if is_would_block_error(&error) && started.elapsed() < patience => continue,
+            other => other?,
+        }
+    }
+
+    let format = WireFormat::from_preamble(&buffer[..PREAMBLE_SIZE]);
+    let leftovers = buffer.split_off(format.preamble().len());
+    Ok((format, leftovers))
+}
+
+// Arson, unless the client
+// asks for something else
+pub fn build_native_codec(
+    reading_stream: Shared<BoxedTransport>,
+    writing_stream: Shared<BoxedTransport>,
+    state: &ServerState,
+) -> Result<(ClientReader, ClientWriter, SharedLimits)> {
+    let (deadlines, patience) = {
+        let config = state.config.read()?;
+        (config.read_deadlines(), Duration::from_secs(config.message_deadline_seconds))
+    };
+
+    let (format, leftovers) = read_preamble(&mut *reading_stream.write()?, patience)?;
+    let reading_stream = Cursor::new(leftovers).chain(reading_stream);
+
+    // Depend on the format, so they
+    // are only known at this point
+    let limits = SharedLimits::new(state.config.read()?.limits(format));
+    let capacity = limits.capacity();
+
+    let (reader, writer): (ClientReader, ClientWriter) = match format {
+        WireFormat::Arson => (
+            Box::new(ArsonReader::with_deadlines(reading_stream, capacity.clone(), deadlines)),
+            Box::new(ArsonWriter::new(writing_stream, capacity)),
+        ),
+        WireFormat::Json => (
+            Box::new(JsonReader::with_deadlines(reading_stream, capacity.clone(), deadlines)),
+            Box::new(JsonWriter::new(writing_stream, capacity)),
+        ),
+        WireFormat::Binary => (
+            Box::new(BinaryReader::with_deadlines(reading_stream, capacity.clone(), deadlines)),
+            Box::new(BinaryWriter::new(writing_stream, capacity)),
+        ),
+    };
+
+    let writer = Box::new(FragmentingWriter::new(writer, limits.clone()));
+    Ok((reader, writer, limits))
+}
+
 pub fn build_connection(
-    stream: TcpStream,
-    names: NamesMap,
-    clients: Clients,
-) -> Result<(ArsonServerSession, ArsonServerSession)> {
-    let reading_stream = stream.try_clone()?.to_shared();
-    let writing_stream = stream.to_shared();
+    transport: BoxedTransport,
+    state: ServerState,
+    build_codec: CodecBuilder,
+) -> Result<(AnyServerSession, AnyServerSession)> {
+    let (reading_stream, writing_stream) = split(transport)?;
+
+    let (reader, writer, limits) = build_codec(reading_stream.clone(), writing_stream.clone(), &state)?;
+
+    // Counted the same way,
+    // whatever the format
+    let reader: ClientReader = Box::new(MeteredReader::new(reader, state.metrics.clone()));
+    let writer: ClientWriter = Box::new(MeteredWriter::new(writer, state.metrics.clone()));
 
-    let reader = ArsonReader::new(reading_stream.clone(), MAXIMUM_MESSAGE_SIZE).to_shared();
-    let writer = ArsonWriter::new(writing_stream.clone()).to_shared();
+    let reader = reader.to_shared();
+    let writer = writer.to_shared();
 
     let reading_sharers = HashMap::new().to_shared();
     let writing_sharers = vec![].to_shared();
 
-    let reader_context = ArsonServerSession::new(
+    let reader_context = AnyServerSession::new(
         ServerContext::new(
             reading_stream,
             reading_sharers.clone(),
             writing_sharers.clone(),
-            names.clone(),
-            clients.clone()
+            limits.clone(),
+            state.clone(),
         ).to_shared(),
         reader.clone(),
         writer.clone(),
     );
 
-    let writer_context = ArsonServerSession::new(
+    let writer_context = AnyServerSession::new(
         ServerContext::new(
             writing_stream,
             reading_sharers.clone(),
             writing_sharers.clone(),
-            names,
-            clients
+            limits,
+            state,
         ).to_shared(),
         reader.clone(),
         writer.clone(),
@@ -0,0 +1,223 @@
+use std::io::{BufRead};
+
+use shared::{Result};
+use shared::connection::{Connection};
+use shared::communication::{WireFormat};
+use shared::connection::messages::{ServerMessage};
+use shared::connection::limits::{field_size};
+
+use crate::connection::{ServerConnection, ServerState, broadcast};
+use crate::config::{ServerConfig, CONFIG_FILE};
+use crate::{find_online, throw_out, is_operator};
+
+const CONSOLE_NAME: &str = "Server";
+
+fn list_sessions(state: &ServerState) -> Result<()> {
+    // Copied, so that the clients
+    // aren't locked while we're printing
+    let clients: Vec<_> = state.clients.read()?
+        .iter()
+        .map(|(address, it)| (address.clone(), it.clone()))
+        .collect();
+
+    println!("(Console) {} online", clients.len());
+
+    for (address, session) in &clients {
+        let mut flags = vec![];
+
+        if let Some(account) = state.accounts.read()?.account_of(address) {
+            flags.push(format!("account {}", account));
+        }
+
+        if is_operator(session)? {
+            flags.push("operator".to_owned());
+        }
+
+        if state.moderation.read()?.is_muted(&session.name()?) {
+            flags.push("muted".to_owned());
+        }
+
+        if flags.is_empty() {
+            println!("(Console) {} > {}", address, session.name()?);
+        } else {
+            println!("(Console) {} > {} ({})", address, session.name()?, flags.join(", "));
+        }
+    }
+
+    for it in state.sessions.read()?.suspended() {
+        println!("(Console) {} > {} suspended, {} pending", &it.address, &it.name, it.pending.len());
+    }
+
+    Ok(())
+}
+
+fn list_transfers(state: &ServerState) -> Result<()> {
+    let clients: Vec<_> = state.clients.read()?
+        .iter()
+        .map(|(address, it)| (address.clone(), it.clone()))
+        .collect();
+
+    let transfers = state.transfers.read()?;
+    let mut count = 0;
+
+    for (address, session) in &clients {
+        let name = session.name()?;
+
+        for it in session.receiving_sharers()?.read()?.values() {
+            // The downloads wait there for the
+            // client to agree, they're listed below
+            if transfers.is_download(address, it.id) {
+                continue
+            }
+
+            count += 1;
+
+            // Unpromoted sharers don't know
+            // the size yet
+            if it.size == 0 {
+                println!("(Console) {} > {} > uploading {}, waiting", &name, it.id, &it.name);
+            } else {
+                println!("(Console) {} > {} > uploading {}, {}%", &name, it.id, &it.name, it.percentage());
+            }
+        }
+    }
+
+    for it in transfers.downloads() {
+        count += 1;
+
+        let name = state.names.get_clone(&it.address)?.unwrap_or_else(|| it.address.clone());
+
+        if it.sent.is_none() {
+            println!("(Console) {} > {} > downloading {}, waiting", &name, it.id, &it.name);
+        } else {
+            println!("(Console) {} > {} > downloading {}, {}%", &name, it.id, &it.name, it.percentage());
+        }
+    }
+
+    if count == 0 {
+        println!("(Console) No transfers at the moment");
+    }
+
+    Ok(())
+}
+
+fn kick(state: &ServerState, name: &str) -> Result<()> {
+    let address = match find_online(&state.names, &state.clients, name)? {
+        Some(it) => it,
+        None => {
+            println!("(Console) There's no {} here", name);
+            return Ok(())
+        }
+    };
+
+    // The session is the one to kick, but it
+    // can still clean up the shared state
+    if let Some(mut session) = state.clients.get_clone(&address)? {
+        throw_out(&mut session, &address, "The server has kicked you out".to_owned())?;
+    }
+
+    log::info!("Kick > {} > {}", CONSOLE_NAME, name);
+
+    let event = ServerMessage::UserKicked {
+        name: name.to_owned(),
+        by: CONSOLE_NAME.to_owned(),
+    };
+
+    broadcast(state, &event)
+}
+
+fn say(state: &ServerState, text: &str) -> Result<()> {
+    let limits = state.config.read()?.limits(WireFormat::default());
+
+    if field_size(text) > limits.text_size {
+        println!("(Console) No way, sorry, this is way too long");
+        return Ok(())
+    }
+
+    let message = ServerMessage::Support {
+        text: text.to_owned(),
+    };
+
+    broadcast(state, &message)
+}
+
+fn reload(state: &ServerState) -> Result<()> {
+    let config = ServerConfig::load(CONFIG_FILE)?;
+    *state.config.write()? = config;
+    println!("(Console) Reloaded {}, new connections will see all of it", CONFIG_FILE);
+    Ok(())
+}
+
+fn shutdown(state: &ServerState) -> Result<()> {
+    let addresses: Vec<String> = state.clients.read()?
+        .keys()
+        .cloned()
+        .collect();
+
+    for address in &addresses {
+        if let Some(mut session) = state.clients.get_clone(address)? {
+            throw_out(&mut session, address, "The server is shutting down".to_owned())?;
+        }
+    }
+
+    log::info!("Shutdown > {} clients said goodbye", addresses.len());
+    std::process::exit(0)
+}
+
+fn help() {
+    println!("(Console) /sessions - list the online and suspended users");
+    println!("(Console) /transfers - list the files being sent");
+    println!("(Console) /kick <name> - disconnect a user");
+    println!("(Console) /say <text> - tell everyone something");
+    println!("(Console) /reload - read {} again", CONFIG_FILE);
+    println!("(Console) /shutdown - disconnect everyone and stop");
+}
+
+fn handle_command(state: &ServerState, line: &str) -> Result<()> {
+    let line = line.trim();
+
+    let (command, rest) = match line.find(' ') {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        "" => Ok(()),
+        "/sessions" => list_sessions(state),
+        "/transfers" => list_transfers(state),
+        "/kick" if rest.is_empty() => {
+            println!("(Console) Kick who? Try /kick <name>");
+            Ok(())
+        }
+        "/kick" => kick(state, rest),
+        "/say" if rest.is_empty() => {
+            println!("(Console) Say what? Try /say <text>");
+            Ok(())
+        }
+        "/say" => say(state, rest),
+        "/reload" => reload(state),
+        "/shutdown" => shutdown(state),
+        "/help" => {
+            help();
+            Ok(())
+        }
+        _ => {
+            println!("(Console) Unknown command, see /help");
+            Ok(())
+        }
+    }
+}
+
+pub fn run(state: ServerState) -> Result<()> {
+    let stdin = std::io::stdin();
+
+    // Stops quietly if there's no terminal,
+    // the server keeps running without it
+    for line in stdin.lock().lines() {
+        if let Err(error) = handle_command(&state, &line?) {
+            println!("(Console) Error > {}", error);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::collections::{VecDeque};
+
+use shared::shared::{Shared};
+use shared::connection::messages::{ServerMessage};
+
+use chrono::{DateTime, Utc};
+
+use serde::{Serialize};
+
+#[derive(Serialize, Clone)]
+pub struct HistoryEntry {
+    pub name: String,
+    pub text: String,
+    // RFC 3339
+    pub time: String,
+}
+
+// Remembers what's been said
+pub struct HistoryStorage {
+    capacity: usize,
+    texts: VecDeque<HistoryEntry>,
+}
+
+impl HistoryStorage {
+    pub fn new(capacity: usize) -> HistoryStorage {
+        HistoryStorage {
+            capacity: capacity,
+            texts: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, message: &ServerMessage) {
+        if let ServerMessage::Text { text, name, time } = message {
+            if self.texts.len() >= self.capacity {
+                self.texts.pop_front();
+            }
+
+            let the_time: DateTime<Utc> = time.to_chrono();
+
+            self.texts.push_back(HistoryEntry {
+                name: name.clone(),
+                text: text.clone(),
+                time: the_time.to_rfc3339(),
+            });
+        }
+    }
+
+    // The latest ones, oldest first
+    pub fn recent(&self, count: usize) -> Vec<HistoryEntry> {
+        let skipped = self.texts.len().saturating_sub(count);
+        self.texts.iter().skip(skipped).cloned().collect()
+    }
+}
+
+pub type History = Shared<HistoryStorage>;
@@ -0,0 +1,109 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream};
+use std::time::{Duration, Instant};
+
+use shared::{Result, is_would_block_error};
+
+use crate::streams::{fill, too_much_data};
+
+const MAXIMUM_HEAD_SIZE: usize = 8192;
+const MAXIMUM_BODY_SIZE: usize = 4096;
+
+// Just enough HTTP/1.1 for the
+// embedded endpoints
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.head, name)
+    }
+}
+
+pub fn find_header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .skip(1)
+        .filter_map(|it| it.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+// Returns the end of the head, the
+// body may already be in the buffer
+pub fn read_head(stream: &mut impl Read, buffer: &mut Vec<u8>, patience: Duration) -> Result<usize> {
+    let started = Instant::now();
+
+    loop {
+        if let Some(index) = buffer.windows(4).position(|it| it == b"\r\n\r\n") {
+            return Ok(index + 4)
+        }
+
+        if buffer.len() >= MAXIMUM_HEAD_SIZE {
+            return Err(too_much_data())
+        }
+
+        match fill(stream, buffer) {
+            Err(error) if is_would_block_error(&error) && started.elapsed() < patience => continue,
+            other => other?,
+        }
+    }
+}
+
+pub fn read_request(stream: &mut TcpStream, patience: Duration) -> Result<Request> {
+    let started = Instant::now();
+    let mut buffer = vec![];
+
+    let head_end = read_head(stream, &mut buffer, patience)?;
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+
+    let length = find_header(&head, "Content-Length")
+        .and_then(|it| it.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    if length > MAXIMUM_BODY_SIZE {
+        return Err(too_much_data())
+    }
+
+    while buffer.len() < head_end + length {
+        match fill(stream, &mut buffer) {
+            Err(error) if is_would_block_error(&error) && started.elapsed() < patience => continue,
+            other => other?,
+        }
+    }
+
+    let mut words = head.lines().next().unwrap_or("").split_whitespace();
+    let method = words.next().unwrap_or("").to_owned();
+    let target = words.next().unwrap_or("");
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_owned(), query.to_owned()),
+        None => (target.to_owned(), String::new()),
+    };
+
+    Ok(Request {
+        method: method,
+        path: path,
+        query: query,
+        body: buffer[head_end..head_end + length].to_vec(),
+        head: head,
+    })
+}
+
+pub fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len(),
+    );
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
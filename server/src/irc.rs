@@ -0,0 +1,595 @@
+use std::collections::{VecDeque};
+use std::io::{Read, Write};
+
+use shared::{Result};
+use shared::shared::{Shared, IntoShared};
+use shared::transport::{BoxedTransport};
+use shared::communication::{ReadMessage, WriteMessage, WireFormat, ReadDeadlines};
+use shared::communication::framing::{Frames};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+    CommonMessage,
+};
+
+use shared::connection::limits::{Limits, SharedLimits, field_size};
+use shared::connection::fragments::{split_text};
+
+use crate::connection::{ServerState, ClientReader, ClientWriter};
+use crate::streams::{read_line};
+
+// Including the "\r\n", see RFC 1459
+const MAXIMUM_LINE_SIZE: usize = 512;
+const SERVER_NAME: &str = "tcp-chat";
+
+// What the reader and the writer
+// of the same client know together
+pub struct IrcState {
+    // The name the room knows us by
+    name: String,
+    // The nick the IRC client has
+    // been told about, and the one
+    // it asks for
+    nick: Option<String>,
+    renaming_to: Option<String>,
+    password: Option<String>,
+    channel: String,
+    ping_token: String,
+    has_user: bool,
+    is_registered: bool,
+    is_greeted: bool,
+    is_welcomed: bool,
+    is_joining: bool,
+    is_joined: bool,
+}
+
+impl IrcState {
+    pub fn new(address: &str, channel: &str) -> IrcState {
+        IrcState {
+            name: address.to_owned(),
+            nick: None,
+            renaming_to: None,
+            password: None,
+            channel: channel.to_owned(),
+            ping_token: SERVER_NAME.to_owned(),
+            has_user: false,
+            is_registered: false,
+            is_greeted: false,
+            is_welcomed: false,
+            is_joining: false,
+            is_joined: false,
+        }
+    }
+
+    fn nick_or_star(&self) -> String {
+        match &self.nick {
+            Some(it) => it.clone(),
+            None => "*".to_owned(),
+        }
+    }
+}
+
+// Whatever comes from the others can't
+// break the line apart, or the rest of
+// it would be a command of its own
+fn clean(text: &str) -> String {
+    text.replace(['\r', '\n', '\0'], " ")
+}
+
+// IRC doesn't allow spaces in
+// the middle of the prefixes
+fn to_nick(name: &str) -> String {
+    name.chars()
+        .map(|it| if it == ' ' || it.is_control() { '_' } else { it })
+        .collect()
+}
+
+fn prefix_of(name: &str) -> String {
+    let nick = to_nick(name);
+    format!(":{}!{}@{}", &nick, &nick, SERVER_NAME)
+}
+
+// At most `size` bytes each, but never in
+// the middle of a character, IRC sends
+// the text the way it is
+fn cut(text: &str, size: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let mut end = std::cmp::min(size, rest.len());
+
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        // A character larger than the
+        // room itself goes whole
+        if end == 0 {
+            end = rest.chars().next().map(char::len_utf8).unwrap_or(rest.len());
+        }
+
+        let (piece, remaining) = rest.split_at(end);
+        pieces.push(piece);
+        rest = remaining;
+    }
+
+    pieces
+}
+
+// A line per line of the text, and more
+// if the head leaves too little room
+fn push_text(head: &str, text: &str, lines: &mut Vec<String>) {
+    let room = MAXIMUM_LINE_SIZE.saturating_sub(head.len() + 2);
+
+    for line in text.lines() {
+        let line = clean(line);
+
+        if line.is_empty() {
+            lines.push(head.to_owned());
+        }
+
+        for piece in cut(&line, room) {
+            lines.push(format!("{}{}", head, piece));
+        }
+    }
+}
+
+fn numeric(state: &IrcState, code: &str, rest: &str) -> String {
+    format!(":{} {} {} {}", SERVER_NAME, code, state.nick_or_star(), rest)
+}
+
+struct IrcLine {
+    command: String,
+    params: Vec<String>,
+}
+
+fn parse_line(line: &str) -> Option<IrcLine> {
+    let mut rest = line.trim_start();
+
+    // The prefix means nothing
+    // when sent by a client
+    if rest.starts_with(':') {
+        rest = match rest.find(' ') {
+            Some(index) => rest[index..].trim_start(),
+            None => return None,
+        };
+    }
+
+    let (middle, trailing) = match rest.find(" :") {
+        Some(index) => (&rest[..index], Some(&rest[index + 2..])),
+        None => (rest, None),
+    };
+
+    let mut words = middle.split_whitespace();
+    let command = words.next()?.to_uppercase();
+    let mut params: Vec<String> = words.map(|it| it.to_owned()).collect();
+
+    if let Some(it) = trailing {
+        params.push(it.to_owned());
+    }
+
+    Some(IrcLine { command, params })
+}
+
+pub struct IrcReader<R> {
+    frames: Frames<R>,
+    // For the answers that only
+    // make sense to the IRC client
+    replies: Shared<BoxedTransport>,
+    state: Shared<IrcState>,
+    limits: Limits,
+    pending: VecDeque<ClientMessage>,
+    // For the texts that only fit
+    // once escaped in pieces
+    next_text_id: usize,
+}
+
+impl<R: Read> IrcReader<R> {
+    pub fn new(
+        stream: R,
+        replies: Shared<BoxedTransport>,
+        state: Shared<IrcState>,
+        limits: Limits,
+        deadlines: ReadDeadlines,
+    ) -> IrcReader<R> {
+        IrcReader {
+            frames: Frames::new(stream, MAXIMUM_LINE_SIZE, deadlines),
+            replies: replies,
+            state: state,
+            limits: limits,
+            pending: VecDeque::new(),
+            next_text_id: 0,
+        }
+    }
+
+    fn reply(&mut self, line: String) -> Result<()> {
+        self.replies.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.replies.flush()?;
+        Ok(())
+    }
+
+    fn try_register(&mut self) {
+        let mut state = match self.state.write() {
+            Ok(it) => it,
+            Err(_) => return,
+        };
+
+        let nick = match &state.renaming_to {
+            Some(it) if state.has_user && !state.is_registered => it.clone(),
+            _ => return,
+        };
+
+        state.is_registered = true;
+        state.is_joining = true;
+
+        self.pending.push_back(ClientMessage::Join);
+
+        // Takes the nick as the name, and
+        // the server answers the same way
+        // in both cases
+        match state.password.take() {
+            Some(password) => {
+                self.pending.push_back(ClientMessage::Authenticate { name: nick, password: password });
+            }
+            None => {
+                self.pending.push_back(ClientMessage::Rename { new_name: nick });
+            }
+        }
+
+        self.pending.push_back(ClientMessage::ListUsers);
+    }
+
+    fn handle_nick(&mut self, params: &[String]) -> Result<()> {
+        let nick = match params.first() {
+            Some(it) => it.clone(),
+            None => {
+                let line = numeric(&*self.state.read()?, "431", ":No nickname given");
+                return self.reply(line)
+            }
+        };
+
+        if field_size(&nick) > self.limits.name_size {
+            let line = numeric(&*self.state.read()?, "432", &format!("{} :Erroneous nickname", &nick));
+            return self.reply(line)
+        }
+
+        let mut state = self.state.write()?;
+
+        state.renaming_to = Some(nick.clone());
+
+        if !state.is_registered {
+            drop(state);
+            self.try_register();
+            return Ok(())
+        }
+
+        self.pending.push_back(ClientMessage::Rename { new_name: nick });
+
+        // Registration still waits for a good name
+        if !state.is_welcomed {
+            self.pending.push_back(ClientMessage::ListUsers);
+        }
+
+        Ok(())
+    }
+
+    fn handle_join(&mut self, params: &[String]) -> Result<()> {
+        let mut state = self.state.write()?;
+        let requested = params.first().cloned().unwrap_or_default();
+
+        for it in requested.split(',').filter(|it| !it.is_empty()) {
+            if it == state.channel {
+                // Everyone gets there
+                // right after the welcome
+                continue
+            }
+
+            let line = numeric(&state, "403", &format!("{} :There's only {} here", it, &state.channel));
+            drop(state);
+            self.reply(line)?;
+            state = self.state.write()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_privmsg(&mut self, params: &[String]) -> Result<()> {
+        let state = self.state.read()?;
+
+        let (target, text) = match params {
+            [target, text, ..] => (target, text),
+            _ => {
+                let line = numeric(&state, "412", ":No text to send");
+                drop(state);
+                return self.reply(line)
+            }
+        };
+
+        if *target != state.channel {
+            let line = numeric(&state, "401", &format!("{} :Private messages aren't supported here", target));
+            drop(state);
+            return self.reply(line)
+        }
+
+        drop(state);
+
+        if field_size(text) <= self.limits.text_size {
+            self.pending.push_back(ClientMessage::Text { text: text.clone() });
+            return Ok(())
+        }
+
+        // Same as the native clients do,
+        // the others get it whole
+        let pieces = split_text(text, self.limits.fragment_size);
+        let count = pieces.len();
+        let id = self.next_text_id;
+
+        self.next_text_id += 1;
+
+        for (index, piece) in pieces.into_iter().enumerate() {
+            self.pending.push_back(ClientMessage::TextFragment {
+                text: piece,
+                id: id,
+                index: index,
+                last: index + 1 == count,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn handle_line(&mut self, line: &str) -> Result<()> {
+        let IrcLine { command, params } = match parse_line(line) {
+            Some(it) => it,
+            None => return Ok(()),
+        };
+
+        let is_registered = self.state.read()?.is_registered;
+
+        match command.as_str() {
+            "CAP" | "MODE" | "WHO" => {
+                // Not supported, but sent by
+                // the clients all the time
+            }
+            "PASS" => {
+                self.state.write()?.password = params.first().cloned();
+            }
+            "NICK" => {
+                self.handle_nick(&params)?;
+            }
+            "USER" => {
+                self.state.write()?.has_user = true;
+                self.try_register();
+            }
+            // Clients may check the server is there
+            // before registering, but the room only
+            // hears from them after the handshake
+            "PING" if !is_registered => {
+                let token = params.first().cloned().unwrap_or_else(|| SERVER_NAME.to_owned());
+                self.reply(format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, token))?;
+            }
+            "PING" => {
+                if let Some(it) = params.first() {
+                    self.state.write()?.ping_token = it.clone();
+                }
+
+                self.pending.push_back(ClientMessage::Common { common: CommonMessage::Ping });
+            }
+            "PONG" => {
+                self.pending.push_back(ClientMessage::Common { common: CommonMessage::Pong });
+            }
+            _ if !is_registered => {
+                let line = numeric(&*self.state.read()?, "451", ":You have not registered");
+                self.reply(line)?;
+            }
+            "JOIN" => {
+                self.handle_join(&params)?;
+            }
+            "PRIVMSG" => {
+                self.handle_privmsg(&params)?;
+            }
+            "NAMES" => {
+                self.pending.push_back(ClientMessage::ListUsers);
+            }
+            "PART" | "QUIT" => {
+                self.pending.push_back(ClientMessage::Leave);
+            }
+            _ => {
+                let line = numeric(&*self.state.read()?, "421", &format!("{} :Unknown command", &command));
+                self.reply(line)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> ReadMessage<ClientMessage> for IrcReader<R> {
+    fn read_message(&mut self) -> Result<ClientMessage> {
+        loop {
+            if let Some(it) = self.pending.pop_front() {
+                return Ok(it)
+            }
+
+            let line = read_line(&mut self.frames)?;
+            self.handle_line(&line)?;
+        }
+    }
+}
+
+pub struct IrcWriter<W> {
+    stream: W,
+    state: Shared<IrcState>,
+}
+
+impl<W: Write> IrcWriter<W> {
+    pub fn new(stream: W, state: Shared<IrcState>) -> IrcWriter<W> {
+        IrcWriter {
+            stream: stream,
+            state: state,
+        }
+    }
+}
+
+fn welcome(state: &mut IrcState, lines: &mut Vec<String>) {
+    state.is_welcomed = true;
+
+    lines.push(numeric(state, "001", &format!(":Welcome to the club, {}", clean(&state.name))));
+    lines.push(numeric(state, "002", &format!(":Your host is {}", SERVER_NAME)));
+    lines.push(numeric(state, "003", ":This server has been around for a while"));
+    lines.push(numeric(state, "004", &format!("{} 0.1 o o", SERVER_NAME)));
+    lines.push(numeric(state, "422", ":MOTD File is missing"));
+}
+
+fn translate_rename(state: &mut IrcState, old_name: &str, new_name: &str, lines: &mut Vec<String>) {
+    if old_name != state.name {
+        if state.is_joined {
+            lines.push(format!("{} NICK :{}", prefix_of(old_name), to_nick(new_name)));
+        }
+
+        return
+    }
+
+    state.renaming_to = None;
+    state.name = new_name.to_owned();
+
+    if state.is_welcomed {
+        lines.push(format!("{} NICK :{}", prefix_of(old_name), to_nick(new_name)));
+        state.nick = Some(to_nick(new_name));
+    } else {
+        state.nick = Some(to_nick(new_name));
+        welcome(state, lines);
+    }
+}
+
+fn translate_user_list(state: &mut IrcState, names: &[String], last: bool, lines: &mut Vec<String>) {
+    if !state.is_welcomed {
+        return
+    }
+
+    if state.is_joining {
+        state.is_joining = false;
+        state.is_joined = true;
+        lines.push(format!("{} JOIN {}", prefix_of(&state.name), &state.channel));
+    }
+
+    let nicks: Vec<String> = names.iter().map(|it| to_nick(it)).collect();
+    lines.push(numeric(state, "353", &format!("= {} :{}", &state.channel, nicks.join(" "))));
+
+    if last {
+        lines.push(numeric(state, "366", &format!("{} :End of /NAMES list.", &state.channel)));
+    }
+}
+
+fn translate(state: &mut IrcState, message: &ServerMessage) -> Vec<String> {
+    let mut lines = vec![];
+
+    match message {
+        ServerMessage::UserRenamed { old_name, new_name } => {
+            translate_rename(state, old_name, new_name, &mut lines);
+        }
+        ServerMessage::UserList { names, last } => {
+            translate_user_list(state, names, *last, &mut lines);
+        }
+        // The personal greeting always comes first,
+        // anything after it may be a rename failure
+        ServerMessage::Support { text } if !state.is_greeted => {
+            state.is_greeted = true;
+            push_text(&format!(":{} NOTICE {} :", SERVER_NAME, state.nick_or_star()), text, &mut lines);
+        }
+        ServerMessage::Support { text } if state.renaming_to.is_some() => {
+            let nick = state.renaming_to.take().unwrap_or_default();
+            lines.push(numeric(state, "433", &format!("{} :{}", nick, clean(text))));
+        }
+        ServerMessage::Support { text } => {
+            push_text(&format!(":{} NOTICE {} :", SERVER_NAME, state.nick_or_star()), text, &mut lines);
+        }
+        ServerMessage::Goodbye { reason } => {
+            lines.push(format!("ERROR :Closing Link: {}", clean(reason)));
+        }
+        ServerMessage::Common { common: CommonMessage::Ping } => {
+            lines.push(format!("PING :{}", SERVER_NAME));
+        }
+        ServerMessage::Common { common: CommonMessage::Pong } => {
+            lines.push(format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, &state.ping_token));
+        }
+        ServerMessage::ResumeToken { .. } => {
+            // There's no way to resume
+            // an IRC session anyway
+        }
+        ServerMessage::Limits { .. } => {
+            // The reader cuts the
+            // texts as needed
+        }
+        // The room events only make sense
+        // after joining the channel
+        _ if !state.is_joined => {}
+        ServerMessage::Text { text, name, .. } => {
+            // IRC clients show their
+            // own messages themselves
+            if *name != state.name {
+                push_text(&format!("{} PRIVMSG {} :", prefix_of(name), &state.channel), text, &mut lines);
+            }
+        }
+        ServerMessage::NewUser { name, .. } => {
+            lines.push(format!("{} JOIN {}", prefix_of(name), &state.channel));
+        }
+        ServerMessage::Interrupt { name, .. } => {
+            lines.push(format!("{} QUIT :Connection lost", prefix_of(name)));
+        }
+        ServerMessage::UserLeaves { name, .. } => {
+            lines.push(format!("{} QUIT :Leaving", prefix_of(name)));
+        }
+        ServerMessage::UserKicked { name, by } => {
+            lines.push(format!("{} KICK {} {} :Kicked", prefix_of(by), &state.channel, to_nick(name)));
+        }
+        other => {
+            push_text(&format!(":{} NOTICE {} :", SERVER_NAME, &state.channel), &other.to_string(), &mut lines);
+        }
+    }
+
+    lines
+}
+
+impl<W: Write> WriteMessage<ServerMessage> for IrcWriter<W> {
+    fn write_message(&mut self, message: &ServerMessage) -> Result<()> {
+        let lines = translate(&mut *self.state.write()?, message);
+
+        if lines.is_empty() {
+            return Ok(())
+        }
+
+        let mut buffer = String::new();
+
+        for it in &lines {
+            buffer.push_str(it);
+            buffer.push_str("\r\n");
+        }
+
+        self.stream.write_all(buffer.as_bytes())?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+pub fn build_irc_codec(
+    reading_stream: Shared<BoxedTransport>,
+    writing_stream: Shared<BoxedTransport>,
+    state: &ServerState,
+) -> Result<(ClientReader, ClientWriter, SharedLimits)> {
+    let address = reading_stream.read()?.peer()?;
+
+    let (channel, limits, deadlines) = {
+        let config = state.config.read()?;
+        (config.irc_channel.clone(), config.limits(WireFormat::default()), config.read_deadlines())
+    };
+
+    let irc = IrcState::new(&address, &channel).to_shared();
+
+    let reader = IrcReader::new(reading_stream, writing_stream.clone(), irc.clone(), limits, deadlines);
+    let writer = IrcWriter::new(writing_stream, irc);
+
+    Ok((Box::new(reader), Box::new(writer), SharedLimits::new(limits)))
+}
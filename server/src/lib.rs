@@ -1,45 +1,85 @@
 mod connection;
+mod accounts;
+mod sessions;
+mod config;
+mod limits;
+mod moderation;
+mod console;
+mod irc;
+mod websocket;
+mod plain;
+mod history;
+mod transfers;
+mod api;
+mod http;
+mod metrics;
+mod streams;
+mod certificates;
 
 use std::thread;
 
-use std::net::{TcpListener, TcpStream};
+use std::net::{TcpListener};
 use std::collections::{HashMap};
-use std::path::{Path};
 use std::fs::{File};
+use std::time::{Duration};
 
 use shared::shared::{IntoShared};
+use shared::transport::{BoxedTransport, Listener, split};
+use shared::transport::tls::{TlsListener, load_server_config, certificate_fingerprint};
 use shared::communication::{DEFAULT_PORT};
-use shared::{Result, with_error_report, ErrorKind};
+use shared::{Result, with_error_report, is_would_block_error, is_size_exceeded_error, ErrorKind};
 
 use shared::communication::{
     explain_common_error,
     MessageProcessing,
+    WriteMessage,
 };
 
 use connection::{
     ServerSession,
     NamesMap,
     Clients,
+    ServerState,
     build_connection,
+    build_native_codec,
+    explain_bad_file_name,
+    remove_client,
+    CodecBuilder,
     RenameResult,
 };
 
+use accounts::{AccountsStorage};
+use sessions::{SessionsStorage, SuspendedSession};
+use config::{CONFIG_FILE};
+
+pub use config::{ServerConfig, RateLimit};
+use limits::{RateLimiter};
+use moderation::{ModerationStorage, Ban, ip_of};
+use history::{HistoryStorage};
+use transfers::{TransfersStorage};
+use metrics::{MetricsStorage};
+use irc::{build_irc_codec};
+use websocket::{build_websocket_codec};
+use plain::{build_plain_codec};
+
 use shared::connection::messages::{
     CommonMessage,
     ServerMessage,
     ClientMessage,
-};
-
-use shared::connection::messages::{
-    MAXIMUM_TEXT_SIZE,
-    MAXIMUM_NAME_SIZE,
-    MAXIMUM_FILE_NAME_SIZE,
+    BanTarget,
 };
 
 use shared::connection::helpers::{
-    send_file_non_blocking,
+    send_chunk,
 };
 
+use shared::connection::sharers::{FileSharer};
+
+use shared::connection::{Connection};
+use shared::connection::heartbeat::{Heartbeat};
+use shared::connection::fragments::{Assembler};
+use shared::connection::limits::{field_size};
+
 fn broadcast_interupt(
     connection: &mut impl ServerSession
 ) -> Result<MessageProcessing> {
@@ -55,17 +95,69 @@ fn broadcast_interupt(
     Ok(MessageProcessing::Stop)
 }
 
+fn expire_session(
+    connection: &mut impl ServerSession,
+    token: &str,
+) -> Result<()> {
+    let grace_period = connection.config()?.resume_grace_period_seconds;
+    thread::sleep(Duration::from_secs(grace_period));
+
+    let session = if let Some(it) = connection.expire(token)? {
+        it
+    } else {
+        // Resumed in time
+        return Ok(())
+    };
+
+    let time = chrono::Utc::now();
+    log::info!("Session Expired > {}", &session.name);
+
+    let response = ServerMessage::Interrupt {
+        name: session.name,
+        time: time.into()
+    };
+
+    connection.broadcast(&response)
+}
+
+fn suspend_session(
+    connection: &mut (impl ServerSession + 'static)
+) -> Result<MessageProcessing> {
+    let address = connection.remote_address()?;
+
+    // Someone has already dealt with
+    // this client, e.g. an operator
+    // has kicked it out
+    if !connection.clients()?.contains_key(&address)? {
+        return Ok(MessageProcessing::Stop)
+    }
+
+    let token = if let Some(it) = connection.suspend()? {
+        it
+    } else {
+        connection.remove_from_clients()?;
+        return broadcast_interupt(connection);
+    };
+
+    let mut the_connection = connection.clone();
+
+    thread::spawn(move || {
+        with_error_report(|| expire_session(&mut the_connection, &token))
+    });
+
+    Ok(MessageProcessing::Stop)
+}
+
 fn handle_upper_bound_violation(
     connection: &mut impl ServerSession,
     bounded_field_name: &str,
 ) -> Result<MessageProcessing> {
-    let time = chrono::Utc::now();
     let name = connection.name()?;
 
-    println!("<{}> Error > {} tried to sabotage the party by violating the {} size bound. Terminated.", &time, &name, bounded_field_name);
+    log::warn!("{} tried to sabotage the party by violating the {} size bound. Terminated.", &name, bounded_field_name);
 
     connection.remove_from_clients()?;
-    return broadcast_interupt(connection);
+    broadcast_interupt(connection)
 }
 
 fn handle_client_chunk(
@@ -99,7 +191,17 @@ fn handle_client_common_message(
 ) -> Result<MessageProcessing> {
     match message {
         CommonMessage::Chunk { data, id } => {
-            handle_client_chunk(connection, data, id.clone())
+            handle_client_chunk(connection, data, *id)
+        }
+        CommonMessage::Ping => {
+            connection.write_message(&CommonMessage::Pong)?;
+            Ok(MessageProcessing::Proceed)
+        }
+        CommonMessage::Pong => {
+            Ok(MessageProcessing::Proceed)
+        }
+        CommonMessage::Unknown { tag } => {
+            handle_client_unknown(connection, tag)
         }
     }
 }
@@ -107,15 +209,69 @@ fn handle_client_common_message(
 fn handle_client_text(
     connection: &mut (impl ServerSession + 'static),
     text: &str,
+) -> Result<MessageProcessing> {
+    if field_size(text) > connection.limits()?.text_size {
+        return handle_upper_bound_violation(connection, "text");
+    }
+
+    broadcast_text(connection, text)
+}
+
+fn handle_client_text_fragment(
+    connection: &mut (impl ServerSession + 'static),
+    texts: &mut Assembler,
+    text: &str,
+    id: usize,
+    index: usize,
+    last: bool,
+) -> Result<MessageProcessing> {
+    if field_size(text) > connection.limits()?.fragment_size {
+        return handle_upper_bound_violation(connection, "text fragment");
+    }
+
+    let pending = texts.is_pending(id);
+
+    let whole = match texts.push(id, index, text, last) {
+        Ok(Some(it)) => it,
+        Ok(None) => return Ok(MessageProcessing::Proceed),
+        Err(error) if is_size_exceeded_error(&error) => {
+            return handle_upper_bound_violation(connection, "long text");
+        }
+        // Lost pieces, e.g. after a flood
+        // rejection, the text is dropped,
+        // and the rest of it is ignored
+        // without a word
+        Err(error) => {
+            if pending {
+                log::warn!("{} > {}", connection.name()?, error);
+            }
+
+            return Ok(MessageProcessing::Proceed)
+        }
+    };
+
+    // The others get it as a single
+    // text, cut the way they need
+    broadcast_text(connection, &whole)
+}
+
+fn broadcast_text(
+    connection: &mut (impl ServerSession + 'static),
+    text: &str,
 ) -> Result<MessageProcessing> {
     let time = chrono::Utc::now();
     let name = connection.name()?;
 
-    if text.len() > MAXIMUM_TEXT_SIZE {
-        return handle_upper_bound_violation(connection, "text");
+    if connection.moderation()?.read()?.is_muted(&name) {
+        let message = ServerMessage::Support {
+            text: "You're muted, nobody hears you".to_owned(),
+        };
+
+        connection.write_message(&message)?;
+        return Ok(MessageProcessing::Proceed)
     }
 
-    println!("<{}> Message > {} > {}", &time, &name, text);
+    log::info!("Message > {} > {}", &name, text);
 
     let response = ServerMessage::Text {
         name: name,
@@ -134,7 +290,7 @@ fn handle_client_leave(
     let name = connection.name()?;
 
     connection.remove_from_clients()?;
-    println!("<{}> User Leaves > {}", &time, &name);
+    log::info!("User Leaves > {}", &name);
 
     let response = ServerMessage::UserLeaves {
         name: name,
@@ -149,11 +305,73 @@ fn handle_client_rename(
     connection: &mut (impl ServerSession + 'static),
     new_name: &str,
 ) -> Result<MessageProcessing> {
-    if new_name.len() > MAXIMUM_NAME_SIZE {
+    if field_size(new_name) > connection.limits()?.name_size {
         return handle_upper_bound_violation(connection, "name");
     }
 
-    match connection.rename(new_name)? {
+    let result = connection.rename(new_name)?;
+    respond_with_rename_result(connection, result)
+}
+
+// Guests are called by their addresses
+fn online_names(names: &NamesMap, clients: &Clients) -> Result<Vec<String>> {
+    let mut online: Vec<String> = clients.read()?
+        .keys()
+        .map(|address| match names.get_clone(address) {
+            Ok(Some(it)) => it,
+            _ => address.clone(),
+        })
+        .collect();
+
+    online.sort();
+    Ok(online)
+}
+
+fn handle_client_list_users(
+    connection: &mut (impl ServerSession + 'static),
+) -> Result<MessageProcessing> {
+    let online = online_names(&connection.names()?, &connection.clients()?)?;
+
+    let limits = connection.limits()?;
+    let mut batch = vec![];
+
+    for it in online {
+        // Measured as a whole, since the
+        // overhead depends on the format
+        let mut names = batch.clone();
+        names.push(it.clone());
+
+        let would_be = ServerMessage::UserList {
+            names: names,
+            last: false,
+        };
+
+        if !limits.fits(&would_be) && !batch.is_empty() {
+            let message = ServerMessage::UserList {
+                names: std::mem::take(&mut batch),
+                last: false,
+            };
+
+            connection.write_message(&message)?;
+        }
+
+        batch.push(it);
+    }
+
+    let message = ServerMessage::UserList {
+        names: batch,
+        last: true,
+    };
+
+    connection.write_message(&message)?;
+    Ok(MessageProcessing::Proceed)
+}
+
+fn respond_with_rename_result(
+    connection: &mut impl ServerSession,
+    result: RenameResult,
+) -> Result<MessageProcessing> {
+    match result {
         RenameResult::Success { old_name, new_name } => {
             let message = ServerMessage::UserRenamed { old_name, new_name };
             connection.write_message(&message)?;
@@ -167,23 +385,64 @@ fn handle_client_rename(
     Ok(MessageProcessing::Proceed)
 }
 
+fn handle_client_register(
+    connection: &mut (impl ServerSession + 'static),
+    name: &str,
+    password: &str,
+) -> Result<MessageProcessing> {
+    if field_size(name) > connection.limits()?.name_size {
+        return handle_upper_bound_violation(connection, "name");
+    }
+
+    if field_size(password) > connection.limits()?.password_size {
+        return handle_upper_bound_violation(connection, "password");
+    }
+
+    let result = connection.register(name, password)?;
+    respond_with_rename_result(connection, result)
+}
+
+fn handle_client_authenticate(
+    connection: &mut (impl ServerSession + 'static),
+    name: &str,
+    password: &str,
+) -> Result<MessageProcessing> {
+    if field_size(name) > connection.limits()?.name_size {
+        return handle_upper_bound_violation(connection, "name");
+    }
+
+    if field_size(password) > connection.limits()?.password_size {
+        return handle_upper_bound_violation(connection, "password");
+    }
+
+    let result = connection.authenticate(name, password)?;
+    respond_with_rename_result(connection, result)
+}
+
 fn handle_client_request_file_upload(
     connection: &mut (impl ServerSession + 'static),
     name: &str,
     size: usize,
     id: usize,
 ) -> Result<MessageProcessing> {
-    if name.len() > MAXIMUM_FILE_NAME_SIZE {
+    if field_size(name) > connection.limits()?.file_name_size {
         return handle_upper_bound_violation(connection, "file name");
     }
 
-    let response = if Path::new(name).exists() {
+    let path = connection.config()?.upload_path(name);
+
+    let response = if let Some(reason) = explain_bad_file_name(name) {
+        ServerMessage::DeclineFileUpload {
+            id: id,
+            reason: reason.to_owned(),
+        }
+    } else if path.exists() {
         ServerMessage::DeclineFileUpload {
             id: id,
             reason: "There's already a file with such a name".to_owned(),
         }
     } else {
-        connection.prepare_sharer(name, File::create(name)?, name)?;
+        connection.prepare_sharer(&path.to_string_lossy(), File::create(&path)?, name)?;
         connection.promote_sharer(name, size, id)?;
 
         ServerMessage::AgreeFileUpload {
@@ -199,11 +458,18 @@ fn handle_client_request_file_download(
     connection: &mut (impl ServerSession + 'static),
     name: &str,
 ) -> Result<MessageProcessing> {
-    if name.len() > MAXIMUM_FILE_NAME_SIZE {
+    if field_size(name) > connection.limits()?.file_name_size {
         return handle_upper_bound_violation(connection, "file name");
     }
 
-    let response = if !Path::new(name).exists() {
+    let path = connection.config()?.upload_path(name);
+
+    let response = if let Some(reason) = explain_bad_file_name(name) {
+        ServerMessage::DeclineFileDownload {
+            name: name.to_owned(),
+            reason: reason.to_owned(),
+        }
+    } else if !path.is_file() {
         ServerMessage::DeclineFileDownload {
             name: name.to_owned(),
             reason: "There's no such a file".to_owned(),
@@ -211,12 +477,15 @@ fn handle_client_request_file_download(
     } else {
         let id = connection.free_id()?;
 
-        let file = File::open(name)?;
+        let file = File::open(&path)?;
         let size = file.metadata()?.len() as usize;
 
-        connection.prepare_sharer(name, file, name)?;
+        connection.prepare_sharer(&path.to_string_lossy(), file, name)?;
         connection.promote_sharer(name, size, id)?;
 
+        let address = connection.remote_address()?;
+        connection.state()?.transfers.write()?.offer(&address, name, id, size);
+
         ServerMessage::AgreeFileDownload {
             name: name.to_owned(),
             id: id,
@@ -238,10 +507,40 @@ fn handle_client_agree_file_download(
         return Ok(MessageProcessing::Proceed)
     };
 
-    send_file_non_blocking(connection, sharer)?;
+    send_download(connection, sharer)?;
     Ok(MessageProcessing::Proceed)
 }
 
+// Goes aside, so that the client can keep
+// chatting, the console sees the progress
+fn send_download(
+    connection: &mut (impl ServerSession + 'static),
+    mut sharer: FileSharer,
+) -> Result<()> {
+    let chunk_size = connection.limits()?.chunk_size;
+    let address = connection.remote_address()?;
+    let transfers = connection.state()?.transfers;
+    let mut the_connection = connection.clone();
+
+    transfers.write()?.progress(&address, sharer.id, 0);
+
+    thread::spawn(move || {
+        with_error_report(|| {
+            let mut result = Ok(());
+
+            while sharer.rest() > 0 && result.is_ok() {
+                result = send_chunk(&mut the_connection, &mut sharer, chunk_size);
+                transfers.write()?.progress(&address, sharer.id, sharer.written);
+            }
+
+            transfers.write()?.finish(&address, sharer.id);
+            result
+        })
+    });
+
+    Ok(())
+}
+
 fn handle_client_decline_file_download(
     connection: &mut (impl ServerSession + 'static),
     id: usize,
@@ -249,72 +548,524 @@ fn handle_client_decline_file_download(
     // Well, they asked for the file, but
     // now they say they can't accept the size.
     connection.remove_sharer(id)?;
+
+    let address = connection.remote_address()?;
+    connection.state()?.transfers.write()?.finish(&address, id);
+    Ok(MessageProcessing::Proceed)
+}
+
+fn is_operator(
+    connection: &impl ServerSession,
+) -> Result<bool> {
+    let address = connection.remote_address()?;
+
+    let account = match connection.accounts()?.read()?.account_of(&address) {
+        Some(it) => it.clone(),
+        None => return Ok(false),
+    };
+
+    if connection.moderation()?.read()?.is_promoted(&account) {
+        return Ok(true)
+    }
+
+    Ok(connection.config()?.operators.contains(&account))
+}
+
+fn check_operator(
+    connection: &mut impl ServerSession,
+) -> Result<bool> {
+    if is_operator(connection)? {
+        return Ok(true)
+    }
+
+    let message = ServerMessage::Support {
+        text: "Only operators can do that".to_owned(),
+    };
+
+    connection.write_message(&message)?;
+    Ok(false)
+}
+
+// Returns the address of
+// the online user with this name
+fn find_online(
+    names: &NamesMap,
+    clients: &Clients,
+    name: &str,
+) -> Result<Option<String>> {
+    let named = names.read()?
+        .iter()
+        .find(|(_, it)| *it == name)
+        .map(|(address, _)| address.clone());
+
+    // Guests are called by their addresses
+    let address = match named {
+        Some(it) => it,
+        None => name.to_owned(),
+    };
+
+    if clients.contains_key(&address)? {
+        Ok(Some(address))
+    } else {
+        Ok(None)
+    }
+}
+
+fn report_missing_user(
+    connection: &mut impl ServerSession,
+    name: &str,
+) -> Result<MessageProcessing> {
+    let message = ServerMessage::Support {
+        text: format!("There's no {} here", name),
+    };
+
+    connection.write_message(&message)?;
+    Ok(MessageProcessing::Proceed)
+}
+
+// Tells the user what happened and
+// closes the connection for good
+fn throw_out(
+    connection: &mut impl ServerSession,
+    address: &str,
+    reason: String,
+) -> Result<()> {
+    let target = connection.clients()?.get_clone(address)?;
+    remove_client(connection, address)?;
+
+    if let Some(mut it) = target {
+        // It may have already gone
+        let _ = it.write_message(&ServerMessage::Goodbye { reason: reason });
+        it.close()?;
+    }
+
+    Ok(())
+}
+
+fn handle_client_promote(
+    connection: &mut (impl ServerSession + 'static),
+    name: &str,
+) -> Result<MessageProcessing> {
+    if field_size(name) > connection.limits()?.name_size {
+        return handle_upper_bound_violation(connection, "name");
+    }
+
+    if !check_operator(connection)? {
+        return Ok(MessageProcessing::Proceed)
+    }
+
+    let address = match find_online(&connection.names()?, &connection.clients()?, name)? {
+        Some(it) => it,
+        None => return report_missing_user(connection, name),
+    };
+
+    // Guests' names can be taken by anyone
+    // later, the accounts are for good
+    let account = match connection.accounts()?.read()?.account_of(&address) {
+        Some(it) => it.clone(),
+        None => {
+            let message = ServerMessage::Support {
+                text: format!("{} has to log in first", name),
+            };
+
+            connection.write_message(&message)?;
+            return Ok(MessageProcessing::Proceed)
+        }
+    };
+
+    connection.moderation()?.write()?.promote(&account)?;
+
+    let by = connection.name()?;
+    log::info!("Promote > {} > {}", &by, name);
+
+    let event = ServerMessage::UserPromoted {
+        name: name.to_owned(),
+        by: by,
+    };
+
+    connection.broadcast(&event)?;
+    Ok(MessageProcessing::Proceed)
+}
+
+fn handle_client_kick(
+    connection: &mut (impl ServerSession + 'static),
+    name: &str,
+) -> Result<MessageProcessing> {
+    if field_size(name) > connection.limits()?.name_size {
+        return handle_upper_bound_violation(connection, "name");
+    }
+
+    if !check_operator(connection)? {
+        return Ok(MessageProcessing::Proceed)
+    }
+
+    let address = match find_online(&connection.names()?, &connection.clients()?, name)? {
+        Some(it) => it,
+        None => return report_missing_user(connection, name),
+    };
+
+    let by = connection.name()?;
+    log::info!("Kick > {} > {}", &by, name);
+
+    throw_out(connection, &address, format!("{} has kicked you out", &by))?;
+
+    let event = ServerMessage::UserKicked {
+        name: name.to_owned(),
+        by: by,
+    };
+
+    connection.broadcast(&event)?;
+    Ok(MessageProcessing::Proceed)
+}
+
+// Turns user names into what
+// the ban actually applies to
+fn resolve_ban_target(
+    connection: &impl ServerSession,
+    target: &BanTarget,
+) -> Result<Option<BanTarget>> {
+    let resolved = match target {
+        BanTarget::Name(name) => {
+            Some(BanTarget::Name(name.clone()))
+        }
+        // The one the client resumes with, so
+        // that it can't come back the same way
+        BanTarget::Session(it) => {
+            let sessions = connection.sessions()?;
+
+            let token = match find_online(&connection.names()?, &connection.clients()?, it)? {
+                Some(address) => sessions.read()?.token_at(&address),
+                None => sessions.read()?.suspended_token(it),
+            };
+
+            token.map(BanTarget::Session)
+        }
+        BanTarget::Ip(it) => match find_online(&connection.names()?, &connection.clients()?, it)? {
+            Some(address) => ip_of(&address).map(BanTarget::Ip),
+            None => {
+                it.parse::<std::net::IpAddr>().ok().map(|_| BanTarget::Ip(it.clone()))
+            }
+        }
+    };
+
+    Ok(resolved)
+}
+
+fn handle_client_ban(
+    connection: &mut (impl ServerSession + 'static),
+    target: &BanTarget,
+    duration_seconds: Option<u64>,
+) -> Result<MessageProcessing> {
+    let value = match target {
+        BanTarget::Name(it) | BanTarget::Session(it) | BanTarget::Ip(it) => it,
+    };
+
+    if field_size(value) > connection.limits()?.name_size {
+        return handle_upper_bound_violation(connection, "ban target");
+    }
+
+    if !check_operator(connection)? {
+        return Ok(MessageProcessing::Proceed)
+    }
+
+    let resolved = match resolve_ban_target(connection, target)? {
+        Some(it) => it,
+        None => return report_missing_user(connection, value),
+    };
+
+    let time = chrono::Utc::now();
+    let by = connection.name()?;
+
+    let ban = Ban {
+        target: resolved.clone(),
+        until: duration_seconds.map(|it| time.timestamp().saturating_add(it as i64)),
+    };
+
+    // The tokens are secret, the
+    // others only see who it was
+    let shown = match resolved {
+        BanTarget::Session(_) => target.clone(),
+        other => other,
+    };
+
+    log::info!("Ban > {} > {}", &by, &shown);
+
+    let reason = format!("{} has banned you", &by);
+
+    let event = ServerMessage::UserBanned {
+        target: shown,
+        by: by,
+        until: ban.until.map(|it| bson::DateTime::from_millis(it.saturating_mul(1000))),
+    };
+
+    let names = connection.names()?;
+    let sessions = connection.sessions()?;
+
+    let affected: Vec<String> = connection.clients()?.read()?
+        .keys()
+        .filter(|address| {
+            let name = match names.get_clone(*address) {
+                Ok(Some(it)) => it,
+                _ => address.to_string(),
+            };
+
+            let token = match sessions.read() {
+                Ok(it) => it.token_at(address),
+                Err(_) => None,
+            };
+
+            ban.affects(address, &name, token.as_deref())
+        })
+        .cloned()
+        .collect();
+
+    connection.moderation()?.write()?.ban(ban)?;
+
+    for address in &affected {
+        throw_out(connection, address, reason.clone())?;
+    }
+
+    connection.broadcast(&event)?;
+    Ok(MessageProcessing::Proceed)
+}
+
+fn handle_client_mute(
+    connection: &mut (impl ServerSession + 'static),
+    name: &str,
+    mute: bool,
+) -> Result<MessageProcessing> {
+    if field_size(name) > connection.limits()?.name_size {
+        return handle_upper_bound_violation(connection, "name");
+    }
+
+    if !check_operator(connection)? {
+        return Ok(MessageProcessing::Proceed)
+    }
+
+    let address = match find_online(&connection.names()?, &connection.clients()?, name)? {
+        Some(it) => it,
+        None => return report_missing_user(connection, name),
+    };
+
+    // Guests go by their addresses
+    let muted = connection.names()?.get_clone(&address)?.unwrap_or(address);
+    let by = connection.name()?;
+
+    let event = if mute {
+        connection.moderation()?.write()?.mute(&muted)?;
+        log::info!("Mute > {} > {}", &by, name);
+        ServerMessage::UserMuted { name: name.to_owned(), by: by }
+    } else if connection.moderation()?.write()?.unmute(&muted)? {
+        log::info!("Unmute > {} > {}", &by, name);
+        ServerMessage::UserUnmuted { name: name.to_owned(), by: by }
+    } else {
+        let message = ServerMessage::Support {
+            text: format!("{} isn't muted", name),
+        };
+
+        connection.write_message(&message)?;
+        return Ok(MessageProcessing::Proceed)
+    };
+
+    connection.broadcast(&event)?;
+    Ok(MessageProcessing::Proceed)
+}
+
+// A newer client, most likely, so there's
+// no reason to drop it
+fn handle_client_unknown(
+    connection: &mut (impl ServerSession + 'static),
+    tag: &str,
+) -> Result<MessageProcessing> {
+    log::warn!("{} > Unknown message {}, skipped", connection.name()?, tag);
     Ok(MessageProcessing::Proceed)
 }
 
 fn handle_client_message(
     connection: &mut (impl ServerSession + 'static),
     message: &ClientMessage,
+    texts: &mut Assembler,
 ) -> Result<MessageProcessing> {
     match message {
+        ClientMessage::Join | ClientMessage::Resume { .. } => {
+            // Already joined during the handshake
+            Ok(MessageProcessing::Proceed)
+        }
+        ClientMessage::Authenticate { name, password } => {
+            handle_client_authenticate(connection, name, password)
+        }
+        ClientMessage::Register { name, password } => {
+            handle_client_register(connection, name, password)
+        }
         ClientMessage::Common { common } => {
-            handle_client_common_message(connection, &common)
+            handle_client_common_message(connection, common)
         }
         ClientMessage::Text { text } => {
-            handle_client_text(connection, &text)
+            handle_client_text(connection, text)
+        }
+        ClientMessage::TextFragment { text, id, index, last } => {
+            handle_client_text_fragment(connection, texts, text, *id, *index, *last)
         }
         ClientMessage::Leave => {
             handle_client_leave(connection)
         }
         ClientMessage::Rename { new_name } => {
-            handle_client_rename(connection, &new_name)
+            handle_client_rename(connection, new_name)
+        }
+        ClientMessage::ListUsers => {
+            handle_client_list_users(connection)
+        }
+        ClientMessage::Promote { name } => {
+            handle_client_promote(connection, name)
+        }
+        ClientMessage::Kick { name } => {
+            handle_client_kick(connection, name)
+        }
+        ClientMessage::Ban { target, duration_seconds } => {
+            handle_client_ban(connection, target, *duration_seconds)
+        }
+        ClientMessage::Mute { name } => {
+            handle_client_mute(connection, name, true)
+        }
+        ClientMessage::Unmute { name } => {
+            handle_client_mute(connection, name, false)
         }
         ClientMessage::RequestFileUpload { name, size, id } => {
-            handle_client_request_file_upload(connection, &name, size.clone(), id.clone())
+            handle_client_request_file_upload(connection, name, *size, *id)
         }
         ClientMessage::RequestFileDownload { name } => {
-            handle_client_request_file_download(connection, &name)
+            handle_client_request_file_download(connection, name)
         }
         ClientMessage::AgreeFileDownload { id } => {
-            handle_client_agree_file_download(connection, id.clone())
+            handle_client_agree_file_download(connection, *id)
         }
         ClientMessage::DeclineFileDownload { id } => {
-            handle_client_decline_file_download(connection, id.clone())
+            handle_client_decline_file_download(connection, *id)
         }
+        ClientMessage::Unknown { tag, .. } => {
+            handle_client_unknown(connection, tag)
+        }
+    }
+}
+
+fn check_heartbeat(
+    connection: &mut (impl ServerSession + 'static),
+    heartbeat: &mut Heartbeat,
+) -> Result<MessageProcessing> {
+    if heartbeat.is_dead() {
+        let name = connection.name()?;
+
+        log::warn!("{} > Stopped answering", &name);
+        return suspend_session(connection);
+    }
+
+    if heartbeat.should_ping() {
+        connection.write_message(&CommonMessage::Ping)?;
+    }
+
+    Ok(MessageProcessing::Proceed)
+}
+
+fn handle_flood(
+    connection: &mut (impl ServerSession + 'static),
+    message: &ClientMessage,
+    limiter: &mut RateLimiter,
+) -> Result<MessageProcessing> {
+    let name = connection.name()?;
+
+    if !limiter.tolerate() {
+        log::warn!("{} kept flooding the chat. Terminated.", &name);
+        connection.remove_from_clients()?;
+        return broadcast_interupt(connection)
     }
+
+    log::warn!("Flood > {} > Message rejected", &name);
+
+    let reason = "Slow down, you're sending too much".to_owned();
+
+    // Transfer requests expect a definite
+    // answer, otherwise the client would
+    // wait for it forever
+    let response = match message {
+        ClientMessage::RequestFileUpload { id, .. } => {
+            ServerMessage::DeclineFileUpload { id: *id, reason: reason }
+        }
+        ClientMessage::RequestFileDownload { name } => {
+            ServerMessage::DeclineFileDownload { name: name.clone(), reason: reason }
+        }
+        _ => {
+            ServerMessage::Support { text: reason }
+        }
+    };
+
+    connection.write_message(&response)?;
+    Ok(MessageProcessing::Proceed)
 }
 
 fn read_and_handle_client_message(
-    connection: &mut (impl ServerSession + 'static)
+    connection: &mut (impl ServerSession + 'static),
+    heartbeat: &mut Heartbeat,
+    limiter: &mut RateLimiter,
+    texts: &mut Assembler,
 ) -> Result<MessageProcessing> {
-    let time = chrono::Utc::now();
     let name = connection.name()?;
 
     let message = match connection.read_message() {
         Ok(it) => it,
         Err(error) => {
-            let explaination = explain_common_error(&error);
-            println!("<{}> Error > {} > {}", &time, &name, &explaination);
+            // The read timeout has elapsed
+            if is_would_block_error(&error) {
+                return check_heartbeat(connection, heartbeat);
+            }
 
-            if let ErrorKind::NothingToRead = error.kind {
-                return broadcast_interupt(connection);
+            let explaination = explain_common_error(&error);
+            log::warn!("{} > {}", &name, &explaination);
+
+            match error.kind {
+                ErrorKind::NothingToRead => {
+                    return suspend_session(connection)
+                }
+                // Someone keeps the connection busy
+                // without actually saying anything
+                ErrorKind::ReadTimeout { .. } => {
+                    connection.remove_from_clients()?;
+                    return broadcast_interupt(connection)
+                }
+                _ => {}
             }
 
             return Err(error)
         }
     };
 
-    handle_client_message(connection, &message)
+    heartbeat.heard();
+
+    if !limiter.allow(&message) {
+        return handle_flood(connection, &message, limiter)
+    }
+
+    handle_client_message(connection, &message, texts)
 }
 
 fn handle_client_messages(
     mut connection: impl ServerSession + 'static
 ) -> Result<()> {
+    let config = connection.config()?;
+
+    let mut heartbeat = Heartbeat::new(
+        Duration::from_secs(config.heartbeat_interval_seconds),
+        Duration::from_secs(config.heartbeat_timeout_seconds),
+    );
+
+    let mut limiter = RateLimiter::new(&config);
+
+    let limits = connection.limits()?;
+    let mut texts = Assembler::new(limits.long_text_size, limits.text_fragments);
+
     loop {
-        let result = read_and_handle_client_message(&mut connection)?;
+        let result = read_and_handle_client_message(&mut connection, &mut heartbeat, &mut limiter, &mut texts)?;
 
         if let MessageProcessing::Stop = &result {
-            connection.remove_from_clients()?;
             break
         }
     }
@@ -333,13 +1084,85 @@ fn setup_names_mapping() -> NamesMap {
     names.to_shared()
 }
 
+enum Handshake {
+    Joined,
+    Resumed(SuspendedSession),
+    // The session has been banned
+    Refused,
+}
+
+fn accept_resume(
+    reading_connection: &mut impl ServerSession,
+    token: &str,
+) -> Result<Handshake> {
+    if reading_connection.moderation()?.read()?.is_session_banned(token) {
+        log::info!("Refused > {} > Banned session", reading_connection.remote_address()?);
+
+        let message = ServerMessage::Goodbye {
+            reason: "You're banned here".to_owned(),
+        };
+
+        reading_connection.write_message(&message)?;
+        return Ok(Handshake::Refused)
+    }
+
+    let session = match reading_connection.resume(token)? {
+        Some(it) => it,
+        None => {
+            let message = ServerMessage::Support {
+                text: "Your previous session has expired, joining anew".to_owned(),
+            };
+
+            reading_connection.write_message(&message)?;
+            return Ok(Handshake::Joined)
+        }
+    };
+
+    Ok(Handshake::Resumed(session))
+}
+
+fn accept_handshake(
+    reading_connection: &mut impl ServerSession,
+) -> Result<Handshake> {
+    let (name, password) = match reading_connection.read_message()? {
+        ClientMessage::Join => return Ok(Handshake::Joined),
+        ClientMessage::Resume { token } => return accept_resume(reading_connection, &token),
+        ClientMessage::Authenticate { name, password } => (name, password),
+        other => {
+            let kind = ErrorKind::MalformedMessage {
+                message: format!("Expected a handshake, but got {:?}", other)
+            };
+
+            return Err(kind.into())
+        }
+    };
+
+    let limits = reading_connection.limits()?;
+
+    if field_size(&name) > limits.name_size || field_size(&password) > limits.password_size {
+        return Err(ErrorKind::MessageSizeExceeded.into())
+    }
+
+    let text = match reading_connection.authenticate(&name, &password)? {
+        RenameResult::Success { new_name, .. } => {
+            format!("You're logged in as {}", new_name)
+        }
+        RenameResult::Failure { reason } => {
+            format!("{}. You're a guest for now", reason)
+        }
+    };
+
+    reading_connection.write_message(&ServerMessage::Support { text })?;
+    Ok(Handshake::Joined)
+}
+
 fn greet_user(
     writing_connection: &mut impl ServerSession,
 ) -> Result<String> {
     let time = chrono::Utc::now();
     let name = writing_connection.name()?;
 
-    println!("<{}> New User > {}", &time, &name);
+    log::info!("New User > {}", &name);
 
     let broadcast_greeting = ServerMessage::NewUser {
         name: name,
@@ -353,45 +1176,297 @@ fn greet_user(
     };
 
     writing_connection.write_message(&personal_greeting)?;
-    Ok(writing_connection.remote_address()?.to_string())
+
+    let token = ServerMessage::ResumeToken {
+        token: writing_connection.issue_token()?,
+    };
+
+    writing_connection.write_message(&token)?;
+    writing_connection.remote_address()
+}
+
+fn welcome_back(
+    writing_connection: &mut impl ServerSession,
+    session: SuspendedSession,
+) -> Result<String> {
+    log::info!("User Resumes > {}", &session.name);
+
+    let personal_greeting = ServerMessage::Support {
+        text: "Welcome back, mate".to_owned(),
+    };
+
+    writing_connection.write_message(&personal_greeting)?;
+
+    for it in &session.pending {
+        writing_connection.write_message(it)?;
+    }
+
+    writing_connection.remote_address()
 }
 
 fn handle_client(
-    stream: TcpStream,
-    names: NamesMap,
-    clients: Clients,
+    transport: BoxedTransport,
+    state: ServerState,
+    build_codec: CodecBuilder,
 ) -> Result<()> {
+    let the_config = state.config.read()?.clone();
+
+    // Interrupts reading once in a while to
+    // check if the client is still there
+    transport.set_read_timeout(Some(Duration::from_secs(the_config.heartbeat_interval_seconds)))?;
+    transport.set_write_timeout(Some(Duration::from_secs(the_config.heartbeat_timeout_seconds)))?;
+
     let (
-        reading_connection,
+        mut reading_connection,
         mut writing_connection
-    ) = build_connection(
-        stream,
-        names,
-        clients.clone(),
-    )?;
+    ) = build_connection(transport, state.clone(), build_codec)?;
+
+    let session = match accept_handshake(&mut reading_connection)? {
+        Handshake::Joined => None,
+        Handshake::Resumed(it) => Some(it),
+        Handshake::Refused => return Ok(()),
+    };
+
+    // Before anything else, so that the
+    // client knows what it may send
+    let limits = ServerMessage::Limits {
+        limits: writing_connection.limits()?,
+    };
 
-    let address = greet_user(&mut writing_connection)?;
-    clients.insert(address, writing_connection.to_shared())?;
+    writing_connection.write_message(&limits)?;
+
+    let address = match session {
+        Some(session) => welcome_back(&mut writing_connection, session)?,
+        None => greet_user(&mut writing_connection)?,
+    };
+
+    state.clients.insert(address, writing_connection.to_shared())?;
 
     with_error_report(|| handle_client_messages(reading_connection));
     Ok(())
 }
 
+fn is_banned(transport: &BoxedTransport, state: &ServerState) -> Result<bool> {
+    let address = transport.peer()?;
+    Ok(state.moderation.read()?.is_banned(&address, &address))
+}
+
+fn refuse_banned(
+    transport: BoxedTransport,
+    state: &ServerState,
+    build_codec: CodecBuilder,
+) -> Result<()> {
+    log::info!("Refused > {} > Banned", transport.peer()?);
+
+    let message = ServerMessage::Goodbye {
+        reason: "You're banned here".to_owned(),
+    };
+
+    let (reading_stream, writing_stream) = split(transport)?;
+    let (_, mut writer, _) = build_codec(reading_stream, writing_stream, state)?;
+    writer.write_message(&message)
+}
+
+fn listen(
+    listener: impl Listener,
+    state: ServerState,
+    build_codec: CodecBuilder,
+) -> Result<()> {
+    loop {
+        let incomming = match listener.accept() {
+            // Only happens to the in-memory
+            // listener, nobody can connect
+            // anymore
+            Err(error) if matches!(error.kind, ErrorKind::NothingToRead) => return Ok(()),
+            other => other,
+        };
+
+        let the_state = state.clone();
+
+        thread::spawn(move || {
+            with_error_report(|| {
+                let transport = incomming?;
+
+                if is_banned(&transport, &the_state)? {
+                    return refuse_banned(transport, &the_state, build_codec)
+                }
+
+                handle_client(transport, the_state, build_codec)
+            })
+        });
+    }
+}
+
+// Gateways are off unless
+// the port is given
+fn spawn_gateway(
+    port: Option<u16>,
+    state: &ServerState,
+    build_codec: CodecBuilder,
+) -> Result<()> {
+    if let Some(port) = port {
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+        let the_state = state.clone();
+
+        thread::spawn(move || {
+            with_error_report(|| listen(listener, the_state, build_codec))
+        });
+    }
+
+    Ok(())
+}
+
+// For the local tools, the clients
+// speak the native protocol there
+#[cfg(unix)]
+fn spawn_unix_socket(path: Option<String>, state: &ServerState) -> Result<()> {
+    if let Some(path) = path {
+        let listener = shared::transport::unix::UnixSocketListener::bind(&path)?;
+        let the_state = state.clone();
+
+        thread::spawn(move || {
+            with_error_report(|| listen(listener, the_state, build_native_codec))
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_unix_socket(path: Option<String>, _: &ServerState) -> Result<()> {
+    if path.is_some() {
+        log::warn!("UNIX sockets aren't supported here, ignoring unix_socket");
+    }
+
+    Ok(())
+}
+
+// Same as the main port,
+// but encrypted
+fn spawn_tls(state: &ServerState) -> Result<()> {
+    let (port, certificate, key) = {
+        let config = state.config.read()?;
+        (config.tls_port, config.tls_certificate.clone(), config.tls_key.clone())
+    };
+
+    if let Some(port) = port {
+        certificates::ensure_self_signed(&certificate, &key)?;
+
+        let tls_config = load_server_config(&certificate, &key)?;
+        log::info!("The TLS certificate fingerprint is {}", certificate_fingerprint(&certificate)?);
+
+        let listener = TlsListener::new(TcpListener::bind(format!("0.0.0.0:{}", port))?, tls_config);
+        let the_state = state.clone();
+
+        thread::spawn(move || {
+            with_error_report(|| listen(listener, the_state, build_native_codec))
+        });
+    }
+
+    Ok(())
+}
+
+fn setup_state(config: ServerConfig) -> Result<ServerState> {
+    std::fs::create_dir_all(&config.uploads_directory)?;
+    config.check_private_files()?;
+
+    Ok(ServerState {
+        names: setup_names_mapping(),
+        clients: HashMap::new().to_shared(),
+        accounts: AccountsStorage::load(&config.accounts_file)?.to_shared(),
+        sessions: SessionsStorage::new().to_shared(),
+        moderation: ModerationStorage::load(&config.bans_file)?.to_shared(),
+        history: HistoryStorage::new(config.history_size).to_shared(),
+        transfers: TransfersStorage::new().to_shared(),
+        metrics: MetricsStorage::new().to_shared(),
+        config: config.to_shared(),
+    })
+}
+
+// Only the main protocol, without the
+// console and the other ports, e.g.
+// for the tests
+pub fn serve(listener: impl Listener, config: ServerConfig) -> Result<()> {
+    let state = setup_state(config)?;
+    listen(listener, state, build_native_codec)
+}
+
+pub enum Gateway {
+    Irc,
+    WebSocket,
+    Plain,
+}
+
+// Same, with one of the gateways
+// next to it, in the same room
+pub fn serve_with_gateway(
+    listener: impl Listener,
+    gateway: Gateway,
+    gateway_listener: impl Listener + 'static,
+    config: ServerConfig,
+) -> Result<()> {
+    let state = setup_state(config)?;
+
+    let build_codec: CodecBuilder = match gateway {
+        Gateway::Irc => build_irc_codec,
+        Gateway::WebSocket => build_websocket_codec,
+        Gateway::Plain => build_plain_codec,
+    };
+
+    let the_state = state.clone();
+
+    thread::spawn(move || {
+        with_error_report(|| listen(gateway_listener, the_state, build_codec))
+    });
+
+    listen(listener, state, build_native_codec)
+}
+
 fn handle_connection() -> Result<()> {
-    let names = setup_names_mapping();
-    let clients = HashMap::new().to_shared();
+    let config = ServerConfig::load(CONFIG_FILE)?;
+    shared::logging::init(&config.logging)?;
+
+    let state = setup_state(config)?;
     let listener = TcpListener::bind(format!("0.0.0.0:{}", DEFAULT_PORT))?;
+    let console_state = state.clone();
+
+    thread::spawn(|| {
+        with_error_report(|| console::run(console_state))
+    });
+
+    let irc_port = state.config.read()?.irc_port;
+    spawn_gateway(irc_port, &state, build_irc_codec)?;
+
+    let websocket_port = state.config.read()?.websocket_port;
+    spawn_gateway(websocket_port, &state, build_websocket_codec)?;
+
+    let plain_port = state.config.read()?.plain_port;
+    spawn_gateway(plain_port, &state, build_plain_codec)?;
 
-    for incomming in listener.incoming() {
-        let the_names = names.clone();
-        let the_clients = clients.clone();
+    let unix_socket = state.config.read()?.unix_socket.clone();
+    spawn_unix_socket(unix_socket, &state)?;
+    spawn_tls(&state)?;
+
+    if let Some(port) = state.config.read()?.api_port {
+        let api_listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+        let api_state = state.clone();
 
         thread::spawn(|| {
-            with_error_report(|| handle_client(incomming?, the_names, the_clients))
+            with_error_report(|| api::listen(api_listener, api_state))
         });
     }
 
-    Ok(())
+    // Only for the local scrapers
+    if let Some(port) = state.config.read()?.metrics_port {
+        let metrics_listener = TcpListener::bind(format!("127.0.0.1:{}", port))?;
+        let metrics_state = state.clone();
+
+        thread::spawn(|| {
+            with_error_report(|| metrics::listen(metrics_listener, metrics_state))
+        });
+    }
+
+    listen(listener, state, build_native_codec)
 }
 
 pub fn start() {
@@ -0,0 +1,96 @@
+use std::time::{Instant};
+
+use shared::connection::messages::{ClientMessage};
+
+use crate::config::{ServerConfig, RateLimit};
+
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: &RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit: limit.clone(),
+            tokens: limit.burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        let tokens = self.tokens + elapsed * self.limit.per_second;
+
+        self.tokens = tokens.min(self.limit.burst);
+        self.last_refill = Instant::now();
+    }
+
+    pub fn take(&mut self) -> bool {
+        self.refill();
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct RateLimiter {
+    text: TokenBucket,
+    fragment: TokenBucket,
+    rename: TokenBucket,
+    transfer: TokenBucket,
+    // Each rejected message takes a token
+    // from here, and once it's empty we
+    // consider the client hopeless
+    tolerance: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(config: &ServerConfig) -> RateLimiter {
+        RateLimiter {
+            text: TokenBucket::new(&config.text_rate),
+            fragment: TokenBucket::new(&config.fragment_rate),
+            rename: TokenBucket::new(&config.rename_rate),
+            transfer: TokenBucket::new(&config.transfer_rate),
+            tolerance: TokenBucket::new(&config.flood_tolerance),
+        }
+    }
+
+    pub fn allow(&mut self, message: &ClientMessage) -> bool {
+        match message {
+            ClientMessage::Text { .. } |
+            ClientMessage::ListUsers => {
+                self.text.take()
+            }
+            // Starting a long text
+            // is sending a text
+            ClientMessage::TextFragment { index: 0, .. } => {
+                self.text.take() && self.fragment.take()
+            }
+            ClientMessage::TextFragment { .. } => {
+                self.fragment.take()
+            }
+            ClientMessage::Rename { .. } |
+            ClientMessage::Register { .. } |
+            ClientMessage::Authenticate { .. } => {
+                self.rename.take()
+            }
+            ClientMessage::RequestFileUpload { .. } |
+            ClientMessage::RequestFileDownload { .. } => {
+                self.transfer.take()
+            }
+            _ => true
+        }
+    }
+
+    // Returns false if the client
+    // should be disconnected
+    pub fn tolerate(&mut self) -> bool {
+        self.tolerance.take()
+    }
+}
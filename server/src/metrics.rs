@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration};
+use std::thread;
+
+use shared::{Result, ErrorKind, with_error_report, is_would_block_error};
+use shared::shared::{Shared};
+use shared::connection::{Connection};
+use shared::communication::{ReadMessage, WriteMessage};
+use shared::connection::messages::{ClientMessage, ServerMessage, CommonMessage};
+
+use crate::connection::{ServerState, ClientReader, ClientWriter};
+use crate::http::{read_request, write_response};
+
+// In seconds
+const LATENCY_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+// Everything that can't be looked
+// up in the state at any moment
+pub struct MetricsStorage {
+    received: BTreeMap<&'static str, u64>,
+    sent: BTreeMap<&'static str, u64>,
+    bytes_received: u64,
+    bytes_sent: u64,
+    decode_errors: BTreeMap<&'static str, u64>,
+    // Not cumulative, unlike
+    // the ones rendered
+    latency_buckets: Vec<u64>,
+    latency_sum: f64,
+    latency_count: u64,
+}
+
+impl MetricsStorage {
+    pub fn new() -> MetricsStorage {
+        MetricsStorage {
+            received: BTreeMap::new(),
+            sent: BTreeMap::new(),
+            bytes_received: 0,
+            bytes_sent: 0,
+            decode_errors: BTreeMap::new(),
+            latency_buckets: vec![0; LATENCY_BUCKETS.len()],
+            latency_sum: 0.0,
+            latency_count: 0,
+        }
+    }
+
+    pub fn received(&mut self, message: &ClientMessage) {
+        *self.received.entry(message.kind()).or_insert(0) += 1;
+
+        if let ClientMessage::Common { common: CommonMessage::Chunk { data, .. } } = message {
+            self.bytes_received += data.len() as u64;
+        }
+    }
+
+    pub fn sent(&mut self, message: &ServerMessage) {
+        *self.sent.entry(message.kind()).or_insert(0) += 1;
+
+        if let ServerMessage::Common { common: CommonMessage::Chunk { data, .. } } = message {
+            self.bytes_sent += data.len() as u64;
+        }
+    }
+
+    pub fn failed_to_read(&mut self, kind: &ErrorKind) {
+        *self.decode_errors.entry(kind.name()).or_insert(0) += 1;
+    }
+
+    pub fn broadcast_took(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|it| seconds <= *it) {
+            self.latency_buckets[index] += 1;
+        }
+
+        self.latency_sum += seconds;
+        self.latency_count += 1;
+    }
+}
+
+pub type Metrics = Shared<MetricsStorage>;
+
+// Counts whatever the client sends,
+// the same way for all the formats
+pub struct MeteredReader {
+    backend: ClientReader,
+    metrics: Metrics,
+}
+
+impl MeteredReader {
+    pub fn new(backend: ClientReader, metrics: Metrics) -> MeteredReader {
+        MeteredReader {
+            backend: backend,
+            metrics: metrics,
+        }
+    }
+}
+
+impl ReadMessage<ClientMessage> for MeteredReader {
+    fn read_message(&mut self) -> Result<ClientMessage> {
+        match self.backend.read_message() {
+            Ok(it) => {
+                self.metrics.write()?.received(&it);
+                Ok(it)
+            }
+            Err(error) => {
+                // Nothing to count, it's
+                // just the read timeout
+                if !is_would_block_error(&error) {
+                    self.metrics.write()?.failed_to_read(&error.kind);
+                }
+
+                Err(error)
+            }
+        }
+    }
+}
+
+pub struct MeteredWriter {
+    backend: ClientWriter,
+    metrics: Metrics,
+}
+
+impl MeteredWriter {
+    pub fn new(backend: ClientWriter, metrics: Metrics) -> MeteredWriter {
+        MeteredWriter {
+            backend: backend,
+            metrics: metrics,
+        }
+    }
+}
+
+impl WriteMessage<ServerMessage> for MeteredWriter {
+    fn write_message(&mut self, message: &ServerMessage) -> Result<()> {
+        self.backend.write_message(message)?;
+        self.metrics.write()?.sent(message);
+        Ok(())
+    }
+}
+
+fn describe(output: &mut String, name: &str, kind: &str, help: &str) {
+    output.push_str(&format!("# HELP {} {}\n", name, help));
+    output.push_str(&format!("# TYPE {} {}\n", name, kind));
+}
+
+fn render_labeled(output: &mut String, name: &str, label: &str, values: &BTreeMap<&'static str, u64>) {
+    for (key, value) in values {
+        output.push_str(&format!("{}{{{}=\"{}\"}} {}\n", name, label, key, value));
+    }
+}
+
+fn render(state: &ServerState) -> Result<String> {
+    let sessions: Vec<_> = state.clients.read()?
+        .values()
+        .cloned()
+        .collect();
+
+    let mut sharers = 0;
+
+    for it in &sessions {
+        sharers += it.receiving_sharers()?.read()?.len();
+        sharers += it.sending_sharers_queue()?.read()?.len();
+    }
+
+    let suspended = state.sessions.read()?.suspended().count();
+    let metrics = state.metrics.read()?;
+    let mut output = String::new();
+
+    describe(&mut output, "chat_connected_clients", "gauge", "Clients online right now");
+    output.push_str(&format!("chat_connected_clients {}\n", sessions.len()));
+
+    describe(&mut output, "chat_suspended_sessions", "gauge", "Sessions waiting to be resumed");
+    output.push_str(&format!("chat_suspended_sessions {}\n", suspended));
+
+    describe(&mut output, "chat_active_file_sharers", "gauge", "Files being uploaded or downloaded");
+    output.push_str(&format!("chat_active_file_sharers {}\n", sharers));
+
+    describe(&mut output, "chat_messages_received_total", "counter", "Messages received from the clients");
+    render_labeled(&mut output, "chat_messages_received_total", "type", &metrics.received);
+
+    describe(&mut output, "chat_messages_sent_total", "counter", "Messages sent to the clients");
+    render_labeled(&mut output, "chat_messages_sent_total", "type", &metrics.sent);
+
+    describe(&mut output, "chat_transferred_bytes_total", "counter", "File bytes sent in chunks");
+    output.push_str(&format!("chat_transferred_bytes_total{{direction=\"in\"}} {}\n", metrics.bytes_received));
+    output.push_str(&format!("chat_transferred_bytes_total{{direction=\"out\"}} {}\n", metrics.bytes_sent));
+
+    describe(&mut output, "chat_decode_errors_total", "counter", "Failed reads, closed connections included");
+    render_labeled(&mut output, "chat_decode_errors_total", "kind", &metrics.decode_errors);
+
+    describe(&mut output, "chat_broadcast_latency_seconds", "histogram", "How long a broadcast takes");
+
+    let mut cumulative = 0;
+
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(&metrics.latency_buckets) {
+        cumulative += count;
+        output.push_str(&format!("chat_broadcast_latency_seconds_bucket{{le=\"{}\"}} {}\n", bound, cumulative));
+    }
+
+    output.push_str(&format!("chat_broadcast_latency_seconds_bucket{{le=\"+Inf\"}} {}\n", metrics.latency_count));
+    output.push_str(&format!("chat_broadcast_latency_seconds_sum {}\n", metrics.latency_sum));
+    output.push_str(&format!("chat_broadcast_latency_seconds_count {}\n", metrics.latency_count));
+
+    Ok(output)
+}
+
+fn handle_request(mut stream: TcpStream, state: ServerState) -> Result<()> {
+    let patience = Duration::from_secs(state.config.read()?.message_deadline_seconds);
+    stream.set_read_timeout(Some(patience))?;
+
+    let request = read_request(&mut stream, patience)?;
+
+    if request.method != "GET" || request.path != "/metrics" {
+        return write_response(&mut stream, "404 Not Found", "text/plain", "Try GET /metrics\n")
+    }
+
+    let body = render(&state)?;
+    write_response(&mut stream, "200 OK", "text/plain; version=0.0.4", &body)
+}
+
+pub fn listen(listener: TcpListener, state: ServerState) -> Result<()> {
+    for incomming in listener.incoming() {
+        let the_state = state.clone();
+
+        thread::spawn(move || {
+            with_error_report(|| handle_request(incomming?, the_state))
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::collections::{HashSet};
+use std::net::{SocketAddr};
+use std::path::{Path};
+use std::fs::{File};
+
+use shared::{Result};
+use shared::shared::{Shared};
+use shared::connection::messages::{BanTarget};
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ban {
+    pub target: BanTarget,
+    // Unix time in seconds,
+    // None means forever
+    pub until: Option<i64>,
+}
+
+pub fn ip_of(address: &str) -> Option<String> {
+    address.parse::<SocketAddr>().ok().map(|it| it.ip().to_string())
+}
+
+impl Ban {
+    pub fn is_active(&self) -> bool {
+        match self.until {
+            Some(it) => chrono::Utc::now().timestamp() < it,
+            None => true,
+        }
+    }
+
+    // The sessions are banned by their resume
+    // tokens, those of the new connections
+    // are only known once they resume
+    pub fn affects(&self, address: &str, name: &str, token: Option<&str>) -> bool {
+        match &self.target {
+            BanTarget::Name(it) => it == name,
+            BanTarget::Session(it) => token == Some(it.as_str()),
+            BanTarget::Ip(it) => ip_of(address).as_ref() == Some(it),
+        }
+    }
+}
+
+// What's saved in the bans file, the mutes and
+// promotions stay there too, so that neither
+// a reconnection nor a restart undo them
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct Saved {
+    bans: Vec<Ban>,
+    // Accounts of the operators
+    // granted by other operators
+    promoted: HashSet<String>,
+    // Names or accounts
+    muted: HashSet<String>,
+}
+
+pub struct ModerationStorage {
+    path: String,
+    saved: Saved,
+}
+
+impl ModerationStorage {
+    pub fn load(path: &str) -> Result<ModerationStorage> {
+        let saved = if Path::new(path).exists() {
+            serde_json::from_reader(File::open(path)?)?
+        } else {
+            Saved::default()
+        };
+
+        let storage = ModerationStorage {
+            path: path.to_owned(),
+            saved: saved,
+        };
+
+        Ok(storage)
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = File::create(&self.path)?;
+        serde_json::to_writer_pretty(file, &self.saved)?;
+        Ok(())
+    }
+
+    pub fn ban(&mut self, ban: Ban) -> Result<()> {
+        self.saved.bans.retain(|it| it.is_active());
+        self.saved.bans.push(ban);
+        self.save()
+    }
+
+    pub fn is_banned(&self, address: &str, name: &str) -> bool {
+        self.saved.bans.iter().any(|it| it.is_active() && it.affects(address, name, None))
+    }
+
+    pub fn is_name_banned(&self, name: &str) -> bool {
+        self.is_target_banned(&BanTarget::Name(name.to_owned()))
+    }
+
+    pub fn is_session_banned(&self, token: &str) -> bool {
+        self.is_target_banned(&BanTarget::Session(token.to_owned()))
+    }
+
+    fn is_target_banned(&self, target: &BanTarget) -> bool {
+        self.saved.bans.iter().any(|it| it.is_active() && &it.target == target)
+    }
+
+    pub fn promote(&mut self, account: &str) -> Result<()> {
+        self.saved.promoted.insert(account.to_owned());
+        self.save()
+    }
+
+    pub fn is_promoted(&self, account: &str) -> bool {
+        self.saved.promoted.contains(account)
+    }
+
+    pub fn mute(&mut self, name: &str) -> Result<()> {
+        self.saved.muted.insert(name.to_owned());
+        self.save()
+    }
+
+    pub fn unmute(&mut self, name: &str) -> Result<bool> {
+        let was_muted = self.saved.muted.remove(name);
+
+        if was_muted {
+            self.save()?;
+        }
+
+        Ok(was_muted)
+    }
+
+    pub fn is_muted(&self, name: &str) -> bool {
+        self.saved.muted.contains(name)
+    }
+
+    // So that a new name doesn't
+    // get anyone out of a mute
+    pub fn rename(&mut self, old_name: &str, new_name: &str) -> Result<()> {
+        if self.saved.muted.remove(old_name) {
+            self.saved.muted.insert(new_name.to_owned());
+            self.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+pub type Moderation = Shared<ModerationStorage>;
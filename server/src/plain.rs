@@ -0,0 +1,240 @@
+use std::collections::{VecDeque};
+use std::io::{Read, Write};
+use std::time::{Duration};
+
+use shared::{Result, is_would_block_error};
+use shared::shared::{Shared};
+use shared::transport::{BoxedTransport};
+use shared::communication::{ReadMessage, WriteMessage, WireFormat, ReadDeadlines};
+use shared::communication::framing::{Frames};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+    BanTarget,
+};
+
+use shared::connection::limits::{Limits, SharedLimits, field_size};
+
+use crate::connection::{ServerState, ClientReader, ClientWriter};
+use crate::streams::{read_line};
+
+const HELP: &[&str] = &[
+    "/rename <name> - change the name",
+    "/register <name> <password> - create an account and log in",
+    "/login <name> <password> - log in",
+    "/users - list the online users",
+    "/op <name>, /kick <name>, /mute <name>, /unmute <name> - for operators",
+    "/ban name|session|ip <target> [seconds] - for operators",
+    "/quit - leave",
+];
+
+fn check_size(value: &str, limit: usize, what: &str) -> std::result::Result<String, String> {
+    if field_size(value) > limit {
+        Err(format!("No way, sorry, this {} is way too long", what))
+    } else {
+        Ok(value.to_owned())
+    }
+}
+
+fn parse_target(words: &[&str], limits: &Limits) -> std::result::Result<String, String> {
+    match words.get(1) {
+        Some(it) => check_size(it, limits.name_size, "name"),
+        None => Err("And who's the lucky one?".to_owned()),
+    }
+}
+
+fn parse_credentials(words: &[&str], limits: &Limits) -> std::result::Result<(String, String), String> {
+    match words {
+        [_, name, password, ..] => Ok((
+            check_size(name, limits.name_size, "name")?,
+            check_size(password, limits.password_size, "password")?,
+        )),
+        _ => Err("I need both a name and a password, in this very order".to_owned()),
+    }
+}
+
+fn parse_ban(words: &[&str], limits: &Limits) -> std::result::Result<ClientMessage, String> {
+    let (kind, target) = match words {
+        [_, kind, target, ..] => (*kind, check_size(target, limits.name_size, "name")?),
+        _ => return Err("Ban what? Try /ban name|session|ip <target> [seconds]".to_owned()),
+    };
+
+    let target = match kind {
+        "name" => BanTarget::Name(target),
+        "session" => BanTarget::Session(target),
+        "ip" => BanTarget::Ip(target),
+        _ => return Err("It's either name, session or ip".to_owned()),
+    };
+
+    let duration_seconds = match words.get(3) {
+        Some(it) => Some(it.parse::<u64>().map_err(|_| "The duration is in seconds".to_owned())?),
+        None => None,
+    };
+
+    Ok(ClientMessage::Ban { target, duration_seconds })
+}
+
+// Err is the explanation for the user
+fn parse_line(line: &str, limits: &Limits) -> std::result::Result<Option<ClientMessage>, String> {
+    if line.trim().is_empty() {
+        return Ok(None)
+    }
+
+    if !line.starts_with('/') {
+        let text = check_size(line, limits.text_size, "text")?;
+        return Ok(Some(ClientMessage::Text { text }))
+    }
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let message = match words[0] {
+        "/quit" | "/q" | "/exit" => ClientMessage::Leave,
+        "/users" => ClientMessage::ListUsers,
+        "/rename" | "/r" => ClientMessage::Rename { new_name: parse_target(&words, limits)? },
+        "/op" => ClientMessage::Promote { name: parse_target(&words, limits)? },
+        "/kick" => ClientMessage::Kick { name: parse_target(&words, limits)? },
+        "/mute" => ClientMessage::Mute { name: parse_target(&words, limits)? },
+        "/unmute" => ClientMessage::Unmute { name: parse_target(&words, limits)? },
+        "/ban" => parse_ban(&words, limits)?,
+        "/register" => {
+            let (name, password) = parse_credentials(&words, limits)?;
+            ClientMessage::Register { name, password }
+        }
+        "/login" => {
+            let (name, password) = parse_credentials(&words, limits)?;
+            ClientMessage::Authenticate { name, password }
+        }
+        "/help" => return Err(HELP.join("\n")),
+        _ => return Err("Unknown command, see /help".to_owned()),
+    };
+
+    Ok(Some(message))
+}
+
+pub struct PlainReader<R> {
+    frames: Frames<R>,
+    // For the answers that never
+    // reach the server
+    replies: Shared<BoxedTransport>,
+    limits: Limits,
+    pending: VecDeque<ClientMessage>,
+}
+
+impl<R: Read> PlainReader<R> {
+    pub fn new(
+        stream: R,
+        replies: Shared<BoxedTransport>,
+        limits: Limits,
+        deadlines: ReadDeadlines,
+    ) -> PlainReader<R> {
+        PlainReader {
+            frames: Frames::new(stream, limits.message_size, deadlines),
+            replies: replies,
+            limits: limits,
+            // Nobody types the handshake by hand
+            pending: vec![ClientMessage::Join].into(),
+        }
+    }
+
+    fn reply(&mut self, text: &str) -> Result<()> {
+        for it in text.lines() {
+            self.replies.write_all(format!("(Server) {}\r\n", it).as_bytes())?;
+        }
+
+        self.replies.flush()?;
+        Ok(())
+    }
+}
+
+impl<R: Read> ReadMessage<ClientMessage> for PlainReader<R> {
+    fn read_message(&mut self) -> Result<ClientMessage> {
+        loop {
+            if let Some(it) = self.pending.pop_front() {
+                return Ok(it)
+            }
+
+            // There's a person on the other side, and
+            // they can't answer a Ping, so the server
+            // never gets the chance to send one. The
+            // deadlines and the TCP errors tell if
+            // they're gone instead
+            let line = match read_line(&mut self.frames) {
+                Ok(it) => it,
+                Err(error) if is_would_block_error(&error) => continue,
+                Err(error) => return Err(error),
+            };
+
+            match parse_line(&line, &self.limits) {
+                Ok(Some(it)) => return Ok(it),
+                Ok(None) => {}
+                Err(explanation) => self.reply(&explanation)?,
+            }
+        }
+    }
+}
+
+pub struct PlainWriter {
+    stream: Shared<BoxedTransport>,
+}
+
+impl PlainWriter {
+    pub fn new(stream: Shared<BoxedTransport>) -> PlainWriter {
+        PlainWriter {
+            stream: stream,
+        }
+    }
+
+    fn write_lines(&mut self, lines: &str) -> Result<()> {
+        self.stream.write_all(format!("{}\r\n", lines).as_bytes())?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+impl WriteMessage<ServerMessage> for PlainWriter {
+    fn write_message(&mut self, message: &ServerMessage) -> Result<()> {
+        match message {
+            ServerMessage::Common { .. } |
+            ServerMessage::Limits { .. } => {
+                return Ok(())
+            }
+            _ => {}
+        }
+
+        // Multi-line texts included
+        let lines = format!("{}", message).replace('\n', "\r\n");
+
+        // The reader wakes up and
+        // sees the connection is gone
+        if let Err(error) = self.write_lines(&lines) {
+            let _ = self.stream.read()?.shutdown();
+            return Err(error)
+        }
+
+        Ok(())
+    }
+}
+
+pub fn build_plain_codec(
+    reading_stream: Shared<BoxedTransport>,
+    writing_stream: Shared<BoxedTransport>,
+    state: &ServerState,
+) -> Result<(ClientReader, ClientWriter, SharedLimits)> {
+    let (limits, deadlines, idle) = {
+        let config = state.config.read()?;
+        (config.limits(WireFormat::default()), config.read_deadlines(), config.plain_idle_deadline_seconds)
+    };
+
+    // People who only read the room look
+    // idle, so they get more time
+    let deadlines = ReadDeadlines {
+        idle: Some(Duration::from_secs(idle)),
+        ..deadlines
+    };
+
+    let reader = PlainReader::new(reading_stream, writing_stream.clone(), limits, deadlines);
+    let writer = PlainWriter::new(writing_stream);
+
+    Ok((Box::new(reader), Box::new(writer), SharedLimits::new(limits)))
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap};
+
+use shared::shared::{Shared};
+use shared::helpers::{to_hex};
+use shared::connection::messages::{ServerMessage};
+
+use rand::{RngCore};
+
+const TOKEN_SIZE: usize = 16;
+const MAXIMUM_PENDING_MESSAGES: usize = 100;
+
+pub struct SuspendedSession {
+    pub address: String,
+    pub name: String,
+    pub pending: Vec<ServerMessage>,
+}
+
+pub struct SessionsStorage {
+    // address -> token
+    tokens: HashMap<String, String>,
+    // token -> session
+    suspended: HashMap<String, SuspendedSession>,
+}
+
+impl SessionsStorage {
+    pub fn new() -> SessionsStorage {
+        SessionsStorage {
+            tokens: HashMap::new(),
+            suspended: HashMap::new(),
+        }
+    }
+
+    pub fn issue_token(&mut self, address: &str) -> String {
+        let mut bytes = [0u8; TOKEN_SIZE];
+        rand::thread_rng().fill_bytes(&mut bytes);
+
+        let token = to_hex(&bytes);
+        self.tokens.insert(address.to_owned(), token.clone());
+        token
+    }
+
+    pub fn suspend(&mut self, address: &str, name: &str) -> Option<String> {
+        let token = self.tokens.remove(address)?;
+
+        let session = SuspendedSession {
+            address: address.to_owned(),
+            name: name.to_owned(),
+            pending: vec![],
+        };
+
+        self.suspended.insert(token.clone(), session);
+        Some(token)
+    }
+
+    pub fn resume(&mut self, token: &str, address: &str) -> Option<SuspendedSession> {
+        let session = self.suspended.remove(token)?;
+        self.tokens.insert(address.to_owned(), token.to_owned());
+        Some(session)
+    }
+
+    // The server may not have noticed the old
+    // connection has gone yet, then the new
+    // one takes its place right away
+    pub fn take_over(&mut self, token: &str, address: &str) -> Option<String> {
+        let old_address = self.tokens.iter()
+            .find(|(_, it)| it.as_str() == token)
+            .map(|(key, _)| key.clone())?;
+
+        self.tokens.remove(&old_address);
+        self.tokens.insert(address.to_owned(), token.to_owned());
+        Some(old_address)
+    }
+
+    pub fn token_at(&self, address: &str) -> Option<String> {
+        self.tokens.get(address).cloned()
+    }
+
+    pub fn suspended_token(&self, name: &str) -> Option<String> {
+        self.suspended.iter()
+            .find(|(_, it)| it.name == name || it.address == name)
+            .map(|(token, _)| token.clone())
+    }
+
+    pub fn expire(&mut self, token: &str) -> Option<SuspendedSession> {
+        self.suspended.remove(token)
+    }
+
+    pub fn forget(&mut self, address: &str) {
+        self.tokens.remove(address);
+    }
+
+    pub fn suspended(&self) -> impl Iterator<Item = &SuspendedSession> {
+        self.suspended.values()
+    }
+
+    pub fn enqueue(&mut self, message: &ServerMessage) {
+        for it in self.suspended.values_mut() {
+            if it.pending.len() < MAXIMUM_PENDING_MESSAGES {
+                it.pending.push(message.clone());
+            }
+        }
+    }
+}
+
+pub type Sessions = Shared<SessionsStorage>;
@@ -0,0 +1,59 @@
+use std::io::{Read};
+
+use shared::{Result, ErrorKind};
+use shared::communication::framing::{Frames};
+
+pub fn would_block() -> shared::Error {
+    std::io::Error::from(std::io::ErrorKind::WouldBlock).into()
+}
+
+pub fn too_much_data() -> shared::Error {
+    std::io::Error::from(std::io::ErrorKind::InvalidData).into()
+}
+
+// Reads whatever is there. Same as for
+// BsonScanner, WouldBlock means there's
+// nothing yet, and the partial data stays
+// in the buffer
+pub fn fill(stream: &mut impl Read, buffer: &mut Vec<u8>) -> Result<()> {
+    let mut chunk = [0u8; 1024];
+
+    let count = match stream.read(&mut chunk) {
+        Ok(it) => it,
+        Err(error) => match error.kind() {
+            std::io::ErrorKind::WouldBlock |
+            std::io::ErrorKind::TimedOut => {
+                return Err(would_block())
+            }
+            std::io::ErrorKind::ConnectionReset => {
+                return Err(ErrorKind::NothingToRead.into())
+            }
+            _ => {
+                return Err(error.into())
+            }
+        }
+    };
+
+    if count == 0 {
+        return Err(ErrorKind::NothingToRead.into())
+    }
+
+    buffer.extend(&chunk[..count]);
+    Ok(())
+}
+
+// For the text-based gateways, the frames
+// keep the cap, line ending included, and
+// give up on the clients that are too slow
+// or silent for too long
+pub fn read_line<R: Read>(frames: &mut Frames<R>) -> Result<String> {
+    loop {
+        if let Some(index) = frames.buffer().iter().position(|it| *it == b'\n') {
+            let line = frames.take(index + 1);
+            let text = String::from_utf8_lossy(&line);
+            return Ok(text.trim_end_matches(['\r', '\n']).to_owned())
+        }
+
+        frames.fill_in_time()?;
+    }
+}
@@ -0,0 +1,76 @@
+use shared::shared::{Shared};
+
+// A file the server sends to a client,
+// the uploads are in the sessions' own
+// receiving sharers
+pub struct Download {
+    pub address: String,
+    pub name: String,
+    pub id: usize,
+    pub size: usize,
+    // None until the client
+    // agrees to the size
+    pub sent: Option<usize>,
+}
+
+impl Download {
+    pub fn percentage(&self) -> u8 {
+        match self.sent {
+            Some(it) if self.size > 0 => (it * 100 / self.size) as u8,
+            Some(_) => 100,
+            None => 0,
+        }
+    }
+}
+
+pub struct TransfersStorage {
+    downloads: Vec<Download>,
+}
+
+impl TransfersStorage {
+    pub fn new() -> TransfersStorage {
+        TransfersStorage {
+            downloads: vec![],
+        }
+    }
+
+    fn find(&mut self, address: &str, id: usize) -> Option<&mut Download> {
+        self.downloads.iter_mut().find(|it| it.address == address && it.id == id)
+    }
+
+    pub fn offer(&mut self, address: &str, name: &str, id: usize, size: usize) {
+        let download = Download {
+            address: address.to_owned(),
+            name: name.to_owned(),
+            id: id,
+            size: size,
+            sent: None,
+        };
+
+        self.downloads.push(download);
+    }
+
+    pub fn progress(&mut self, address: &str, id: usize, sent: usize) {
+        if let Some(it) = self.find(address, id) {
+            it.sent = Some(sent);
+        }
+    }
+
+    pub fn finish(&mut self, address: &str, id: usize) {
+        self.downloads.retain(|it| it.address != address || it.id != id);
+    }
+
+    pub fn is_download(&self, address: &str, id: usize) -> bool {
+        self.downloads.iter().any(|it| it.address == address && it.id == id)
+    }
+
+    pub fn forget(&mut self, address: &str) {
+        self.downloads.retain(|it| it.address != address);
+    }
+
+    pub fn downloads(&self) -> impl Iterator<Item = &Download> {
+        self.downloads.iter()
+    }
+}
+
+pub type Transfers = Shared<TransfersStorage>;
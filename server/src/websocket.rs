@@ -0,0 +1,308 @@
+use std::io::{Read, Write, Cursor, Chain};
+use std::time::{Duration};
+
+use shared::{Result, ErrorKind};
+use shared::shared::{Shared};
+use shared::transport::{BoxedTransport};
+use shared::communication::{ReadMessage, WriteMessage, WireFormat, ReadDeadlines};
+use shared::communication::framing::{Frames};
+use shared::communication::json::{JsonWriter, from_json};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+};
+
+use shared::connection::limits::{SharedLimits};
+
+use sha1::{Sha1, Digest};
+
+use crate::connection::{ServerState, ClientReader, ClientWriter, FragmentingWriter};
+use crate::streams::{too_much_data};
+use crate::http::{read_head, find_header};
+
+// See RFC 6455, section 1.3
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+// 2 bytes, 8 more for the longest length
+// and 4 for the mask, see RFC 6455, section 5.2
+const MAXIMUM_FRAME_HEADER_SIZE: usize = 14;
+
+fn protocol_error(reason: &str) -> shared::Error {
+    ErrorKind::MalformedMessage { message: reason.to_owned() }.into()
+}
+
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    // The server never masks
+    // and never fragments
+    let mut frame = vec![0x80 | opcode];
+
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend(&(payload.len() as u64).to_be_bytes());
+    }
+
+    frame.extend(payload);
+    frame
+}
+
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> Result<()> {
+    stream.write_all(&encode_frame(opcode, payload))?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(HANDSHAKE_GUID.as_bytes());
+    base64::encode(hasher.finalize())
+}
+
+// Returns the bytes that came
+// after the request, if any
+fn accept_upgrade(stream: &mut BoxedTransport, patience: Duration) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    let end = read_head(stream, &mut buffer, patience)?;
+
+    let request = String::from_utf8_lossy(&buffer[..end]).into_owned();
+
+    let is_upgrade = find_header(&request, "Upgrade")
+        .map(|it| it.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+
+    let key = match find_header(&request, "Sec-WebSocket-Key") {
+        Some(it) if is_upgrade && request.starts_with("GET ") => it,
+        _ => {
+            stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            return Err(protocol_error("Not a WebSocket handshake"))
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(buffer[end..].to_vec())
+}
+
+struct Frame {
+    is_final: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+// None means the frame
+// hasn't fully arrived yet
+fn parse_frame(buffer: &[u8], cap: usize) -> Result<Option<(Frame, usize)>> {
+    if buffer.len() < 2 {
+        return Ok(None)
+    }
+
+    let is_final = buffer[0] & 0x80 != 0;
+    let opcode = buffer[0] & 0x0F;
+    let is_masked = buffer[1] & 0x80 != 0;
+
+    if !is_masked {
+        return Err(protocol_error("Client frames must be masked"))
+    }
+
+    let (length, offset) = match buffer[1] & 0x7F {
+        126 if buffer.len() >= 4 => {
+            (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4)
+        }
+        127 if buffer.len() >= 10 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&buffer[2..10]);
+            (u64::from_be_bytes(bytes), 10)
+        }
+        126 | 127 => return Ok(None),
+        it => (it as u64, 2),
+    };
+
+    if length > cap as u64 {
+        return Err(too_much_data())
+    }
+
+    let end = offset + 4 + length as usize;
+
+    if buffer.len() < end {
+        return Ok(None)
+    }
+
+    let mask = &buffer[offset..offset + 4];
+
+    let payload = buffer[offset + 4..end]
+        .iter()
+        .enumerate()
+        .map(|(index, it)| it ^ mask[index % 4])
+        .collect();
+
+    Ok(Some((Frame { is_final, opcode, payload }, end)))
+}
+
+pub struct WebSocketReader<R> {
+    // The bytes that came along with
+    // the handshake are read first
+    frames: Frames<Chain<Cursor<Vec<u8>>, R>>,
+    // Control frames are answered
+    // right away
+    replies: Shared<BoxedTransport>,
+    message: Vec<u8>,
+    cap: usize,
+}
+
+impl<R: Read> WebSocketReader<R> {
+    pub fn new(
+        stream: R,
+        replies: Shared<BoxedTransport>,
+        leftovers: Vec<u8>,
+        cap: usize,
+        deadlines: ReadDeadlines,
+    ) -> WebSocketReader<R> {
+        let stream = Cursor::new(leftovers).chain(stream);
+
+        WebSocketReader {
+            frames: Frames::new(stream, cap + MAXIMUM_FRAME_HEADER_SIZE, deadlines),
+            replies: replies,
+            message: vec![],
+            cap: cap,
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<Frame> {
+        loop {
+            if let Some((frame, size)) = parse_frame(self.frames.buffer(), self.cap)? {
+                self.frames.take(size);
+                return Ok(frame)
+            }
+
+            self.frames.fill_in_time()?;
+        }
+    }
+
+    // Returns the payload of a complete
+    // data message, fragments are glued
+    fn read_payload(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let frame = self.read_frame()?;
+
+            match frame.opcode {
+                OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                    if self.message.len() + frame.payload.len() > self.cap {
+                        return Err(too_much_data())
+                    }
+
+                    self.message.extend(frame.payload);
+
+                    if frame.is_final {
+                        return Ok(std::mem::take(&mut self.message))
+                    }
+                }
+                OPCODE_PING => {
+                    write_frame(&mut self.replies, OPCODE_PONG, &frame.payload)?;
+                }
+                OPCODE_PONG => {
+                    // The heartbeat goes through
+                    // the JSON messages instead
+                }
+                OPCODE_CLOSE => {
+                    write_frame(&mut self.replies, OPCODE_CLOSE, &frame.payload)?;
+                    return Err(ErrorKind::NothingToRead.into())
+                }
+                _ => {
+                    return Err(protocol_error("Unknown opcode"))
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> ReadMessage<ClientMessage> for WebSocketReader<R> {
+    fn read_message(&mut self) -> Result<ClientMessage> {
+        // A frame holds exactly one message,
+        // no need to look for the line breaks
+        let payload = self.read_payload()?;
+        from_json(&payload)
+    }
+}
+
+// Collects everything written before
+// a flush into a single text frame
+pub struct FrameWriter<W> {
+    stream: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let payload = std::mem::take(&mut self.buffer);
+        self.stream.write_all(&encode_frame(OPCODE_TEXT, &payload))?;
+        self.stream.flush()
+    }
+}
+
+pub struct WebSocketWriter<W> {
+    backend: JsonWriter<FrameWriter<W>>,
+}
+
+impl<W: Write> WebSocketWriter<W> {
+    pub fn new(stream: W, cap: usize) -> WebSocketWriter<W> {
+        let frames = FrameWriter {
+            stream: stream,
+            buffer: vec![],
+        };
+
+        WebSocketWriter {
+            backend: JsonWriter::new(frames, cap),
+        }
+    }
+}
+
+impl<W: Write> WriteMessage<ServerMessage> for WebSocketWriter<W> {
+    fn write_message(&mut self, message: &ServerMessage) -> Result<()> {
+        self.backend.write_message(message)
+    }
+}
+
+pub fn build_websocket_codec(
+    reading_stream: Shared<BoxedTransport>,
+    writing_stream: Shared<BoxedTransport>,
+    state: &ServerState,
+) -> Result<(ClientReader, ClientWriter, SharedLimits)> {
+    let (patience, limits, deadlines) = {
+        let config = state.config.read()?;
+        (Duration::from_secs(config.message_deadline_seconds), config.limits(WireFormat::Json), config.read_deadlines())
+    };
+
+    let leftovers = accept_upgrade(&mut *reading_stream.write()?, patience)?;
+
+    let reader = WebSocketReader::new(reading_stream, writing_stream.clone(), leftovers, limits.message_size, deadlines);
+    let writer = WebSocketWriter::new(writing_stream, limits.message_size);
+
+    // Speaks the same JSON, pieces included
+    let limits = SharedLimits::new(limits);
+    let writer = FragmentingWriter::new(Box::new(writer), limits.clone());
+
+    Ok((Box::new(reader), Box::new(writer), limits))
+}
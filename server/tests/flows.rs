@@ -0,0 +1,589 @@
+mod harness;
+
+use harness::{TestServer, ScriptedClient, FileGuard, test_config, uploads_directory};
+
+use server::{ServerConfig, RateLimit, serve};
+
+use shared::communication::{WireFormat};
+use shared::transport::memory;
+use shared::connection::fragments::{Assembler, split_text};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+    CommonMessage,
+    BanTarget,
+};
+
+fn expect_text(client: &mut ScriptedClient, from: &str, text: &str) {
+    client.expect(&format!("{} saying '{}'", from, text), |it| match it {
+        ServerMessage::Text { name, text: it, .. } => name == from && it == text,
+        _ => false,
+    });
+}
+
+fn chat_between_two(server: &TestServer) {
+    let mut alice = server.join_as("alice");
+    let mut bob = server.join();
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    bob.rename("bob");
+    bob.send(ClientMessage::Text { text: "Hi there".to_owned() });
+
+    expect_text(&mut alice, "bob", "Hi there");
+    expect_text(&mut bob, "bob", "Hi there");
+}
+
+#[test]
+fn join_rename_and_text_in_memory() {
+    chat_between_two(&TestServer::in_memory());
+}
+
+#[test]
+fn join_rename_and_text_over_tcp() {
+    chat_between_two(&TestServer::on_tcp());
+}
+
+#[test]
+fn join_rename_and_text_over_tls() {
+    chat_between_two(&TestServer::on_tls());
+}
+
+#[test]
+fn tls_certificates_are_trusted_on_first_use() {
+    let server = TestServer::on_tls();
+
+    // Nothing is known the first time,
+    // whatever the server shows is taken
+    let (_, seen) = server.connect_tls(None).expect("Couldn't connect the first time");
+    assert_eq!(Some(&seen), server.fingerprint.as_ref());
+
+    server.connect_tls(Some(seen)).expect("The same certificate has been refused");
+
+    let error = match server.connect_tls(Some("00".repeat(32))) {
+        Ok(_) => panic!("A changed certificate has been accepted"),
+        Err(it) => it,
+    };
+
+    assert!(error.to_string().contains("has changed"), "Unexpected error > {}", error);
+}
+
+// Alice speaks Arson anyway
+fn chat_with_format(format: WireFormat) {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+    let mut bob = server.join_speaking(format);
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    bob.rename("bob");
+    bob.send(ClientMessage::Text { text: "Hi \"there\"\nfrom afar".to_owned() });
+
+    expect_text(&mut alice, "bob", "Hi \"there\"\nfrom afar");
+    expect_text(&mut bob, "bob", "Hi \"there\"\nfrom afar");
+}
+
+#[test]
+fn json_and_arson_clients_share_the_room() {
+    chat_with_format(WireFormat::Json);
+}
+
+#[test]
+fn binary_and_arson_clients_share_the_room() {
+    chat_with_format(WireFormat::Binary);
+}
+
+#[test]
+fn newcomers_are_announced_by_their_address() {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+    let mut bob = server.join();
+
+    let joined = alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    // The guests are called by their
+    // addresses until they pick a name
+    bob.rename("bob");
+
+    if let ServerMessage::NewUser { name, .. } = joined {
+        assert_eq!(Some(name), bob.address);
+    }
+}
+
+#[test]
+fn taken_names_are_refused() {
+    let server = TestServer::in_memory();
+    let _alice = server.join_as("alice");
+    let mut impostor = server.join();
+
+    impostor.send(ClientMessage::Rename { new_name: "alice".to_owned() });
+    impostor.expect("a refusal", |it| matches!(it, ServerMessage::Support { .. }));
+}
+
+fn log_in(client: &mut ScriptedClient, message: ClientMessage, name: &str) {
+    client.send(message);
+
+    client.expect_eventually("logging in", |it| match it {
+        ServerMessage::UserRenamed { new_name, .. } => new_name == name,
+        _ => false,
+    });
+}
+
+fn join_as_operator(server: &TestServer) -> ScriptedClient {
+    let mut alice = server.join();
+    let register = ClientMessage::Register { name: "alice".to_owned(), password: "secret".to_owned() };
+
+    log_in(&mut alice, register, "alice");
+    alice
+}
+
+fn operator_config() -> ServerConfig {
+    ServerConfig {
+        operators: vec!["alice".to_owned()],
+        ..test_config()
+    }
+}
+
+#[test]
+fn mutes_survive_reconnecting_and_restarts() {
+    let config = operator_config();
+    let server = TestServer::in_memory_with(config.clone());
+    let mut alice = join_as_operator(&server);
+    let mut bob = server.join_as("bob");
+
+    alice.send(ClientMessage::Mute { name: "bob".to_owned() });
+    bob.expect_eventually("the mute", |it| matches!(it, ServerMessage::UserMuted { .. }));
+    bob.send(ClientMessage::Leave);
+
+    // Same files, so it's
+    // as if it restarted
+    let restarted = TestServer::in_memory_with(config);
+    let mut bob = restarted.join_as("bob");
+
+    bob.send(ClientMessage::Text { text: "Hi there".to_owned() });
+
+    bob.expect_eventually("the warning", |it| match it {
+        ServerMessage::Support { text } => text.starts_with("You're muted"),
+        _ => false,
+    });
+}
+
+#[test]
+fn promotions_follow_the_account() {
+    let server = TestServer::in_memory_with(operator_config());
+    let mut alice = join_as_operator(&server);
+    let mut carol = server.join();
+    let mut dave = server.join_as("dave");
+
+    let register = ClientMessage::Register { name: "carol".to_owned(), password: "secret".to_owned() };
+    log_in(&mut carol, register, "carol");
+
+    // Anyone could take a
+    // guest's name later
+    alice.send(ClientMessage::Promote { name: "dave".to_owned() });
+
+    alice.expect_eventually("a refusal", |it| match it {
+        ServerMessage::Support { text } => text.ends_with("has to log in first"),
+        _ => false,
+    });
+
+    alice.send(ClientMessage::Promote { name: "carol".to_owned() });
+    carol.expect_eventually("the promotion", |it| matches!(it, ServerMessage::UserPromoted { .. }));
+    carol.send(ClientMessage::Leave);
+
+    let mut carol = server.join();
+    let authenticate = ClientMessage::Authenticate { name: "carol".to_owned(), password: "secret".to_owned() };
+
+    log_in(&mut carol, authenticate, "carol");
+    carol.send(ClientMessage::Kick { name: "dave".to_owned() });
+
+    dave.expect_eventually("being kicked", |it| matches!(it, ServerMessage::Goodbye { .. }));
+}
+
+#[test]
+fn banned_sessions_cant_be_resumed() {
+    let server = TestServer::in_memory_with(operator_config());
+    let mut alice = join_as_operator(&server);
+    let mut bob = server.join_as("bob");
+
+    let ban = ClientMessage::Ban {
+        target: BanTarget::Session("bob".to_owned()),
+        duration_seconds: None,
+    };
+
+    alice.send(ban);
+    bob.expect_eventually("the goodbye", |it| matches!(it, ServerMessage::Goodbye { .. }));
+
+    // Only the name is shown,
+    // never the token
+    alice.expect_eventually("the ban", |it| match it {
+        ServerMessage::UserBanned { target, .. } => *target == BanTarget::Session("bob".to_owned()),
+        _ => false,
+    });
+
+    let mut again = server.connect();
+    again.send(ClientMessage::Resume { token: bob.token.clone() });
+    again.expect("the refusal", |it| matches!(it, ServerMessage::Goodbye { .. }));
+
+    // Anyone else is still welcome,
+    // the IP isn't what's banned
+    let mut carol = server.join_as("carol");
+    carol.send(ClientMessage::Text { text: "Hi there".to_owned() });
+
+    expect_text(&mut carol, "carol", "Hi there");
+
+    alice.expect_eventually("carol's text", |it| match it {
+        ServerMessage::Text { name, .. } => name == "carol",
+        _ => false,
+    });
+}
+
+#[test]
+fn leaving_is_announced() {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+    let mut bob = server.join_as("bob");
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    bob.send(ClientMessage::Leave);
+
+    alice.expect("bob leaving", |it| match it {
+        ServerMessage::UserLeaves { name, .. } => name == "bob",
+        _ => false,
+    });
+}
+
+#[test]
+fn dropped_connections_are_announced_as_interrupts() {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+    let bob = server.join_as("bob");
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    bob.disconnect();
+
+    alice.expect("bob's interrupt", |it| match it {
+        ServerMessage::Interrupt { name, .. } => name == "bob",
+        _ => false,
+    });
+}
+
+#[test]
+fn sessions_are_resumed_before_the_drop_is_noticed() {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+    let bob = server.join_as("bob");
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    // The old connection is still
+    // open as far as the server knows
+    let mut new_bob = server.resume(&bob.token);
+
+    new_bob.expect("the welcome back", |it| match it {
+        ServerMessage::Support { text } => text.starts_with("Welcome back"),
+        _ => false,
+    });
+
+    new_bob.send(ClientMessage::Text { text: "Still me".to_owned() });
+
+    expect_text(&mut alice, "bob", "Still me");
+    expect_text(&mut new_bob, "bob", "Still me");
+
+    // The name hasn't been left behind
+    // with the old connection
+    new_bob.send(ClientMessage::ListUsers);
+
+    new_bob.expect("the users", |it| match it {
+        ServerMessage::UserList { names, .. } => {
+            names.iter().filter(|it| it.as_str() == "bob").count() == 1
+        }
+        _ => false,
+    });
+}
+
+#[test]
+fn users_are_listed() {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+    let _bob = server.join_as("bob");
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+    alice.send(ClientMessage::ListUsers);
+
+    alice.expect("the list", |it| match it {
+        ServerMessage::UserList { names, last } => *last && names == &["alice", "bob"],
+        _ => false,
+    });
+}
+
+fn upload(client: &mut ScriptedClient, name: &str, data: &[u8]) {
+    let id = 7;
+
+    client.send(ClientMessage::RequestFileUpload {
+        name: name.to_owned(),
+        size: data.len(),
+        id: id,
+    });
+
+    client.expect("the upload agreement", |it| match it {
+        ServerMessage::AgreeFileUpload { id: it } => *it == id,
+        _ => false,
+    });
+
+    for chunk in data.chunks(client.limits.chunk_size) {
+        let common = CommonMessage::Chunk {
+            data: chunk.to_vec(),
+            id: id,
+        };
+
+        client.send(ClientMessage::Common { common });
+    }
+}
+
+fn download(client: &mut ScriptedClient, name: &str) -> Vec<u8> {
+    client.send(ClientMessage::RequestFileDownload { name: name.to_owned() });
+
+    let agreement = client.expect("the download agreement", |it| match it {
+        ServerMessage::AgreeFileDownload { name: it, .. } => it == name,
+        _ => false,
+    });
+
+    let (size, id) = match agreement {
+        ServerMessage::AgreeFileDownload { size, id, .. } => (size, id),
+        _ => unreachable!(),
+    };
+
+    client.send(ClientMessage::AgreeFileDownload { id });
+
+    let mut received = vec![];
+
+    while received.len() < size {
+        let chunk = client.expect("a chunk", |it| match it {
+            ServerMessage::Common { common: CommonMessage::Chunk { id: it, .. } } => *it == id,
+            _ => false,
+        });
+
+        if let ServerMessage::Common { common: CommonMessage::Chunk { data, .. } } = chunk {
+            received.extend(data);
+        }
+    }
+
+    received
+}
+
+#[test]
+fn uploaded_files_can_be_downloaded() {
+    let server = TestServer::in_memory();
+    let file = FileGuard::new("harness-upload");
+
+    let mut alice = server.join_as("alice");
+    let mut bob = server.join_as("bob");
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    // Several chunks, the last
+    // one is incomplete
+    let data: Vec<u8> = (0..alice.limits.chunk_size * 2 + 17).map(|it| (it % 251) as u8).collect();
+
+    upload(&mut alice, &file.name, &data);
+
+    for it in [&mut alice, &mut bob] {
+        it.expect("the new file", |message| match message {
+            ServerMessage::NewFile { name } => name == &file.name,
+            _ => false,
+        });
+    }
+
+    assert_eq!(download(&mut bob, &file.name), data);
+}
+
+#[test]
+fn existing_files_are_not_overwritten() {
+    let server = TestServer::in_memory();
+    let file = FileGuard::new("harness-existing");
+    std::fs::create_dir_all(uploads_directory()).unwrap();
+    std::fs::write(&file.path, b"Old").unwrap();
+
+    let mut alice = server.join_as("alice");
+
+    alice.send(ClientMessage::RequestFileUpload {
+        name: file.name.clone(),
+        size: 3,
+        id: 0,
+    });
+
+    alice.expect("a refusal", |it| matches!(it, ServerMessage::DeclineFileUpload { id: 0, .. }));
+}
+
+#[test]
+fn missing_files_are_not_downloaded() {
+    let server = TestServer::in_memory();
+    let file = FileGuard::new("harness-missing");
+    let mut alice = server.join_as("alice");
+
+    alice.send(ClientMessage::RequestFileDownload { name: file.name.clone() });
+    alice.expect("a refusal", |it| matches!(it, ServerMessage::DeclineFileDownload { .. }));
+}
+
+#[test]
+fn only_the_uploads_are_reachable() {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+
+    // The server's own directory
+    // has a Cargo.toml for sure
+    for name in ["Cargo.toml", "../Cargo.toml", "..", "src\\lib.rs", "/etc/hostname", ""] {
+        alice.send(ClientMessage::RequestFileDownload { name: name.to_owned() });
+        alice.expect("a refusal", |it| matches!(it, ServerMessage::DeclineFileDownload { .. }));
+    }
+
+    let file = FileGuard::new("harness-escaping");
+    let name = format!("../{}", file.name);
+
+    alice.send(ClientMessage::RequestFileUpload { name: name, size: 3, id: 0 });
+    alice.expect("a refusal", |it| matches!(it, ServerMessage::DeclineFileUpload { id: 0, .. }));
+
+    assert!(!std::env::temp_dir().join(&file.name).exists());
+}
+
+#[test]
+fn private_files_are_kept_out_of_the_uploads() {
+    let inside = |name: &str| uploads_directory().join(name).to_string_lossy().into_owned();
+
+    let configs = [
+        ServerConfig { accounts_file: inside("accounts.json"), ..test_config() },
+        ServerConfig { tls_certificate: inside("server.crt"), ..test_config() },
+        ServerConfig { tls_key: inside("server.key"), ..test_config() },
+    ];
+
+    for config in configs {
+        let (listener, _connector) = memory::listener();
+        assert!(serve(listener, config).is_err());
+    }
+}
+
+fn send_long_text(client: &mut ScriptedClient, text: &str) {
+    try_send_long_text(client, text).expect("Couldn't send a long text");
+}
+
+fn try_send_long_text(client: &mut ScriptedClient, text: &str) -> shared::Result<()> {
+    let pieces = split_text(text, client.limits.fragment_size);
+    let count = pieces.len();
+
+    for (index, piece) in pieces.into_iter().enumerate() {
+        client.try_send(ClientMessage::TextFragment {
+            text: piece,
+            id: 5,
+            index: index,
+            last: index + 1 == count,
+        })?;
+    }
+
+    Ok(())
+}
+
+fn expect_long_text(client: &mut ScriptedClient, from: &str) -> String {
+    let mut texts = Assembler::new(usize::MAX, usize::MAX);
+
+    loop {
+        let fragment = client.expect("a piece of the text", |it| match it {
+            ServerMessage::TextFragment { name, .. } => name == from,
+            _ => false,
+        });
+
+        if let ServerMessage::TextFragment { text, id, index, last, .. } = fragment {
+            if let Some(whole) = texts.push(id, index, &text, last).unwrap() {
+                return whole
+            }
+        }
+    }
+}
+
+#[test]
+fn long_texts_are_glued_back() {
+    let server = TestServer::in_memory();
+    let mut alice = server.join_as("alice");
+
+    let mut bob = server.join_speaking(WireFormat::Binary);
+    bob.rename("bob");
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    let text = "Quite a long story. ".repeat(150);
+    send_long_text(&mut alice, &text);
+
+    // Cut again, the way
+    // each of them needs
+    assert_eq!(expect_long_text(&mut alice, "alice"), text);
+    assert_eq!(expect_long_text(&mut bob, "alice"), text);
+}
+
+#[test]
+fn long_texts_are_limited() {
+    let config = ServerConfig {
+        maximum_long_text_size: 1000,
+        ..test_config()
+    };
+
+    let server = TestServer::in_memory_with(config);
+    let mut alice = server.join_as("alice");
+    let mut bob = server.join_as("bob");
+
+    alice.expect("bob joining", |it| matches!(it, ServerMessage::NewUser { .. }));
+
+    // Bob is dropped midway, so
+    // the rest may not get through
+    let _ = try_send_long_text(&mut bob, &"x".repeat(2000));
+
+    alice.expect("bob being dropped", |it| matches!(it, ServerMessage::Interrupt { .. }));
+}
+
+#[test]
+fn long_text_pieces_are_rate_limited() {
+    let config = ServerConfig {
+        fragment_rate: RateLimit { burst: 2.0, per_second: 0.0 },
+        ..test_config()
+    };
+
+    let server = TestServer::in_memory_with(config);
+    let mut bob = server.join_as("bob");
+
+    let text = "x".repeat(2000);
+    let count = split_text(&text, bob.limits.fragment_size).len();
+
+    send_long_text(&mut bob, &text);
+
+    // Two pieces get through,
+    // the rest are rejected
+    for _ in 2..count {
+        bob.expect("a warning", |it| match it {
+            ServerMessage::Support { text } => text.starts_with("Slow down"),
+            _ => false,
+        });
+    }
+
+    // The broken text is dropped, but
+    // the plain texts still work
+    bob.send(ClientMessage::Text { text: "Hi there".to_owned() });
+    expect_text(&mut bob, "bob", "Hi there");
+}
+
+#[test]
+fn multi_line_texts_keep_their_newlines() {
+    let server = TestServer::in_memory();
+    let text = "fn main() {\n    println!(\"Hi\");\n}";
+
+    for format in WireFormat::ALL.iter() {
+        let mut client = server.join_speaking(*format);
+        let name = format!("{:?}", format).to_lowercase();
+
+        client.rename(&name);
+        client.send(ClientMessage::Text { text: text.to_owned() });
+
+        expect_text(&mut client, &name, text);
+    }
+}
@@ -0,0 +1,179 @@
+mod harness;
+
+use harness::{TestServer, test_config};
+
+use server::{ServerConfig, Gateway};
+
+use shared::connection::fragments::{split_text};
+use shared::connection::messages::{ClientMessage};
+
+fn gateway_config() -> ServerConfig {
+    ServerConfig {
+        // The deadlines are only checked
+        // when a read gives up
+        heartbeat_interval_seconds: 1,
+        ..test_config()
+    }
+}
+
+#[test]
+fn irc_pings_are_answered_before_registering() {
+    let server = TestServer::with_gateway(Gateway::Irc, gateway_config());
+    let mut client = server.connect_lines();
+
+    client.send("PING :are-you-there");
+    client.expect("the pong", |it| it.ends_with("PONG tcp-chat :are-you-there"));
+
+    client.register("alice");
+}
+
+#[test]
+fn long_texts_are_cut_into_irc_lines() {
+    let server = TestServer::with_gateway(Gateway::Irc, gateway_config());
+    let mut alice = server.connect_lines();
+
+    alice.register("alice");
+    alice.expect("joining the channel", |it| it.contains(" 366 "));
+
+    let mut bob = server.join_as("bob");
+    let text = "ыx".repeat(600);
+    let pieces = split_text(&text, bob.limits.fragment_size);
+    let count = pieces.len();
+
+    for (index, piece) in pieces.into_iter().enumerate() {
+        bob.send(ClientMessage::TextFragment {
+            text: piece,
+            id: 1,
+            index: index,
+            last: index + 1 == count,
+        });
+    }
+
+    let head = ":bob!bob@tcp-chat PRIVMSG #chat :";
+    let mut received = String::new();
+
+    while received.len() < text.len() {
+        let line = alice.expect("a piece of the text", |it| it.starts_with(head));
+
+        // The "\r\n" included
+        assert!(line.len() + 2 <= 512, "{} bytes long", line.len() + 2);
+        received.push_str(&line[head.len()..]);
+    }
+
+    assert_eq!(received, text);
+}
+
+#[test]
+fn irc_texts_too_long_once_escaped_arrive_whole() {
+    let server = TestServer::with_gateway(Gateway::Irc, gateway_config());
+    let mut alice = server.connect_lines();
+    let mut bob = server.connect_lines();
+
+    alice.register("alice");
+    bob.register("bob");
+    bob.expect("joining the channel", |it| it.contains(" 366 "));
+
+    // Fits into the line, but each
+    // of them is escaped in JSON
+    let text = "\"".repeat(450);
+    alice.send(&format!("PRIVMSG #chat :{}", &text));
+
+    let line = bob.expect("the text", |it| it.contains(" PRIVMSG "));
+    assert_eq!(line, format!(":alice!alice@tcp-chat PRIVMSG #chat :{}", &text));
+}
+
+#[test]
+fn irc_commands_cant_be_smuggled_in() {
+    let server = TestServer::with_gateway(Gateway::Irc, gateway_config());
+    let mut alice = server.connect_lines();
+
+    alice.register("alice");
+    alice.expect("joining the channel", |it| it.contains(" 366 "));
+
+    let mut bob = server.join_as("bob");
+
+    bob.send(ClientMessage::Rename { new_name: "x\r\nKICK #chat alice".to_owned() });
+    bob.send(ClientMessage::Text { text: "Hi\rKICK #chat alice\0".to_owned() });
+
+    loop {
+        let line = alice.receive().expect("The server has hung up");
+
+        if line.contains(" PRIVMSG ") {
+            assert_eq!(line, ":bob!bob@tcp-chat PRIVMSG #chat :Hi KICK #chat alice ");
+            break
+        }
+
+        assert!(!line.contains("KICK"), "Smuggled in > {}", line);
+    }
+}
+
+#[test]
+fn silent_irc_clients_are_dropped() {
+    let server = TestServer::with_gateway(Gateway::Irc, ServerConfig {
+        idle_deadline_seconds: 1,
+        ..gateway_config()
+    });
+
+    let mut client = server.connect_lines();
+    client.expect_hang_up();
+}
+
+#[test]
+fn silent_websocket_clients_are_dropped() {
+    let server = TestServer::with_gateway(Gateway::WebSocket, ServerConfig {
+        idle_deadline_seconds: 1,
+        ..gateway_config()
+    });
+
+    let mut client = server.connect_lines();
+
+    client.send("GET / HTTP/1.1");
+    client.send("Upgrade: websocket");
+    client.send("Connection: Upgrade");
+    client.send("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==");
+    client.send("");
+
+    client.expect("the upgrade", |it| it.starts_with("HTTP/1.1 101"));
+    client.expect_hang_up();
+}
+
+#[test]
+fn quiet_plain_clients_outlast_the_heartbeat() {
+    let server = TestServer::with_gateway(Gateway::Plain, ServerConfig {
+        heartbeat_timeout_seconds: 1,
+        ..gateway_config()
+    });
+
+    let mut client = server.connect_lines();
+    std::thread::sleep(std::time::Duration::from_secs(3));
+
+    client.send("Still here");
+    client.expect("the text", |it| it.ends_with("Still here"));
+}
+
+#[test]
+fn silent_plain_clients_are_dropped() {
+    let server = TestServer::with_gateway(Gateway::Plain, ServerConfig {
+        plain_idle_deadline_seconds: 1,
+        ..gateway_config()
+    });
+
+    let mut client = server.connect_lines();
+    client.expect_hang_up();
+}
+
+#[test]
+fn slow_plain_lines_are_dropped() {
+    let server = TestServer::with_gateway(Gateway::Plain, ServerConfig {
+        message_deadline_seconds: 1,
+        ..gateway_config()
+    });
+
+    let mut client = server.connect_lines();
+
+    client.send("Hi there");
+    client.expect("the text", |it| it.ends_with("Hi there"));
+
+    client.send_raw("Still typ");
+    client.expect_hang_up();
+}
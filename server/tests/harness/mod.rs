@@ -0,0 +1,497 @@
+// Starts a real server inside the test process
+// and talks to it the same way the client does
+
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use server::{ServerConfig, Gateway, serve, serve_with_gateway};
+
+use shared::is_would_block_error;
+use shared::shared::{Shared};
+use shared::communication::{ReadMessage, WriteMessage, WireFormat};
+use shared::communication::arson::{ArsonReader, ArsonWriter};
+use shared::communication::json::{JsonReader, JsonWriter};
+use shared::communication::binary::{BinaryReader, BinaryWriter};
+use shared::transport::{BoxedTransport, split};
+use shared::transport::memory::{self, MemoryConnector};
+use shared::transport::tls::{TlsTransport, TlsListener, load_server_config, certificate_fingerprint};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+    CommonMessage,
+};
+
+use shared::connection::limits::{Limits, DEFAULT_MESSAGE_SIZE};
+
+// How long to wait for a message
+// that is supposed to arrive
+const PATIENCE: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Something nobody else
+// in this process uses
+pub fn unique_name(prefix: &str) -> String {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    format!("{}-{}-{}", prefix, std::process::id(), id)
+}
+
+// Shared by all the tests, the
+// file names are unique anyway
+pub fn uploads_directory() -> PathBuf {
+    std::env::temp_dir().join("harness-uploads")
+}
+
+pub fn test_config() -> ServerConfig {
+    let directory = std::env::temp_dir();
+
+    ServerConfig {
+        accounts_file: path_in(&directory, "accounts"),
+        bans_file: path_in(&directory, "bans"),
+        uploads_directory: uploads_directory().to_string_lossy().into_owned(),
+        // Interrupts are reported
+        // right away
+        resume_grace_period_seconds: 0,
+        ..ServerConfig::default()
+    }
+}
+
+fn path_in(directory: &Path, prefix: &str) -> String {
+    directory.join(unique_name(prefix) + ".json").to_string_lossy().into_owned()
+}
+
+enum Endpoint {
+    Memory(MemoryConnector),
+    Tcp(SocketAddr),
+    Tls(SocketAddr),
+}
+
+pub struct TestServer {
+    endpoint: Endpoint,
+    gateway: Option<MemoryConnector>,
+    // Of the TLS certificate
+    pub fingerprint: Option<String>,
+}
+
+impl TestServer {
+    pub fn in_memory() -> TestServer {
+        TestServer::in_memory_with(test_config())
+    }
+
+    pub fn in_memory_with(config: ServerConfig) -> TestServer {
+        let (listener, connector) = memory::listener();
+
+        thread::spawn(move || {
+            serve(listener, config).expect("The server has failed");
+        });
+
+        TestServer {
+            endpoint: Endpoint::Memory(connector),
+            gateway: None,
+            fingerprint: None,
+        }
+    }
+
+    // On an ephemeral port
+    pub fn on_tcp() -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind");
+        let address = listener.local_addr().expect("No local address");
+        let config = test_config();
+
+        thread::spawn(move || {
+            serve(listener, config).expect("The server has failed");
+        });
+
+        TestServer {
+            endpoint: Endpoint::Tcp(address),
+            gateway: None,
+            fingerprint: None,
+        }
+    }
+
+    // With a fresh self-signed
+    // certificate every time
+    pub fn on_tls() -> TestServer {
+        let directory = std::env::temp_dir();
+        let certificate = directory.join(unique_name("certificate") + ".crt").to_string_lossy().into_owned();
+        let key = directory.join(unique_name("key") + ".key").to_string_lossy().into_owned();
+
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).expect("Couldn't generate a certificate");
+        std::fs::write(&certificate, generated.cert.pem()).expect("Couldn't save the certificate");
+        std::fs::write(&key, generated.key_pair.serialize_pem()).expect("Couldn't save the key");
+
+        let tls_config = load_server_config(&certificate, &key).expect("Couldn't load the certificate");
+        let fingerprint = certificate_fingerprint(&certificate).expect("Couldn't read the fingerprint");
+
+        let _ = std::fs::remove_file(&certificate);
+        let _ = std::fs::remove_file(&key);
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind");
+        let address = listener.local_addr().expect("No local address");
+        let config = test_config();
+
+        thread::spawn(move || {
+            serve(TlsListener::new(listener, tls_config), config).expect("The server has failed");
+        });
+
+        TestServer {
+            endpoint: Endpoint::Tls(address),
+            gateway: None,
+            fingerprint: Some(fingerprint),
+        }
+    }
+
+    // Same as the client does it, returns the
+    // fingerprint the server has shown
+    pub fn connect_tls(&self, expected: Option<String>) -> shared::Result<(TlsTransport, String)> {
+        let address = match &self.endpoint {
+            Endpoint::Tls(it) => it,
+            _ => panic!("Not a TLS server"),
+        };
+
+        let stream = TcpStream::connect(address).expect("Couldn't connect");
+        stream.set_read_timeout(Some(PATIENCE)).expect("Couldn't set the timeout");
+
+        TlsTransport::connect(stream, "localhost", expected)
+    }
+
+    // The main protocol along with one of the
+    // gateways, see connect_lines()
+    pub fn with_gateway(gateway: Gateway, config: ServerConfig) -> TestServer {
+        let (listener, connector) = memory::listener();
+        let (gateway_listener, gateway_connector) = memory::listener();
+
+        thread::spawn(move || {
+            serve_with_gateway(listener, gateway, gateway_listener, config).expect("The server has failed");
+        });
+
+        TestServer {
+            endpoint: Endpoint::Memory(connector),
+            gateway: Some(gateway_connector),
+            fingerprint: None,
+        }
+    }
+
+    pub fn connect_lines(&self) -> LineClient {
+        let connector = self.gateway.as_ref().expect("No gateway");
+        LineClient::new(Box::new(connector.connect().expect("Couldn't connect")))
+    }
+
+    fn open(&self) -> BoxedTransport {
+        match &self.endpoint {
+            Endpoint::Memory(connector) => {
+                Box::new(connector.connect().expect("Couldn't connect"))
+            }
+            Endpoint::Tcp(address) => {
+                Box::new(TcpStream::connect(address).expect("Couldn't connect"))
+            }
+            Endpoint::Tls(_) => {
+                let (transport, _) = self.connect_tls(self.fingerprint.clone()).expect("Couldn't shake hands");
+                Box::new(transport)
+            }
+        }
+    }
+
+    // Joins as a guest and waits
+    // for the greeting
+    pub fn join(&self) -> ScriptedClient {
+        self.join_speaking(WireFormat::Arson)
+    }
+
+    pub fn join_speaking(&self, format: WireFormat) -> ScriptedClient {
+        let mut client = ScriptedClient::new(self.open(), format);
+        client.send(ClientMessage::Join);
+
+        let limits = client.expect("the limits", |it| matches!(it, ServerMessage::Limits { .. }));
+
+        if let ServerMessage::Limits { limits } = limits {
+            client.limits = limits;
+        }
+
+        client.expect("the greeting", |it| matches!(it, ServerMessage::Support { .. }));
+
+        let token = client.expect("the resume token", |it| matches!(it, ServerMessage::ResumeToken { .. }));
+
+        if let ServerMessage::ResumeToken { token } = token {
+            client.token = token;
+        }
+
+        client
+    }
+
+    // Comes back with the token of an
+    // earlier connection
+    pub fn resume(&self, token: &str) -> ScriptedClient {
+        let mut client = self.connect();
+        client.send(ClientMessage::Resume { token: token.to_owned() });
+
+        let limits = client.expect("the limits", |it| matches!(it, ServerMessage::Limits { .. }));
+
+        if let ServerMessage::Limits { limits } = limits {
+            client.limits = limits;
+        }
+
+        client.token = token.to_owned();
+        client
+    }
+
+    // Without any handshake
+    pub fn connect(&self) -> ScriptedClient {
+        ScriptedClient::new(self.open(), WireFormat::Arson)
+    }
+
+    // Joins and picks a name right away
+    pub fn join_as(&self, name: &str) -> ScriptedClient {
+        let mut client = self.join();
+        client.rename(name);
+        client
+    }
+}
+
+pub struct ScriptedClient {
+    transport: Shared<BoxedTransport>,
+    reader: Box<dyn ReadMessage<ServerMessage>>,
+    writer: Box<dyn WriteMessage<ClientMessage>>,
+    pub token: String,
+    // What the server has advertised
+    pub limits: Limits,
+    // The name the server knew the
+    // client by before the last rename
+    pub address: Option<String>,
+}
+
+impl ScriptedClient {
+    fn new(mut transport: BoxedTransport, format: WireFormat) -> ScriptedClient {
+        // Lets the reader give up
+        // once in a while
+        transport.set_read_timeout(Some(POLL_INTERVAL)).expect("Couldn't set the timeout");
+        transport.write_all(format.preamble()).expect("Couldn't send the preamble");
+
+        let (reading, writing) = split(transport).expect("Couldn't split the transport");
+
+        // The writer doesn't check the size, so that
+        // the tests can see what the server does
+        // with the oversized messages
+        let (reader, writer): (Box<dyn ReadMessage<ServerMessage>>, Box<dyn WriteMessage<ClientMessage>>) = match format {
+            WireFormat::Arson => (
+                Box::new(ArsonReader::new(reading, DEFAULT_MESSAGE_SIZE)),
+                Box::new(ArsonWriter::new(writing.clone(), usize::MAX)),
+            ),
+            WireFormat::Json => (
+                Box::new(JsonReader::new(reading, DEFAULT_MESSAGE_SIZE)),
+                Box::new(JsonWriter::new(writing.clone(), usize::MAX)),
+            ),
+            WireFormat::Binary => (
+                Box::new(BinaryReader::new(reading, DEFAULT_MESSAGE_SIZE)),
+                Box::new(BinaryWriter::new(writing.clone(), usize::MAX)),
+            ),
+        };
+
+        ScriptedClient {
+            transport: writing,
+            reader: reader,
+            writer: writer,
+            token: String::new(),
+            limits: Limits::new(format, DEFAULT_MESSAGE_SIZE),
+            address: None,
+        }
+    }
+
+    pub fn send(&mut self, message: ClientMessage) {
+        self.try_send(message).expect("Couldn't send a message");
+    }
+
+    // For when the server is
+    // allowed to hang up first
+    pub fn try_send(&mut self, message: ClientMessage) -> shared::Result<()> {
+        self.writer.write_message(&message)
+    }
+
+    // The next message, but the
+    // heartbeat is skipped
+    pub fn receive(&mut self) -> ServerMessage {
+        let started = Instant::now();
+
+        loop {
+            let message: ServerMessage = match self.reader.read_message() {
+                Ok(it) => it,
+                Err(error) if is_would_block_error(&error) => {
+                    if started.elapsed() > PATIENCE {
+                        panic!("Nothing has arrived in {}s", PATIENCE.as_secs())
+                    }
+
+                    continue
+                }
+                Err(error) => panic!("Couldn't read a message > {}", error),
+            };
+
+            match &message {
+                ServerMessage::Common { common: CommonMessage::Ping } => {
+                    self.send(ClientMessage::Common { common: CommonMessage::Pong });
+                }
+                ServerMessage::Common { common: CommonMessage::Pong } => {}
+                _ => return message,
+            }
+        }
+    }
+
+    // Fails unless the very next
+    // message is the expected one
+    pub fn expect(&mut self, what: &str, check: impl Fn(&ServerMessage) -> bool) -> ServerMessage {
+        let message = self.receive();
+
+        if !check(&message) {
+            panic!("Expected {}, but got {:?}", what, message)
+        }
+
+        message
+    }
+
+    // Skips whatever else
+    // happens in the meantime
+    pub fn expect_eventually(&mut self, what: &str, check: impl Fn(&ServerMessage) -> bool) -> ServerMessage {
+        let started = Instant::now();
+
+        loop {
+            if started.elapsed() > PATIENCE {
+                panic!("Expected {}, but it hasn't arrived in {}s", what, PATIENCE.as_secs())
+            }
+
+            let message = self.receive();
+
+            if check(&message) {
+                return message
+            }
+        }
+    }
+
+    pub fn rename(&mut self, new_name: &str) {
+        self.send(ClientMessage::Rename { new_name: new_name.to_owned() });
+
+        let renamed = self.expect("the rename confirmation", |it| match it {
+            ServerMessage::UserRenamed { new_name: it, .. } => it == new_name,
+            _ => false,
+        });
+
+        if let ServerMessage::UserRenamed { old_name, .. } = renamed {
+            self.address.get_or_insert(old_name);
+        }
+    }
+
+    // Goes away without saying goodbye
+    pub fn disconnect(self) {
+        self.transport.read().expect("Poisoned lock").shutdown().expect("Couldn't shut down");
+    }
+}
+
+// Sends and receives the raw lines, e.g.
+// for IRC or the HTTP handshakes
+pub struct LineClient {
+    transport: BoxedTransport,
+    buffer: Vec<u8>,
+}
+
+impl LineClient {
+    fn new(transport: BoxedTransport) -> LineClient {
+        transport.set_read_timeout(Some(POLL_INTERVAL)).expect("Couldn't set the timeout");
+
+        LineClient {
+            transport: transport,
+            buffer: vec![],
+        }
+    }
+
+    pub fn send(&mut self, line: &str) {
+        self.send_raw(&format!("{}\r\n", line));
+    }
+
+    // Without the line ending
+    pub fn send_raw(&mut self, text: &str) {
+        self.transport.write_all(text.as_bytes()).expect("Couldn't send a line");
+    }
+
+    // None once the server
+    // has hung up
+    pub fn receive(&mut self) -> Option<String> {
+        let started = Instant::now();
+        let mut chunk = [0; 1024];
+
+        loop {
+            if let Some(index) = self.buffer.iter().position(|it| *it == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..index + 1).collect();
+                let line = String::from_utf8_lossy(&line);
+                return Some(line.trim_end_matches(&['\r', '\n'][..]).to_owned())
+            }
+
+            match self.transport.read(&mut chunk) {
+                Ok(0) => return None,
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(error) if is_timeout(&error) => {
+                    if started.elapsed() > PATIENCE {
+                        panic!("Nothing has arrived in {}s", PATIENCE.as_secs())
+                    }
+                }
+                Err(error) => panic!("Couldn't read a line > {}", error),
+            }
+        }
+    }
+
+    // Skips everything else
+    pub fn expect(&mut self, what: &str, check: impl Fn(&str) -> bool) -> String {
+        loop {
+            match self.receive() {
+                Some(it) if check(&it) => return it,
+                Some(_) => continue,
+                None => panic!("The server has hung up before sending {}", what),
+            }
+        }
+    }
+
+    pub fn register(&mut self, nick: &str) {
+        self.send(&format!("NICK {}", nick));
+        self.send(&format!("USER {} 0 * :{}", nick, nick));
+        self.expect("the welcome", |it| it.contains(" 001 "));
+    }
+
+    pub fn expect_hang_up(&mut self) {
+        while self.receive().is_some() {}
+    }
+}
+
+fn is_timeout(error: &std::io::Error) -> bool {
+    matches!(error.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+// Removes the file once the test is
+// over, even if it fails. The name is
+// what the clients ask for, the path
+// is where it's stored
+pub struct FileGuard {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl FileGuard {
+    pub fn new(prefix: &str) -> FileGuard {
+        let name = unique_name(prefix) + ".txt";
+
+        FileGuard {
+            path: uploads_directory().join(&name),
+            name: name,
+        }
+    }
+}
+
+impl Drop for FileGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -1,10 +1,55 @@
+pub mod framing;
 pub mod json;
 pub mod bson;
 pub mod arson;
+pub mod binary;
+
+use std::time::{Duration};
 
 use crate::{Result, Error, ErrorKind};
 
+use serde::{Serialize, Deserialize};
+use serde::de::{DeserializeOwned, Visitor};
+
 pub const DEFAULT_PORT: u32 = 6969;
+pub const DEFAULT_TLS_PORT: u32 = 6970;
+
+pub const PREAMBLE_SIZE: usize = 4;
+
+// How the messages look on the wire
+#[derive(Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    #[default]
+    Arson,
+    Json,
+    Binary,
+}
+
+impl WireFormat {
+    pub const ALL: [WireFormat; 3] = [WireFormat::Arson, WireFormat::Json, WireFormat::Binary];
+
+    // What the client sends before the
+    // handshake. Arson goes without it, as
+    // it always has. A BSON document never
+    // starts like this, since its size
+    // would be way too large
+    pub fn preamble(&self) -> &'static [u8] {
+        match self {
+            WireFormat::Arson => b"",
+            WireFormat::Json => b"JSON",
+            WireFormat::Binary => b"BIN1",
+        }
+    }
+
+    pub fn from_preamble(bytes: &[u8]) -> WireFormat {
+        match bytes {
+            b"JSON" => WireFormat::Json,
+            b"BIN1" => WireFormat::Binary,
+            _ => WireFormat::Arson,
+        }
+    }
+}
 
 pub trait ReadMessage<M> {
     fn read_message(&mut self) -> Result<M>;
@@ -14,6 +59,77 @@ pub trait WriteMessage<M> {
     fn write_message(&mut self, message: &M) -> Result<()>;
 }
 
+impl<M, R: ReadMessage<M> + ?Sized> ReadMessage<M> for Box<R> {
+    fn read_message(&mut self) -> Result<M> {
+        (**self).read_message()
+    }
+}
+
+impl<M, W: WriteMessage<M> + ?Sized> WriteMessage<M> for Box<W> {
+    fn write_message(&mut self, message: &M) -> Result<()> {
+        (**self).write_message(message)
+    }
+}
+
+// For the messages the newer versions may
+// add: they're kept the way they've arrived
+// instead of breaking the connection
+pub trait Tolerant: Sized {
+    fn is_known(tag: &str) -> bool;
+    fn unknown(tag: String, raw: Vec<u8>) -> Self;
+}
+
+// Pretends to be a format, but only
+// remembers what serde asks for
+struct Introspector<'a> {
+    variants: &'a mut &'static [&'static str],
+}
+
+impl<'de, 'a> serde::Deserializer<'de> for Introspector<'a> {
+    type Error = serde::de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> std::result::Result<V::Value, Self::Error> {
+        Err(serde::de::Error::custom("Not an enum"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        _: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        *self.variants = variants;
+        Err(serde::de::Error::custom("Only the names are needed"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+// The names of the variants serde
+// knows about, the skipped ones
+// aren't there
+pub fn variants_of<M: DeserializeOwned>() -> &'static [&'static str] {
+    let mut variants: &'static [&'static str] = &[];
+    let _ = M::deserialize(Introspector { variants: &mut variants });
+    variants
+}
+
+// Limits for how long a reader may
+// wait for the other side. None means
+// no limit at all
+#[derive(Clone, Copy, Default)]
+pub struct ReadDeadlines {
+    // From the first byte of a message
+    // till the last one
+    pub message: Option<Duration>,
+    // Between two complete messages
+    pub idle: Option<Duration>,
+}
+
 pub fn explain_common_error(error: &Error) -> String {
     match &error.kind {
         ErrorKind::Io { source: io_error } => match io_error.kind() {
@@ -2,35 +2,57 @@ use std::io::{Read, Write};
 
 use crate::{ErrorKind, Result};
 use crate::communication::bson::{BsonReader, BsonScanner, BsonWriter};
+use crate::helpers::capped_reader::{Capacity};
 
 use crate::communication::{
     ReadMessage,
     WriteMessage,
+    ReadDeadlines,
+    Tolerant,
 };
 
-use bson::doc;
+use bson::{doc, Document};
+use serde::de::{DeserializeOwned};
+
+// Every message is a document with a
+// single key, the name of the variant
+fn from_document<M: DeserializeOwned + Tolerant>(document: Document) -> Result<M> {
+    if let Some(tag) = document.keys().next() {
+        if !M::is_known(tag) {
+            let mut raw = vec![];
+            document.to_writer(&mut raw)?;
+            return Ok(M::unknown(tag.clone(), raw))
+        }
+    }
+
+    Ok(bson::from_bson(document.into())?)
+}
 
 pub struct ArsonReader<R> {
     backend: BsonReader<R>,
 }
 
 impl<R: Read> ArsonReader<R> {
-    pub fn new(reader: R, cap: usize) -> ArsonReader<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> ArsonReader<R> {
         ArsonReader {
             backend: BsonReader::new(reader, cap),
         }
     }
+
+    pub fn with_deadlines(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> ArsonReader<R> {
+        ArsonReader {
+            backend: BsonReader::with_deadlines(reader, cap, deadlines),
+        }
+    }
 }
 
 impl<R, M> ReadMessage<M> for ArsonReader<R>
 where
     R: Read,
-    M: for<'de> serde::Deserialize<'de>,
+    M: DeserializeOwned + Tolerant,
 {
     fn read_message(&mut self) -> Result<M> {
-        let it = self.backend.read_message()?;
-        let message: M = bson::from_bson(it.into())?;
-        Ok(message)
+        from_document(self.backend.read_message()?)
     }
 }
 
@@ -39,7 +61,7 @@ pub struct ArsonScanner<R> {
 }
 
 impl<R: Read> ArsonScanner<R> {
-    pub fn new(reader: R, cap: usize) -> ArsonScanner<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> ArsonScanner<R> {
         ArsonScanner {
             backend: BsonScanner::new(reader, cap),
         }
@@ -49,12 +71,10 @@ impl<R: Read> ArsonScanner<R> {
 impl<R, M> ReadMessage<M> for ArsonScanner<R>
 where
     R: Read,
-    M: for<'de> serde::Deserialize<'de>,
+    M: DeserializeOwned + Tolerant,
 {
     fn read_message(&mut self) -> Result<M> {
-        let it = self.backend.read_message()?;
-        let message: M = bson::from_bson(it.into())?;
-        Ok(message)
+        from_document(self.backend.read_message()?)
     }
 }
 
@@ -63,9 +83,9 @@ pub struct ArsonWriter<W> {
 }
 
 impl<W: Write> ArsonWriter<W> {
-    pub fn new(stream: W) -> ArsonWriter<W> {
+    pub fn new(stream: W, cap: impl Into<Capacity>) -> ArsonWriter<W> {
         ArsonWriter {
-            backend: BsonWriter::new(stream),
+            backend: BsonWriter::new(stream, cap),
         }
     }
 }
@@ -0,0 +1,310 @@
+use std::convert::{TryFrom};
+use std::io::{Read, Write};
+
+use crate::{Error, ErrorKind, Result, is_would_block_error};
+use crate::communication::{ReadMessage, WriteMessage, ReadDeadlines};
+use crate::communication::framing::{Frames, would_block};
+use crate::helpers::capped_reader::{Capacity};
+
+// A varint never takes more than
+// this for a 64-bit number
+const MAXIMUM_VARINT_SIZE: usize = 10;
+
+pub fn malformed(message: &str) -> Error {
+    ErrorKind::MalformedMessage { message: message.to_owned() }.into()
+}
+
+// Anything that knows how to
+// put itself into the binary
+// format and back
+pub trait BinaryMessage: Sized {
+    fn encode(&self, encoder: &mut Encoder);
+    fn decode(decoder: &mut Decoder) -> Result<Self>;
+}
+
+#[derive(Default)]
+pub struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    // LEB128: 7 bits at a time, the lowest
+    // ones first, the highest bit tells if
+    // there's more to come
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buffer.push((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+
+        self.buffer.push(value as u8);
+    }
+
+    pub fn size(&mut self, value: usize) {
+        self.varint(value as u64);
+    }
+
+    // Zigzag, so that the small negative
+    // numbers stay short as well
+    pub fn signed(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn boolean(&mut self, value: bool) {
+        self.buffer.push(value as u8);
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.size(value.len());
+        self.buffer.extend(value);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    // As it is, without the size
+    pub fn raw(&mut self, value: &[u8]) {
+        self.buffer.extend(value);
+    }
+
+    pub fn option<T>(&mut self, value: &Option<T>, encode: impl FnOnce(&mut Encoder, &T)) {
+        self.boolean(value.is_some());
+
+        if let Some(it) = value {
+            encode(self, it);
+        }
+    }
+}
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            data: data,
+        }
+    }
+
+    // Whatever hasn't been decoded yet
+    pub fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if count > self.data.len() {
+            return Err(malformed("The message ends too early"))
+        }
+
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+
+        for index in 0..MAXIMUM_VARINT_SIZE {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7F) as u64) << (7 * index);
+
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+
+        Err(malformed("The varint is too long"))
+    }
+
+    pub fn size(&mut self) -> Result<usize> {
+        usize::try_from(self.varint()?).map_err(|_| malformed("The number is too large"))
+    }
+
+    pub fn signed(&mut self) -> Result<i64> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    pub fn boolean(&mut self) -> Result<bool> {
+        match self.take(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(malformed("Not a boolean")),
+        }
+    }
+
+    pub fn bytes(&mut self) -> Result<Vec<u8>> {
+        let size = self.size()?;
+        Ok(self.take(size)?.to_vec())
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| malformed("The string is not UTF-8"))
+    }
+
+    pub fn option<T>(&mut self, decode: impl FnOnce(&mut Decoder<'a>) -> Result<T>) -> Result<Option<T>> {
+        if self.boolean()? {
+            Ok(Some(decode(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+// Returns the size of the body and the size
+// of its prefix, if the prefix has arrived
+fn try_fetch_size(buffer: &[u8]) -> Result<Option<(usize, usize)>> {
+    let mut decoder = Decoder::new(buffer);
+
+    match decoder.size() {
+        Ok(size) => Ok(Some((size, buffer.len() - decoder.data.len()))),
+        // Just not there yet
+        Err(_) if buffer.len() < MAXIMUM_VARINT_SIZE => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+// Each message is a varint size
+// followed by that many bytes
+pub struct BinaryScanner<R> {
+    frames: Frames<R>,
+    cap: Capacity,
+}
+
+impl<R: Read> BinaryScanner<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> BinaryScanner<R> {
+        BinaryScanner::with_deadlines(reader, cap, ReadDeadlines::default())
+    }
+
+    pub fn with_deadlines(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> BinaryScanner<R> {
+        let cap = cap.into();
+
+        BinaryScanner {
+            frames: Frames::new(reader, cap.clone(), deadlines),
+            cap: cap,
+        }
+    }
+
+    fn parse<M: BinaryMessage>(&mut self) -> Result<M> {
+        let (size, prefix) = match try_fetch_size(self.frames.buffer())? {
+            Some(it) => it,
+            None => return Err(would_block()),
+        };
+
+        // No need to wait for
+        // the rest of it
+        if size > self.cap.get().saturating_sub(prefix) {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into())
+        }
+
+        if self.frames.buffer().len() < prefix + size {
+            return Err(would_block())
+        }
+
+        let frame = self.frames.take(prefix + size);
+        M::decode(&mut Decoder::new(&frame[prefix..]))
+    }
+}
+
+impl<R, M> ReadMessage<M> for BinaryScanner<R>
+where
+    R: Read,
+    M: BinaryMessage,
+{
+    fn read_message(&mut self) -> Result<M> {
+        // We might've read multiple messages
+        // before, and now we need to return
+        // them one by one from the inner buffer
+        match self.parse() {
+            Err(error) if is_would_block_error(&error) => {}
+            other => return other,
+        }
+
+        self.frames.fill_in_time()?;
+        self.parse()
+    }
+}
+
+// Same as BsonReader, blocks, but
+// survives the read timeouts
+pub struct BinaryReader<R> {
+    backend: BinaryScanner<R>,
+}
+
+impl<R: Read> BinaryReader<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> BinaryReader<R> {
+        BinaryReader::with_deadlines(reader, cap, ReadDeadlines::default())
+    }
+
+    pub fn with_deadlines(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> BinaryReader<R> {
+        BinaryReader {
+            backend: BinaryScanner::with_deadlines(reader, cap, deadlines),
+        }
+    }
+}
+
+impl<R, M> ReadMessage<M> for BinaryReader<R>
+where
+    R: Read,
+    M: BinaryMessage,
+{
+    fn read_message(&mut self) -> Result<M> {
+        loop {
+            match self.backend.parse() {
+                Err(error) if is_would_block_error(&error) => {}
+                other => return other,
+            }
+
+            self.backend.frames.fill_in_time()?;
+        }
+    }
+}
+
+pub struct BinaryWriter<W> {
+    stream: W,
+    cap: Capacity,
+}
+
+impl<W> BinaryWriter<W> {
+    pub fn new(stream: W, cap: impl Into<Capacity>) -> BinaryWriter<W> {
+        BinaryWriter {
+            stream: stream,
+            cap: cap.into(),
+        }
+    }
+}
+
+impl<W, M> WriteMessage<M> for BinaryWriter<W>
+where
+    W: Write,
+    M: BinaryMessage,
+{
+    fn write_message(&mut self, message: &M) -> Result<()> {
+        let mut body = Encoder::new();
+        message.encode(&mut body);
+        let body = body.finish();
+
+        let mut frame = Encoder::new();
+        frame.bytes(&body);
+        let frame = frame.finish();
+
+        // The prefix included
+        if frame.len() > self.cap.get() {
+            return Err(ErrorKind::MessageSizeExceeded.into())
+        }
+
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
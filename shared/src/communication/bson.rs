@@ -1,13 +1,9 @@
 use std::io::{Read, Write};
 
-use crate::{ErrorKind, Result};
-use crate::communication::{ReadMessage, WriteMessage};
-
-use crate::helpers::capped_reader::{
-    IntoCappedReader,
-    CappedReader,
-    CappedRead,
-};
+use crate::{ErrorKind, Result, is_would_block_error};
+use crate::communication::{ReadMessage, WriteMessage, ReadDeadlines};
+use crate::communication::framing::{Frames, would_block};
+use crate::helpers::capped_reader::{Capacity};
 
 use bson::Document;
 
@@ -45,117 +41,116 @@ pub fn from_reader_with_checked_errors<R: Read>(
     }
 }
 
-pub struct BsonReader<R> {
-    stream: CappedReader<R>,
-}
-
-impl<R: Read> BsonReader<R> {
-    pub fn new(reader: R, cap: usize) -> BsonReader<R> {
-        BsonReader {
-            stream: reader.to_capped(cap),
-        }
-    }
-}
-
-impl<R: Read> ReadMessage<Document> for BsonReader<R> {
-    fn read_message(&mut self) -> Result<Document> {
-        let result = from_reader_with_checked_errors(&mut self.stream);
-
-        if let Ok(..) = result {
-            self.stream.clear();
-        }
-
-        result
-    }
-}
-
 pub struct BsonScanner<R> {
-    stream: CappedReader<R>,
-    buffer: Vec<u8>,
+    frames: Frames<R>,
 }
 
 impl<R: Read> BsonScanner<R> {
-    pub fn new(reader: R, cap: usize) -> BsonScanner<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> BsonScanner<R> {
+        BsonScanner::with_deadlines(reader, cap, ReadDeadlines::default())
+    }
+
+    pub fn with_deadlines(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> BsonScanner<R> {
         BsonScanner {
-            stream: reader.to_capped(cap),
-            buffer: vec![],
+            frames: Frames::new(reader, cap, deadlines),
         }
     }
 
     fn try_fetch_size(&self) -> Option<usize> {
         // See:
         // https://github.com/mongodb/bson-rust/blob/master/src/de/mod.rs#L145
-        if self.buffer.len() < 4 {
+        let buffer = self.frames.buffer();
+
+        if buffer.len() < 4 {
             return None;
         }
 
         let mut size_field = [0u8; 4];
-
-        for it in 0..4 {
-            size_field[it] = self.buffer[it];
-        }
+        size_field.copy_from_slice(&buffer[..4]);
 
         let size = i32::from_le_bytes(size_field) as usize;
-        return Some(size);
+        Some(size)
     }
 
     fn parse(&mut self) -> Result<Document> {
         if let Some(size) = self.try_fetch_size() {
-            if self.buffer.len() >= size {
-                let result = from_reader_with_checked_errors(&mut self.buffer.as_slice());
+            if self.frames.buffer().len() >= size {
+                let result = from_reader_with_checked_errors(self.frames.buffer());
 
-                if let Ok(..) = result {
-                    self.stream.clear();
-                    self.buffer.drain(..size);
+                if result.is_ok() {
+                    self.frames.take(size);
                 }
 
                 return result;
             }
         }
 
-        Err(std::io::Error::from(std::io::ErrorKind::WouldBlock).into())
+        Err(would_block())
     }
 }
 
 impl<R: Read> ReadMessage<Document> for BsonScanner<R> {
     fn read_message(&mut self) -> Result<Document> {
-        let mut new_data = vec![0u8; self.stream.space_left()];
-
-        let count = match self.stream.read(&mut new_data) {
-            Ok(count) => count,
-            Err(error) => match error.kind() {
-                // We might be unable to read something
-                // new, but we might've read multiple
-                // messages before, and now we need
-                // to return them one by one from
-                // the inner buffer
-                std::io::ErrorKind::WouldBlock => {
-                    0
-                }
-                // the other side disconnects before sending
-                // a single message
-                std::io::ErrorKind::ConnectionReset => {
-                    return Err(ErrorKind::NothingToRead.into())
-                }
-                _ => {
-                    return Err(error.into())
-                }
-            }
-        };
+        // We might've read multiple messages
+        // before, and now we need to return
+        // them one by one from the inner buffer
+        match self.parse() {
+            Err(error) if is_would_block_error(&error) => {}
+            other => return other,
+        }
 
-        self.buffer.extend(&new_data[..count]);
+        self.frames.fill_in_time()?;
         self.parse()
     }
 }
 
+// Blocks until the whole message arrives, but
+// can be safely interrupted by a read timeout
+// set on the underlying stream: the partially
+// received message is kept, and the next call
+// continues from where this one has stopped.
+// Note that the deadlines are only checked
+// when some data arrives or the read timeout
+// elapses.
+pub struct BsonReader<R> {
+    backend: BsonScanner<R>,
+}
+
+impl<R: Read> BsonReader<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> BsonReader<R> {
+        BsonReader::with_deadlines(reader, cap, ReadDeadlines::default())
+    }
+
+    pub fn with_deadlines(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> BsonReader<R> {
+        BsonReader {
+            backend: BsonScanner::with_deadlines(reader, cap, deadlines),
+        }
+    }
+}
+
+impl<R: Read> ReadMessage<Document> for BsonReader<R> {
+    fn read_message(&mut self) -> Result<Document> {
+        loop {
+            match self.backend.parse() {
+                Err(error) if is_would_block_error(&error) => {}
+                other => return other,
+            }
+
+            self.backend.frames.fill_in_time()?;
+        }
+    }
+}
+
 pub struct BsonWriter<W> {
     stream: W,
+    cap: Capacity,
 }
 
 impl<W> BsonWriter<W> {
-    pub fn new(stream: W) -> BsonWriter<W> {
+    pub fn new(stream: W, cap: impl Into<Capacity>) -> BsonWriter<W> {
         BsonWriter {
             stream: stream,
+            cap: cap.into(),
         }
     }
 }
@@ -166,6 +161,13 @@ impl<W: Write> WriteMessage<Document> for BsonWriter<W> {
         // it simply stays filled with 0
         let mut buffer = vec![];
         message.to_writer(&mut buffer)?;
+
+        // The other side would drop us
+        // anyway, so nothing is sent
+        if buffer.len() > self.cap.get() {
+            return Err(ErrorKind::MessageSizeExceeded.into())
+        }
+
         self.stream.write_all(&buffer)?;
         self.stream.flush()?;
         Ok(())
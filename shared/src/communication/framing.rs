@@ -0,0 +1,144 @@
+use std::io::{Read};
+use std::time::{Instant};
+
+use crate::{Error, ErrorKind, Result, is_would_block_error};
+use crate::communication::{ReadDeadlines};
+
+use crate::helpers::capped_reader::{
+    IntoCappedReader,
+    CappedReader,
+    Capacity,
+    CappedRead,
+};
+
+pub fn would_block() -> Error {
+    std::io::Error::from(std::io::ErrorKind::WouldBlock).into()
+}
+
+// Collects the incoming bytes until a whole
+// message can be cut off the front. Knows
+// nothing about the format itself, the
+// scanners decide where a message ends
+pub struct Frames<R> {
+    stream: CappedReader<R>,
+    buffer: Vec<u8>,
+    deadlines: ReadDeadlines,
+    // When the first byte of the
+    // current message has arrived
+    message_started: Option<Instant>,
+    last_message: Instant,
+}
+
+impl<R: Read> Frames<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> Frames<R> {
+        Frames {
+            stream: reader.to_capped(cap),
+            buffer: vec![],
+            deadlines: deadlines,
+            message_started: None,
+            last_message: Instant::now(),
+        }
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    // Removes the first `size` bytes
+    // as a complete message
+    pub fn take(&mut self, size: usize) -> Vec<u8> {
+        self.stream.clear();
+        let message = self.buffer.drain(..size).collect();
+
+        let now = Instant::now();
+        self.last_message = now;
+
+        // The next message may have
+        // already started arriving
+        self.message_started = if self.buffer.is_empty() {
+            None
+        } else {
+            Some(now)
+        };
+
+        message
+    }
+
+    fn check_deadlines(&self) -> Result<()> {
+        if let (Some(limit), Some(started)) = (self.deadlines.message, self.message_started) {
+            if started.elapsed() > limit {
+                let kind = ErrorKind::ReadTimeout {
+                    message: format!("A single message took longer than {}s to arrive", limit.as_secs())
+                };
+
+                return Err(kind.into())
+            }
+        }
+
+        if let Some(limit) = self.deadlines.idle {
+            if self.last_message.elapsed() > limit {
+                let kind = ErrorKind::ReadTimeout {
+                    message: format!("No messages for longer than {}s", limit.as_secs())
+                };
+
+                return Err(kind.into())
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        let mut new_data = vec![0u8; self.stream.space_left()];
+
+        let count = match self.stream.read(&mut new_data) {
+            Ok(count) => count,
+            Err(error) => match error.kind() {
+                // Either there's nothing to read
+                // in the non-blocking mode, or the
+                // read timeout has elapsed. Whatever
+                // we've already got stays in the
+                // buffer until next time
+                std::io::ErrorKind::WouldBlock |
+                std::io::ErrorKind::TimedOut => {
+                    return Err(would_block())
+                }
+                // the other side disconnects before sending
+                // a single message
+                std::io::ErrorKind::ConnectionReset => {
+                    return Err(ErrorKind::NothingToRead.into())
+                }
+                _ => {
+                    return Err(error.into())
+                }
+            }
+        };
+
+        // Reading 0 bytes without blocking means
+        // the other side has closed the connection
+        if count == 0 && !new_data.is_empty() {
+            return Err(ErrorKind::NothingToRead.into())
+        }
+
+        if count > 0 && self.message_started.is_none() {
+            self.message_started = Some(Instant::now());
+        }
+
+        self.buffer.extend(&new_data[..count]);
+        Ok(())
+    }
+
+    // Same as fill(), but also gives up
+    // if the other side is too slow
+    pub fn fill_in_time(&mut self) -> Result<()> {
+        self.check_deadlines()?;
+
+        match self.fill() {
+            Err(error) if is_would_block_error(&error) => {
+                self.check_deadlines()?;
+                Err(error)
+            }
+            other => other,
+        }
+    }
+}
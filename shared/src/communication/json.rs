@@ -1,57 +1,157 @@
 use std::io::{Read, Write};
 
-use crate::{ErrorKind, Result};
-use crate::helpers::capped_reader::{CappedReader, CappedRead};
-use crate::communication::{ReadMessage, WriteMessage};
+use crate::{ErrorKind, Result, is_would_block_error};
+use crate::communication::{ReadMessage, WriteMessage, ReadDeadlines, Tolerant};
+use crate::communication::framing::{Frames, would_block};
+use crate::helpers::capped_reader::{Capacity};
 
-use serde_json::{Deserializer, Value};
+use serde::{Serialize};
+use serde::de::{DeserializeOwned};
+use serde_json::{Value};
 
+// A unit variant is just its name, the
+// rest are objects with a single key
+pub fn from_json<M: DeserializeOwned + Tolerant>(bytes: &[u8]) -> Result<M> {
+    let value: Value = serde_json::from_slice(bytes)?;
+
+    let tag = match &value {
+        Value::String(it) => Some(it.clone()),
+        Value::Object(it) if it.len() == 1 => it.keys().next().cloned(),
+        _ => None,
+    };
+
+    if let Some(tag) = tag {
+        if !M::is_known(&tag) {
+            return Ok(M::unknown(tag, bytes.to_vec()))
+        }
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+// One message per line. serde_json escapes
+// the line breaks inside the strings, so
+// a message can't be cut in the middle
+pub struct JsonScanner<R> {
+    frames: Frames<R>,
+}
+
+impl<R: Read> JsonScanner<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> JsonScanner<R> {
+        JsonScanner::with_deadlines(reader, cap, ReadDeadlines::default())
+    }
+
+    pub fn with_deadlines(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> JsonScanner<R> {
+        JsonScanner {
+            frames: Frames::new(reader, cap, deadlines),
+        }
+    }
+
+    fn parse<M: DeserializeOwned + Tolerant>(&mut self) -> Result<M> {
+        loop {
+            let end = match self.frames.buffer().iter().position(|it| *it == b'\n') {
+                Some(index) => index + 1,
+                None => return Err(would_block()),
+            };
+
+            let line = self.frames.take(end);
+
+            // Empty lines are allowed,
+            // e.g. for the humans
+            // typing by hand
+            if line.iter().all(|it| it.is_ascii_whitespace()) {
+                continue
+            }
+
+            return from_json(&line)
+        }
+    }
+}
+
+impl<R, M> ReadMessage<M> for JsonScanner<R>
+where
+    R: Read,
+    M: DeserializeOwned + Tolerant,
+{
+    fn read_message(&mut self) -> Result<M> {
+        // We might've read multiple messages
+        // before, and now we need to return
+        // them one by one from the inner buffer
+        match self.parse() {
+            Err(error) if is_would_block_error(&error) => {}
+            other => return other,
+        }
+
+        self.frames.fill_in_time()?;
+        self.parse()
+    }
+}
+
+// Same as BsonReader, blocks, but
+// survives the read timeouts
 pub struct JsonReader<R> {
-    stream: CappedReader<R>,
+    backend: JsonScanner<R>,
 }
 
-impl<R> JsonReader<R> {
-    pub fn new(capped_reader: CappedReader<R>) -> JsonReader<R> {
+impl<R: Read> JsonReader<R> {
+    pub fn new(reader: R, cap: impl Into<Capacity>) -> JsonReader<R> {
+        JsonReader::with_deadlines(reader, cap, ReadDeadlines::default())
+    }
+
+    pub fn with_deadlines(reader: R, cap: impl Into<Capacity>, deadlines: ReadDeadlines) -> JsonReader<R> {
         JsonReader {
-            stream: capped_reader,
+            backend: JsonScanner::with_deadlines(reader, cap, deadlines),
         }
     }
 }
 
-impl<R: Read> ReadMessage<Value> for JsonReader<R> {
-    fn read_message(&mut self) -> Result<Value> {
-        let mut iterator = Deserializer::from_reader(&mut self.stream).into_iter::<Value>();
-
-        match iterator.next() {
-            Some(Ok(it)) => {
-                self.stream.clear();
-                Ok(it)
-            }
-            Some(Err(serde)) => {
-                Err(serde.into())
-            }
-            _ => {
-                Err(ErrorKind::NothingToRead.into())
+impl<R, M> ReadMessage<M> for JsonReader<R>
+where
+    R: Read,
+    M: DeserializeOwned + Tolerant,
+{
+    fn read_message(&mut self) -> Result<M> {
+        loop {
+            match self.backend.parse() {
+                Err(error) if is_would_block_error(&error) => {}
+                other => return other,
             }
+
+            self.backend.frames.fill_in_time()?;
         }
     }
 }
 
 pub struct JsonWriter<W> {
     stream: W,
+    cap: Capacity,
 }
 
 impl<W> JsonWriter<W> {
-    pub fn new(stream: W) -> JsonWriter<W> {
+    pub fn new(stream: W, cap: impl Into<Capacity>) -> JsonWriter<W> {
         JsonWriter {
             stream: stream,
+            cap: cap.into(),
         }
     }
 }
 
-impl<W: Write> WriteMessage<Value> for JsonWriter<W> {
-    fn write_message(&mut self, message: &Value) -> Result<()> {
-        self.stream.write(message.to_string().as_bytes())?;
+impl<W, M> WriteMessage<M> for JsonWriter<W>
+where
+    W: Write,
+    M: Serialize,
+{
+    fn write_message(&mut self, message: &M) -> Result<()> {
+        let mut buffer = serde_json::to_vec(message)?;
+        buffer.push(b'\n');
+
+        // The newline counts
+        // on the other side too
+        if buffer.len() > self.cap.get() {
+            return Err(ErrorKind::MessageSizeExceeded.into())
+        }
+
+        self.stream.write_all(&buffer)?;
         self.stream.flush()?;
         Ok(())
     }
@@ -1,41 +1,61 @@
 pub mod messages;
 pub mod sharers;
 pub mod helpers;
+pub mod heartbeat;
+pub mod session;
+pub mod limits;
+pub mod fragments;
 
-use std::net::{TcpStream, SocketAddr};
 use std::io::{Write};
 use std::fs::{File};
 use std::cmp::{min};
 
 use crate::{Result};
 use crate::shared::{Shared};
+use crate::transport::{BoxedTransport};
 
 use sharers::{FileSharer, FileSharers};
+use limits::{Limits, SharedLimits};
 
 pub struct Context {
-    stream: Shared<TcpStream>,
+    transport: Shared<BoxedTransport>,
+    // Remembered, since the address can't be
+    // queried once the other side resets
+    // the connection
+    address: Option<String>,
     reading_sharers: FileSharers,
     sending_sharers: Shared<Vec<FileSharer>>,
+    limits: SharedLimits,
     nexd_id: usize,
 }
 
 impl Context {
     pub fn new(
-        stream: Shared<TcpStream>,
+        transport: Shared<BoxedTransport>,
         reading_sharers: FileSharers,
         sending_sharers: Shared<Vec<FileSharer>>,
+        limits: SharedLimits,
     ) -> Context {
+        let address = match transport.read() {
+            Ok(it) => it.peer().ok(),
+            Err(_) => None,
+        };
+
         Context {
-            stream: stream,
+            transport: transport,
+            address: address,
             reading_sharers: reading_sharers,
             sending_sharers: sending_sharers,
+            limits: limits,
             nexd_id: 0,
         }
     }
 }
 
 pub trait Connection {
-    fn remote_address(&self) -> Result<SocketAddr>;
+    // Whatever identifies the other side,
+    // the address for TCP
+    fn remote_address(&self) -> Result<String>;
 
     fn free_id(&mut self) -> Result<usize>;
 
@@ -66,11 +86,26 @@ pub trait Connection {
     fn enqueu_sending_sharer(&mut self, sharer: FileSharer) -> Result<()>;
 
     fn sending_sharers_queue(&self) -> Result<Shared<Vec<FileSharer>>>;
+
+    fn receiving_sharers(&self) -> Result<FileSharers>;
+
+    // What the server has advertised,
+    // same for both halves
+    fn limits(&self) -> Result<Limits>;
+
+    fn set_limits(&mut self, limits: Limits) -> Result<()>;
+
+    // Breaks the connection for both
+    // sides, so the reading thread wakes up
+    fn close(&mut self) -> Result<()>;
 }
 
 impl Connection for Context {
-    fn remote_address(&self) -> Result<SocketAddr> {
-        Ok(self.stream.inner.read()?.peer_addr()?)
+    fn remote_address(&self) -> Result<String> {
+        match &self.address {
+            Some(it) => Ok(it.clone()),
+            None => self.transport.inner.read()?.peer(),
+        }
     }
 
     fn free_id(&mut self) -> Result<usize> {
@@ -126,7 +161,7 @@ impl Connection for Context {
 
         let writing_count = min(data.len(), sharer.rest());
 
-        sharer.file.write(&data[..writing_count])?;
+        sharer.file.write_all(&data[..writing_count])?;
         sharer.written += writing_count;
 
         Ok(sharer.written >= sharer.size)
@@ -149,6 +184,22 @@ impl Connection for Context {
     fn sending_sharers_queue(&self) -> Result<Shared<Vec<FileSharer>>> {
         Ok(self.sending_sharers.clone())
     }
+
+    fn receiving_sharers(&self) -> Result<FileSharers> {
+        Ok(self.reading_sharers.clone())
+    }
+
+    fn limits(&self) -> Result<Limits> {
+        self.limits.get()
+    }
+
+    fn set_limits(&mut self, limits: Limits) -> Result<()> {
+        self.limits.set(limits)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.transport.read()?.shutdown()
+    }
 }
 
 pub trait WithConnection {
@@ -157,7 +208,7 @@ pub trait WithConnection {
 }
 
 impl<W: WithConnection> Connection for W {
-    fn remote_address(&self) -> Result<SocketAddr> {
+    fn remote_address(&self) -> Result<String> {
         self.connection().remote_address()
     }
 
@@ -206,10 +257,26 @@ impl<W: WithConnection> Connection for W {
     fn sending_sharers_queue(&self) -> Result<Shared<Vec<FileSharer>>> {
         self.connection().sending_sharers_queue()
     }
+
+    fn receiving_sharers(&self) -> Result<FileSharers> {
+        self.connection().receiving_sharers()
+    }
+
+    fn limits(&self) -> Result<Limits> {
+        self.connection().limits()
+    }
+
+    fn set_limits(&mut self, limits: Limits) -> Result<()> {
+        self.connection_mut().set_limits(limits)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.connection_mut().close()
+    }
 }
 
 impl<T: Connection> Connection for Shared<T> {
-    fn remote_address(&self) -> Result<SocketAddr> {
+    fn remote_address(&self) -> Result<String> {
         self.inner.read()?.remote_address()
     }
 
@@ -258,4 +325,20 @@ impl<T: Connection> Connection for Shared<T> {
     fn sending_sharers_queue(&self) -> Result<Shared<Vec<FileSharer>>> {
         self.inner.write()?.sending_sharers_queue()
     }
+
+    fn receiving_sharers(&self) -> Result<FileSharers> {
+        self.inner.read()?.receiving_sharers()
+    }
+
+    fn limits(&self) -> Result<Limits> {
+        self.inner.read()?.limits()
+    }
+
+    fn set_limits(&mut self, limits: Limits) -> Result<()> {
+        self.inner.write()?.set_limits(limits)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.write()?.close()
+    }
 }
@@ -0,0 +1,128 @@
+use std::collections::{HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{ErrorKind, Result};
+
+use super::limits::{character_size};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Only has to differ from the ones
+// of the texts still on their way
+pub fn next_id() -> usize {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// At most `size` each, measured the same
+// way as the fields, but never in the
+// middle of a character
+pub fn split_text(text: &str, size: usize) -> Vec<String> {
+    let mut pieces = vec![];
+    let mut piece = String::new();
+    let mut piece_size = 0;
+
+    for it in text.chars() {
+        let it_size = character_size(it);
+
+        // A character larger than the
+        // piece itself goes whole
+        if piece_size + it_size > size && !piece.is_empty() {
+            pieces.push(std::mem::take(&mut piece));
+            piece_size = 0;
+        }
+
+        piece.push(it);
+        piece_size += it_size;
+    }
+
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+
+    pieces
+}
+
+struct Assembly {
+    text: String,
+    count: usize,
+}
+
+// Glues the pieces back together. The
+// limits are for all the unfinished
+// texts at once, so that a single
+// sender can't keep too much of them
+pub struct Assembler {
+    pending: HashMap<usize, Assembly>,
+    size: usize,
+    count: usize,
+    maximum_size: usize,
+    maximum_count: usize,
+}
+
+impl Assembler {
+    pub fn new(maximum_size: usize, maximum_count: usize) -> Assembler {
+        Assembler {
+            pending: HashMap::new(),
+            size: 0,
+            count: 0,
+            maximum_size: maximum_size,
+            maximum_count: maximum_count,
+        }
+    }
+
+    // The whole text once the
+    // last piece has arrived
+    pub fn push(&mut self, id: usize, index: usize, text: &str, last: bool) -> Result<Option<String>> {
+        // Starting over
+        if index == 0 {
+            self.forget(id);
+        }
+
+        let expected = self.pending.get(&id).map(|it| it.count).unwrap_or(0);
+
+        if index != expected {
+            self.forget(id);
+
+            let kind = ErrorKind::MalformedMessage {
+                message: format!("Expected the piece {} of the text #{}, but got {}", expected, id, index)
+            };
+
+            return Err(kind.into())
+        }
+
+        if self.size + text.len() > self.maximum_size || self.count + 1 > self.maximum_count {
+            self.forget(id);
+            return Err(ErrorKind::MessageSizeExceeded.into())
+        }
+
+        let assembly = self.pending.entry(id).or_insert(Assembly {
+            text: String::new(),
+            count: 0,
+        });
+
+        assembly.text.push_str(text);
+        assembly.count += 1;
+
+        self.size += text.len();
+        self.count += 1;
+
+        if !last {
+            return Ok(None)
+        }
+
+        Ok(self.forget(id))
+    }
+
+    pub fn is_pending(&self, id: usize) -> bool {
+        self.pending.contains_key(&id)
+    }
+
+    fn forget(&mut self, id: usize) -> Option<String> {
+        let assembly = self.pending.remove(&id)?;
+
+        self.size -= assembly.text.len();
+        self.count -= assembly.count;
+
+        Some(assembly.text)
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::{Duration, Instant};
+
+pub struct Heartbeat {
+    interval: Duration,
+    timeout: Duration,
+    last_heard: Instant,
+    last_ping: Instant,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, timeout: Duration) -> Heartbeat {
+        Heartbeat {
+            interval: interval,
+            timeout: timeout,
+            last_heard: Instant::now(),
+            last_ping: Instant::now(),
+        }
+    }
+
+    pub fn heard(&mut self) {
+        self.last_heard = Instant::now();
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.last_heard.elapsed() >= self.timeout
+    }
+
+    // Says whether the other side has been silent
+    // for long enough to check it, and assumes
+    // the ping is sent if so
+    pub fn should_ping(&mut self) -> bool {
+        let is_silent = self.last_heard.elapsed() >= self.interval;
+        let is_time = self.last_ping.elapsed() >= self.interval;
+
+        if is_silent && is_time {
+            self.last_ping = Instant::now();
+        }
+
+        is_silent && is_time
+    }
+}
@@ -7,7 +7,7 @@ use crate::communication::{
     WriteMessage,
 };
 
-use super::messages::{CommonMessage, CHUNK_SIZE};
+use super::messages::{CommonMessage};
 use super::sharers::{FileSharer};
 use super::{Connection};
 
@@ -16,11 +16,12 @@ use chrono::{Local};
 pub fn send_chunk<W>(
     writer: &mut W,
     sharer: &mut FileSharer,
+    chunk_size: usize,
 ) -> Result<()>
 where
     W: WriteMessage<CommonMessage>
 {
-    let mut buffer = [0u8; CHUNK_SIZE];
+    let mut buffer = vec![0u8; chunk_size];
     let read = sharer.file.read(&mut buffer)?;
 
     let chunk = CommonMessage::Chunk {
@@ -41,7 +42,7 @@ where
 
     if (time_point - sharer.old_time_point).num_seconds() >= 1 {
         sharer.old_time_point = time_point;
-        println!("(Console) File {} > {}%", sharer.name, sharer.percentage());
+        log::info!("File {} > {}%", sharer.name, sharer.percentage());
     }
 
     Ok(())
@@ -50,12 +51,13 @@ where
 pub fn send_file<W>(
     writer: &mut W,
     sharer: &mut FileSharer,
+    chunk_size: usize,
 ) -> Result<()>
 where
     W: WriteMessage<CommonMessage>
 {
     while sharer.rest() > 0 {
-        send_chunk(writer, sharer)?;
+        send_chunk(writer, sharer, chunk_size)?;
     }
 
     Ok(())
@@ -64,6 +66,7 @@ where
 pub fn send_file_non_blocking<W>(
     writer: &mut W,
     mut sharer: FileSharer,
+    chunk_size: usize,
 ) -> Result<()>
 where
     W: WriteMessage<CommonMessage>
@@ -73,7 +76,7 @@ where
     let mut the_writer = writer.clone();
 
     std::thread::spawn(move || {
-        with_error_report(|| send_file(&mut the_writer, &mut sharer));
+        with_error_report(|| send_file(&mut the_writer, &mut sharer, chunk_size));
     });
 
     Ok(())
@@ -87,14 +90,15 @@ where
 {
     let sending_sharers = connection.sending_sharers_queue()?;
 
-    if sending_sharers.read()?.len() == 0 {
+    if sending_sharers.read()?.is_empty() {
         return Ok(false)
     }
 
+    let chunk_size = connection.limits()?.chunk_size;
     let mut to_be_removed = vec![];
 
     for (index, it) in sending_sharers.write()?.iter_mut().enumerate() {
-        let result = send_chunk(connection, it);
+        let result = send_chunk(connection, it, chunk_size);
 
         if let Err(error) = result {
             if !is_would_block_error(&error) {
@@ -106,11 +110,8 @@ where
         }
     }
 
-    let mut removed_count = 0usize;
-
-    for it in to_be_removed {
+    for (removed_count, it) in to_be_removed.into_iter().enumerate() {
         sending_sharers.write()?.remove(it - removed_count);
-        removed_count += 1;
     }
 
     Ok(true)
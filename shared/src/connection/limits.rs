@@ -0,0 +1,287 @@
+use std::cmp::{min, max};
+
+use serde::{Serialize, Deserialize};
+
+use bson::{DateTime};
+
+use crate::{ErrorKind, Result};
+use crate::shared::{Shared, IntoShared};
+use crate::helpers::capped_reader::{Capacity};
+
+use crate::communication::{WriteMessage, WireFormat};
+use crate::communication::arson::{ArsonWriter};
+use crate::communication::json::{JsonWriter};
+use crate::communication::binary::{BinaryMessage, BinaryWriter};
+
+use super::messages::{ClientMessage, ServerMessage, CommonMessage, BanTarget};
+
+// Unless the server says otherwise
+pub const DEFAULT_MESSAGE_SIZE: usize = 1024;
+pub const DEFAULT_LONG_TEXT_SIZE: usize = 16 * 1024;
+pub const DEFAULT_TEXT_FRAGMENTS: usize = 64;
+
+// The largest number BSON can carry
+const LARGEST_NUMBER: usize = i64::MAX as usize;
+
+// How large things may get on a particular
+// connection. Nothing is hardcoded: the sizes
+// come from encoding the sample messages in
+// the format of the connection
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub format: WireFormat,
+    pub message_size: usize,
+    // The data of a single Chunk
+    pub chunk_size: usize,
+    pub text_size: usize,
+    pub name_size: usize,
+    pub password_size: usize,
+    pub file_name_size: usize,
+    // The text of a single TextFragment
+    pub fragment_size: usize,
+    // The whole text glued from the fragments,
+    // these two aren't derived but configured
+    pub long_text_size: usize,
+    pub text_fragments: usize,
+}
+
+impl Limits {
+    pub fn new(format: WireFormat, message_size: usize) -> Limits {
+        let mut limits = Limits {
+            format: format,
+            message_size: message_size,
+            chunk_size: chunk_size(format, message_size),
+            text_size: usize::MAX,
+            name_size: usize::MAX,
+            password_size: usize::MAX,
+            file_name_size: usize::MAX,
+            fragment_size: usize::MAX,
+            long_text_size: DEFAULT_LONG_TEXT_SIZE,
+            text_fragments: DEFAULT_TEXT_FRAGMENTS,
+        };
+
+        // The fields reach the other clients
+        // as they are, whatever those speak,
+        // so they must fit into every format
+        for it in WireFormat::ALL.iter() {
+            let text = text_size(*it, message_size);
+
+            limits.text_size = min(limits.text_size, text);
+            limits.password_size = min(limits.password_size, password_size(*it, message_size, text));
+            limits.file_name_size = min(limits.file_name_size, file_name_size(*it, message_size));
+            limits.fragment_size = min(limits.fragment_size, fragment_size(*it, message_size, text));
+        }
+
+        limits.name_size = limits.text_size;
+        limits
+    }
+
+    // The whole frame, in
+    // the connection format
+    pub fn measure<M: Serialize + BinaryMessage>(&self, message: &M) -> usize {
+        encoded_size(self.format, message)
+    }
+
+    pub fn fits<M: Serialize + BinaryMessage>(&self, message: &M) -> bool {
+        self.measure(message) <= self.message_size
+    }
+
+    // The fields the server checks one by one,
+    // a message may fit as a whole and still
+    // get its sender disconnected
+    pub fn check(&self, message: &ClientMessage) -> Result<()> {
+        let fields: Vec<(usize, usize)> = match message {
+            ClientMessage::Authenticate { name, password } |
+            ClientMessage::Register { name, password } => vec![
+                (field_size(name), self.name_size),
+                (field_size(password), self.password_size),
+            ],
+            ClientMessage::Text { text } => vec![(field_size(text), self.text_size)],
+            ClientMessage::TextFragment { text, .. } => vec![(field_size(text), self.fragment_size)],
+            ClientMessage::Rename { new_name } => vec![(field_size(new_name), self.name_size)],
+            ClientMessage::Promote { name } |
+            ClientMessage::Kick { name } |
+            ClientMessage::Mute { name } |
+            ClientMessage::Unmute { name } => vec![(field_size(name), self.name_size)],
+            ClientMessage::Ban { target, .. } => {
+                let value = match target {
+                    BanTarget::Name(it) | BanTarget::Session(it) | BanTarget::Ip(it) => it,
+                };
+
+                vec![(field_size(value), self.name_size)]
+            }
+            ClientMessage::RequestFileUpload { name, .. } |
+            ClientMessage::RequestFileDownload { name } => vec![(field_size(name), self.file_name_size)],
+            _ => vec![],
+        };
+
+        if fields.iter().any(|(size, limit)| size > limit) {
+            return Err(ErrorKind::MessageSizeExceeded.into())
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::new(WireFormat::default(), DEFAULT_MESSAGE_SIZE)
+    }
+}
+
+fn encoded_size<M: Serialize + BinaryMessage>(format: WireFormat, message: &M) -> usize {
+    let mut buffer = vec![];
+
+    let result = match format {
+        WireFormat::Arson => ArsonWriter::new(&mut buffer, usize::MAX).write_message(message),
+        WireFormat::Json => JsonWriter::new(&mut buffer, usize::MAX).write_message(message),
+        WireFormat::Binary => BinaryWriter::new(&mut buffer, usize::MAX).write_message(message),
+    };
+
+    match result {
+        Ok(()) => buffer.len(),
+        Err(_) => usize::MAX,
+    }
+}
+
+// The largest n for which the message still
+// fits, given that it only grows with n
+fn largest_fitting(message_size: usize, measure: impl Fn(usize) -> usize) -> usize {
+    let mut low = 0;
+    let mut high = message_size;
+
+    if measure(low) > message_size {
+        return 0
+    }
+
+    while low < high {
+        let middle = low + (high - low).div_ceil(2);
+
+        if measure(middle) <= message_size {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+
+    low
+}
+
+// The room a character takes in the roomiest
+// format: JSON escapes the quotes, backslashes
+// and control characters, up to \u00XX for
+// a single byte
+pub fn character_size(character: char) -> usize {
+    match character {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' => 2,
+        it if (it as u32) < 0x20 => 6,
+        it => it.len_utf8(),
+    }
+}
+
+// What the field sizes are checked against, so
+// that a field that fits takes no more room in
+// any format than the filler it's measured with
+pub fn field_size(text: &str) -> usize {
+    text.chars().map(character_size).sum()
+}
+
+fn filler(size: usize) -> String {
+    "x".repeat(size)
+}
+
+fn chunk_size(format: WireFormat, message_size: usize) -> usize {
+    let chunk = |size| CommonMessage::Chunk {
+        // The longest byte in JSON
+        data: vec![255; size],
+        id: LARGEST_NUMBER,
+    };
+
+    largest_fitting(message_size, |size| max(
+        encoded_size(format, &ClientMessage::Common { common: chunk(size) }),
+        encoded_size(format, &ServerMessage::Common { common: chunk(size) }),
+    ))
+}
+
+// Both the text and the name of
+// the author, half of the space each
+fn text_size(format: WireFormat, message_size: usize) -> usize {
+    largest_fitting(message_size, |size| encoded_size(format, &ServerMessage::Text {
+        text: filler(size),
+        name: filler(size),
+        time: DateTime::from_millis(i64::MIN),
+    }))
+}
+
+fn password_size(format: WireFormat, message_size: usize, name_size: usize) -> usize {
+    largest_fitting(message_size, |size| encoded_size(format, &ClientMessage::Authenticate {
+        name: filler(name_size),
+        password: filler(size),
+    }))
+}
+
+fn fragment_size(format: WireFormat, message_size: usize, name_size: usize) -> usize {
+    largest_fitting(message_size, |size| max(
+        encoded_size(format, &ClientMessage::TextFragment {
+            text: filler(size),
+            id: LARGEST_NUMBER,
+            index: LARGEST_NUMBER,
+            last: false,
+        }),
+        encoded_size(format, &ServerMessage::TextFragment {
+            text: filler(size),
+            name: filler(name_size),
+            time: DateTime::from_millis(i64::MIN),
+            id: LARGEST_NUMBER,
+            index: LARGEST_NUMBER,
+            last: false,
+        }),
+    ))
+}
+
+fn file_name_size(format: WireFormat, message_size: usize) -> usize {
+    largest_fitting(message_size, |size| max(
+        encoded_size(format, &ClientMessage::RequestFileUpload {
+            name: filler(size),
+            size: LARGEST_NUMBER,
+            id: LARGEST_NUMBER,
+        }),
+        encoded_size(format, &ServerMessage::AgreeFileDownload {
+            name: filler(size),
+            size: LARGEST_NUMBER,
+            id: LARGEST_NUMBER,
+        }),
+    ))
+}
+
+// Both halves of a connection see the
+// same limits, and the reader follows
+// the size of the message
+#[derive(Clone)]
+pub struct SharedLimits {
+    limits: Shared<Limits>,
+    capacity: Capacity,
+}
+
+impl SharedLimits {
+    pub fn new(limits: Limits) -> SharedLimits {
+        SharedLimits {
+            capacity: Capacity::new(limits.message_size),
+            limits: limits.to_shared(),
+        }
+    }
+
+    pub fn capacity(&self) -> Capacity {
+        self.capacity.clone()
+    }
+
+    pub fn get(&self) -> Result<Limits> {
+        Ok(*self.limits.read()?)
+    }
+
+    pub fn set(&self, limits: Limits) -> Result<()> {
+        *self.limits.write()? = limits;
+        self.capacity.set(limits.message_size);
+        Ok(())
+    }
+}
@@ -1,36 +1,129 @@
+mod binary;
+
 use std::fmt::{Display, Formatter};
 
 use chrono::{Local};
 
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{self, Visitor, MapAccess, IgnoredAny};
 
 use bson::{DateTime};
 
-pub const MAXIMUM_MESSAGE_SIZE: usize = 1024;
+use crate::communication::{Tolerant, variants_of};
 
-// Found empirically
-pub const MINIMUM_TEXT_MESSAGE_SIZE: usize = 52;
-pub const MAXIMUM_TEXT_MESSAGE_CONTENT: usize = MAXIMUM_MESSAGE_SIZE - MINIMUM_TEXT_MESSAGE_SIZE;
+use super::limits::{Limits};
 
-pub const MAXIMUM_TEXT_SIZE: usize = MAXIMUM_TEXT_MESSAGE_CONTENT / 2;
-pub const MAXIMUM_NAME_SIZE: usize = MAXIMUM_TEXT_SIZE;
+#[derive(Serialize, Debug, Clone)]
+pub enum CommonMessage {
+    Chunk { data: Vec<u8>, id: usize },
+    Ping,
+    Pong,
+    // Sent by a newer version, never
+    // sent by this version itself
+    #[serde(skip)]
+    Unknown { tag: String },
+}
 
-pub const MINIMUM_CLIENT_REQUEST_FILE_UPLOAD_SIZE: usize = 66;
-pub const MAXIMUM_FILE_NAME_SIZE: usize = MAXIMUM_MESSAGE_SIZE - MINIMUM_CLIENT_REQUEST_FILE_UPLOAD_SIZE;
+#[derive(Deserialize)]
+struct ChunkFields {
+    data: Vec<u8>,
+    id: usize,
+}
 
-pub const CHUNK_SIZE: usize = 100;
+struct CommonMessageVisitor;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum CommonMessage {
-    Chunk { data: Vec<u8>, id: usize },
+impl<'de> Visitor<'de> for CommonMessageVisitor {
+    type Value = CommonMessage;
+
+    fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "a common message")
+    }
+
+    // The unit variants
+    // are just the names
+    fn visit_str<E: de::Error>(self, tag: &str) -> std::result::Result<CommonMessage, E> {
+        let message = match tag {
+            "Ping" => CommonMessage::Ping,
+            "Pong" => CommonMessage::Pong,
+            _ => CommonMessage::Unknown { tag: tag.to_owned() },
+        };
+
+        Ok(message)
+    }
+
+    // The rest have a single key
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<CommonMessage, A::Error> {
+        let tag: String = match map.next_key()? {
+            Some(it) => it,
+            None => return Err(de::Error::custom("A common message without a name")),
+        };
+
+        let message = match tag.as_str() {
+            "Chunk" => {
+                let fields: ChunkFields = map.next_value()?;
+                CommonMessage::Chunk { data: fields.data, id: fields.id }
+            }
+            _ => {
+                map.next_value::<IgnoredAny>()?;
+                self.visit_str(&tag)?
+            }
+        };
+
+        Ok(message)
+    }
+}
+
+// Same as derived, except that the outer
+// message is known, so the unknown variants
+// can't be caught by its tag the way the
+// Tolerant messages are
+impl<'de> Deserialize<'de> for CommonMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<CommonMessage, D::Error> {
+        deserializer.deserialize_any(CommonMessageVisitor)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum BanTarget {
+    Name(String),
+    // The full address of a connection
+    Session(String),
+    Ip(String),
+}
+
+impl Display for BanTarget {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            BanTarget::Name(it) => write!(formatter, "the name {}", it),
+            BanTarget::Session(it) => write!(formatter, "the session {}", it),
+            BanTarget::Ip(it) => write!(formatter, "the IP {}", it),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessage {
+    // Handshake
+    Join,
+    Authenticate { name: String, password: String },
+    Resume { token: String },
+
     // Main
     Text { text: String },
+    // A piece of a text too long for a single
+    // message, the pieces go in order
+    TextFragment { text: String, id: usize, index: usize, last: bool },
     Leave,
     Rename { new_name: String },
+    Register { name: String, password: String },
+    ListUsers,
+
+    // Moderation
+    Promote { name: String },
+    Kick { name: String },
+    Ban { target: BanTarget, duration_seconds: Option<u64> },
+    Mute { name: String },
+    Unmute { name: String },
 
     // Sending files
     Common { common: CommonMessage },
@@ -38,18 +131,43 @@ pub enum ClientMessage {
     RequestFileDownload { name: String },
     AgreeFileDownload { id: usize },
     DeclineFileDownload { id: usize },
+
+    // Sent by a newer client, never
+    // sent by this version itself
+    #[serde(skip)]
+    Unknown { tag: String, raw: Vec<u8> },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ServerMessage {
     // Main
     Text { text: String, name: String, time: DateTime },
+    // The same, but the text is glued back by the client,
+    // the ids are the server's own
+    TextFragment { text: String, name: String, time: DateTime, id: usize, index: usize, last: bool },
     NewUser { name: String, time: DateTime },
     Interrupt { name: String, time: DateTime },
     UserLeaves { name: String, time: DateTime },
     Support { text: String },
     UserRenamed { old_name: String, new_name: String },
     NewFile { name: String },
+    ResumeToken { token: String },
+    // What the connection can carry,
+    // sent right after the handshake
+    Limits { limits: Limits },
+    // The list may be split into several
+    // messages to fit the maximum size
+    UserList { names: Vec<String>, last: bool },
+
+    // Moderation
+    UserPromoted { name: String, by: String },
+    UserKicked { name: String, by: String },
+    UserBanned { target: BanTarget, by: String, until: Option<DateTime> },
+    UserMuted { name: String, by: String },
+    UserUnmuted { name: String, by: String },
+    // The server closes the connection
+    // and doesn't want the client back
+    Goodbye { reason: String },
 
     // Sending files
     Common { common: CommonMessage },
@@ -57,6 +175,119 @@ pub enum ServerMessage {
     DeclineFileUpload { id: usize, reason: String },
     AgreeFileDownload { name: String, size: usize, id: usize },
     DeclineFileDownload { name: String, reason: String },
+
+    // Sent by a newer server, never
+    // sent by this version itself
+    #[serde(skip)]
+    Unknown { tag: String, raw: Vec<u8> },
+}
+
+// The messages one side sends, the
+// common ones travel inside them
+pub trait Envelope {
+    fn wrap(common: CommonMessage) -> Self;
+}
+
+impl Tolerant for ClientMessage {
+    fn is_known(tag: &str) -> bool {
+        variants_of::<ClientMessage>().contains(&tag)
+    }
+
+    fn unknown(tag: String, raw: Vec<u8>) -> ClientMessage {
+        ClientMessage::Unknown { tag: tag, raw: raw }
+    }
+}
+
+impl Tolerant for ServerMessage {
+    fn is_known(tag: &str) -> bool {
+        variants_of::<ServerMessage>().contains(&tag)
+    }
+
+    fn unknown(tag: String, raw: Vec<u8>) -> ServerMessage {
+        ServerMessage::Unknown { tag: tag, raw: raw }
+    }
+}
+
+impl Envelope for ClientMessage {
+    fn wrap(common: CommonMessage) -> ClientMessage {
+        ClientMessage::Common { common }
+    }
+}
+
+impl Envelope for ServerMessage {
+    fn wrap(common: CommonMessage) -> ServerMessage {
+        ServerMessage::Common { common }
+    }
+}
+
+impl CommonMessage {
+    // For the statistics, the data
+    // itself doesn't matter there
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CommonMessage::Chunk { .. } => "Chunk",
+            CommonMessage::Ping => "Ping",
+            CommonMessage::Pong => "Pong",
+            CommonMessage::Unknown { .. } => "Unknown",
+        }
+    }
+}
+
+impl ClientMessage {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientMessage::Join => "Join",
+            ClientMessage::Authenticate { .. } => "Authenticate",
+            ClientMessage::Resume { .. } => "Resume",
+            ClientMessage::Text { .. } => "Text",
+            ClientMessage::TextFragment { .. } => "TextFragment",
+            ClientMessage::Leave => "Leave",
+            ClientMessage::Rename { .. } => "Rename",
+            ClientMessage::Register { .. } => "Register",
+            ClientMessage::ListUsers => "ListUsers",
+            ClientMessage::Promote { .. } => "Promote",
+            ClientMessage::Kick { .. } => "Kick",
+            ClientMessage::Ban { .. } => "Ban",
+            ClientMessage::Mute { .. } => "Mute",
+            ClientMessage::Unmute { .. } => "Unmute",
+            ClientMessage::Common { common } => common.kind(),
+            ClientMessage::RequestFileUpload { .. } => "RequestFileUpload",
+            ClientMessage::RequestFileDownload { .. } => "RequestFileDownload",
+            ClientMessage::AgreeFileDownload { .. } => "AgreeFileDownload",
+            ClientMessage::DeclineFileDownload { .. } => "DeclineFileDownload",
+            ClientMessage::Unknown { .. } => "Unknown",
+        }
+    }
+}
+
+impl ServerMessage {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerMessage::Text { .. } => "Text",
+            ServerMessage::TextFragment { .. } => "TextFragment",
+            ServerMessage::NewUser { .. } => "NewUser",
+            ServerMessage::Interrupt { .. } => "Interrupt",
+            ServerMessage::UserLeaves { .. } => "UserLeaves",
+            ServerMessage::Support { .. } => "Support",
+            ServerMessage::UserRenamed { .. } => "UserRenamed",
+            ServerMessage::NewFile { .. } => "NewFile",
+            ServerMessage::ResumeToken { .. } => "ResumeToken",
+            ServerMessage::Limits { .. } => "Limits",
+            ServerMessage::UserList { .. } => "UserList",
+            ServerMessage::UserPromoted { .. } => "UserPromoted",
+            ServerMessage::UserKicked { .. } => "UserKicked",
+            ServerMessage::UserBanned { .. } => "UserBanned",
+            ServerMessage::UserMuted { .. } => "UserMuted",
+            ServerMessage::UserUnmuted { .. } => "UserUnmuted",
+            ServerMessage::Goodbye { .. } => "Goodbye",
+            ServerMessage::Common { common } => common.kind(),
+            ServerMessage::AgreeFileUpload { .. } => "AgreeFileUpload",
+            ServerMessage::DeclineFileUpload { .. } => "DeclineFileUpload",
+            ServerMessage::AgreeFileDownload { .. } => "AgreeFileDownload",
+            ServerMessage::DeclineFileDownload { .. } => "DeclineFileDownload",
+            ServerMessage::Unknown { .. } => "Unknown",
+        }
+    }
 }
 
 impl Display for ServerMessage {
@@ -65,7 +296,15 @@ impl Display for ServerMessage {
             ServerMessage::Text { text, name, time } => {
                 let the_time: chrono::DateTime<Local> = time.to_chrono().into();
                 let formatted = the_time.format("%e %b %Y %T");
-                write!(formatter, "<{}> [{}] {}", formatted, name, text)
+                let header = format!("<{}> [{}] ", formatted, name);
+
+                // The rest of the lines go
+                // under the first one
+                let indent = format!("\n{}", " ".repeat(header.chars().count()));
+                write!(formatter, "{}{}", header, text.replace('\n', &indent))
+            }
+            ServerMessage::TextFragment { name, index, .. } => {
+                write!(formatter, "(Server) Here's the piece {} of what {} says", index, name)
             }
             ServerMessage::NewUser { name, .. } => {
                 write!(formatter, "~~ Meet our new mate: {} ~~", name)
@@ -85,6 +324,40 @@ impl Display for ServerMessage {
             ServerMessage::NewFile { name } => {
                 write!(formatter, "~~ And the new file is {} ~~", &name)
             }
+            ServerMessage::ResumeToken { .. } => {
+                write!(formatter, "(Server) Here's your ticket back in case you get lost")
+            }
+            ServerMessage::Limits { limits } => {
+                write!(formatter, "(Server) Keep your texts under {} bytes, please", limits.text_size)
+            }
+            ServerMessage::UserList { names, .. } => {
+                write!(formatter, "(Server) Online: {}", names.join(", "))
+            }
+            ServerMessage::UserPromoted { name, by } => {
+                write!(formatter, "~~ {} has made {} an operator ~~", &by, &name)
+            }
+            ServerMessage::UserKicked { name, by } => {
+                write!(formatter, "~~ {} has been kicked out by {} ~~", &name, &by)
+            }
+            ServerMessage::UserBanned { target, by, until } => match until {
+                Some(time) => {
+                    let the_time: chrono::DateTime<Local> = time.to_chrono().into();
+                    let formatted = the_time.format("%e %b %Y %T");
+                    write!(formatter, "~~ {} has banned {} until {} ~~", &by, &target, formatted)
+                }
+                None => {
+                    write!(formatter, "~~ {} has banned {} for good ~~", &by, &target)
+                }
+            }
+            ServerMessage::UserMuted { name, by } => {
+                write!(formatter, "~~ {} has muted {} ~~", &by, &name)
+            }
+            ServerMessage::UserUnmuted { name, by } => {
+                write!(formatter, "~~ {} has unmuted {} ~~", &by, &name)
+            }
+            ServerMessage::Goodbye { reason } => {
+                write!(formatter, "(Server) {}", &reason)
+            }
             ServerMessage::AgreeFileUpload { id } => {
                 write!(formatter, "(Server) Sure, I'm ready to accept #{}", &id)
             }
@@ -97,10 +370,22 @@ impl Display for ServerMessage {
             ServerMessage::DeclineFileDownload { name, reason } => {
                 write!(formatter, "(Server) Nah, I won't give you {}. {}", &name, &reason)
             }
+            ServerMessage::Unknown { tag, .. } => {
+                write!(formatter, "(Server) Something called {} I don't understand", &tag)
+            }
             ServerMessage::Common { common } => match common {
                 CommonMessage::Chunk { .. } => {
                     write!(formatter, "(Server) Here are some bytes for you")
                 }
+                CommonMessage::Ping => {
+                    write!(formatter, "(Server) Are you still there?")
+                }
+                CommonMessage::Pong => {
+                    write!(formatter, "(Server) Still here")
+                }
+                CommonMessage::Unknown { tag } => {
+                    write!(formatter, "(Server) Something called {} I don't understand", &tag)
+                }
             }
         }
     }
@@ -0,0 +1,516 @@
+// The tags never change once assigned,
+// the new variants get the new ones
+
+use bson::{DateTime};
+
+use crate::{Result};
+use crate::communication::{WireFormat};
+use crate::communication::binary::{BinaryMessage, Encoder, Decoder, malformed};
+
+use super::{CommonMessage, BanTarget, ClientMessage, ServerMessage};
+use crate::connection::limits::{Limits};
+
+fn encode_time(encoder: &mut Encoder, time: &DateTime) {
+    encoder.signed(time.timestamp_millis());
+}
+
+fn decode_time(decoder: &mut Decoder) -> Result<DateTime> {
+    Ok(DateTime::from_millis(decoder.signed()?))
+}
+
+impl BinaryMessage for CommonMessage {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            CommonMessage::Chunk { data, id } => {
+                encoder.varint(0);
+                encoder.bytes(data);
+                encoder.size(*id);
+            }
+            CommonMessage::Ping => encoder.varint(1),
+            CommonMessage::Pong => encoder.varint(2),
+            // Never sent, and nobody passes
+            // the common messages on
+            CommonMessage::Unknown { .. } => {}
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<CommonMessage> {
+        match decoder.varint()? {
+            0 => Ok(CommonMessage::Chunk {
+                data: decoder.bytes()?,
+                id: decoder.size()?,
+            }),
+            1 => Ok(CommonMessage::Ping),
+            2 => Ok(CommonMessage::Pong),
+            // The rest of the message
+            // is left unread
+            tag => Ok(CommonMessage::Unknown { tag: tag.to_string() }),
+        }
+    }
+}
+
+impl BinaryMessage for BanTarget {
+    fn encode(&self, encoder: &mut Encoder) {
+        let (tag, value) = match self {
+            BanTarget::Name(it) => (0, it),
+            BanTarget::Session(it) => (1, it),
+            BanTarget::Ip(it) => (2, it),
+        };
+
+        encoder.varint(tag);
+        encoder.string(value);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<BanTarget> {
+        let tag = decoder.varint()?;
+        let value = decoder.string()?;
+
+        match tag {
+            0 => Ok(BanTarget::Name(value)),
+            1 => Ok(BanTarget::Session(value)),
+            2 => Ok(BanTarget::Ip(value)),
+            _ => Err(malformed("Unknown ban target")),
+        }
+    }
+}
+
+impl BinaryMessage for WireFormat {
+    fn encode(&self, encoder: &mut Encoder) {
+        let tag = match self {
+            WireFormat::Arson => 0,
+            WireFormat::Json => 1,
+            WireFormat::Binary => 2,
+        };
+
+        encoder.varint(tag);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<WireFormat> {
+        match decoder.varint()? {
+            0 => Ok(WireFormat::Arson),
+            1 => Ok(WireFormat::Json),
+            2 => Ok(WireFormat::Binary),
+            _ => Err(malformed("Unknown wire format")),
+        }
+    }
+}
+
+impl BinaryMessage for Limits {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.format.encode(encoder);
+        encoder.size(self.message_size);
+        encoder.size(self.chunk_size);
+        encoder.size(self.text_size);
+        encoder.size(self.name_size);
+        encoder.size(self.password_size);
+        encoder.size(self.file_name_size);
+        encoder.size(self.fragment_size);
+        encoder.size(self.long_text_size);
+        encoder.size(self.text_fragments);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Limits> {
+        Ok(Limits {
+            format: WireFormat::decode(decoder)?,
+            message_size: decoder.size()?,
+            chunk_size: decoder.size()?,
+            text_size: decoder.size()?,
+            name_size: decoder.size()?,
+            password_size: decoder.size()?,
+            file_name_size: decoder.size()?,
+            fragment_size: decoder.size()?,
+            long_text_size: decoder.size()?,
+            text_fragments: decoder.size()?,
+        })
+    }
+}
+
+impl BinaryMessage for ClientMessage {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            ClientMessage::Join => encoder.varint(0),
+            ClientMessage::Authenticate { name, password } => {
+                encoder.varint(1);
+                encoder.string(name);
+                encoder.string(password);
+            }
+            ClientMessage::Resume { token } => {
+                encoder.varint(2);
+                encoder.string(token);
+            }
+            ClientMessage::Text { text } => {
+                encoder.varint(3);
+                encoder.string(text);
+            }
+            ClientMessage::Leave => encoder.varint(4),
+            ClientMessage::Rename { new_name } => {
+                encoder.varint(5);
+                encoder.string(new_name);
+            }
+            ClientMessage::Register { name, password } => {
+                encoder.varint(6);
+                encoder.string(name);
+                encoder.string(password);
+            }
+            ClientMessage::ListUsers => encoder.varint(7),
+            ClientMessage::Promote { name } => {
+                encoder.varint(8);
+                encoder.string(name);
+            }
+            ClientMessage::Kick { name } => {
+                encoder.varint(9);
+                encoder.string(name);
+            }
+            ClientMessage::Ban { target, duration_seconds } => {
+                encoder.varint(10);
+                target.encode(encoder);
+                encoder.option(duration_seconds, |it, value| it.varint(*value));
+            }
+            ClientMessage::Mute { name } => {
+                encoder.varint(11);
+                encoder.string(name);
+            }
+            ClientMessage::Unmute { name } => {
+                encoder.varint(12);
+                encoder.string(name);
+            }
+            ClientMessage::Common { common } => {
+                encoder.varint(13);
+                common.encode(encoder);
+            }
+            ClientMessage::RequestFileUpload { name, size, id } => {
+                encoder.varint(14);
+                encoder.string(name);
+                encoder.size(*size);
+                encoder.size(*id);
+            }
+            ClientMessage::RequestFileDownload { name } => {
+                encoder.varint(15);
+                encoder.string(name);
+            }
+            ClientMessage::AgreeFileDownload { id } => {
+                encoder.varint(16);
+                encoder.size(*id);
+            }
+            ClientMessage::DeclineFileDownload { id } => {
+                encoder.varint(17);
+                encoder.size(*id);
+            }
+            ClientMessage::TextFragment { text, id, index, last } => {
+                encoder.varint(18);
+                encoder.string(text);
+                encoder.size(*id);
+                encoder.size(*index);
+                encoder.boolean(*last);
+            }
+            // Passed on the way it has
+            // arrived, tag included
+            ClientMessage::Unknown { raw, .. } => encoder.raw(raw),
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<ClientMessage> {
+        let raw = decoder.rest();
+
+        let message = match decoder.varint()? {
+            0 => ClientMessage::Join,
+            1 => ClientMessage::Authenticate {
+                name: decoder.string()?,
+                password: decoder.string()?,
+            },
+            2 => ClientMessage::Resume {
+                token: decoder.string()?,
+            },
+            3 => ClientMessage::Text {
+                text: decoder.string()?,
+            },
+            4 => ClientMessage::Leave,
+            5 => ClientMessage::Rename {
+                new_name: decoder.string()?,
+            },
+            6 => ClientMessage::Register {
+                name: decoder.string()?,
+                password: decoder.string()?,
+            },
+            7 => ClientMessage::ListUsers,
+            8 => ClientMessage::Promote {
+                name: decoder.string()?,
+            },
+            9 => ClientMessage::Kick {
+                name: decoder.string()?,
+            },
+            10 => ClientMessage::Ban {
+                target: BanTarget::decode(decoder)?,
+                duration_seconds: decoder.option(|it| it.varint())?,
+            },
+            11 => ClientMessage::Mute {
+                name: decoder.string()?,
+            },
+            12 => ClientMessage::Unmute {
+                name: decoder.string()?,
+            },
+            13 => ClientMessage::Common {
+                common: CommonMessage::decode(decoder)?,
+            },
+            14 => ClientMessage::RequestFileUpload {
+                name: decoder.string()?,
+                size: decoder.size()?,
+                id: decoder.size()?,
+            },
+            15 => ClientMessage::RequestFileDownload {
+                name: decoder.string()?,
+            },
+            16 => ClientMessage::AgreeFileDownload {
+                id: decoder.size()?,
+            },
+            17 => ClientMessage::DeclineFileDownload {
+                id: decoder.size()?,
+            },
+            18 => ClientMessage::TextFragment {
+                text: decoder.string()?,
+                id: decoder.size()?,
+                index: decoder.size()?,
+                last: decoder.boolean()?,
+            },
+            tag => ClientMessage::Unknown {
+                tag: tag.to_string(),
+                raw: raw.to_vec(),
+            },
+        };
+
+        Ok(message)
+    }
+}
+
+impl BinaryMessage for ServerMessage {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            ServerMessage::Text { text, name, time } => {
+                encoder.varint(0);
+                encoder.string(text);
+                encoder.string(name);
+                encode_time(encoder, time);
+            }
+            ServerMessage::NewUser { name, time } => {
+                encoder.varint(1);
+                encoder.string(name);
+                encode_time(encoder, time);
+            }
+            ServerMessage::Interrupt { name, time } => {
+                encoder.varint(2);
+                encoder.string(name);
+                encode_time(encoder, time);
+            }
+            ServerMessage::UserLeaves { name, time } => {
+                encoder.varint(3);
+                encoder.string(name);
+                encode_time(encoder, time);
+            }
+            ServerMessage::Support { text } => {
+                encoder.varint(4);
+                encoder.string(text);
+            }
+            ServerMessage::UserRenamed { old_name, new_name } => {
+                encoder.varint(5);
+                encoder.string(old_name);
+                encoder.string(new_name);
+            }
+            ServerMessage::NewFile { name } => {
+                encoder.varint(6);
+                encoder.string(name);
+            }
+            ServerMessage::ResumeToken { token } => {
+                encoder.varint(7);
+                encoder.string(token);
+            }
+            ServerMessage::UserList { names, last } => {
+                encoder.varint(8);
+                encoder.size(names.len());
+
+                for it in names {
+                    encoder.string(it);
+                }
+
+                encoder.boolean(*last);
+            }
+            ServerMessage::UserPromoted { name, by } => {
+                encoder.varint(9);
+                encoder.string(name);
+                encoder.string(by);
+            }
+            ServerMessage::UserKicked { name, by } => {
+                encoder.varint(10);
+                encoder.string(name);
+                encoder.string(by);
+            }
+            ServerMessage::UserBanned { target, by, until } => {
+                encoder.varint(11);
+                target.encode(encoder);
+                encoder.string(by);
+                encoder.option(until, encode_time);
+            }
+            ServerMessage::UserMuted { name, by } => {
+                encoder.varint(12);
+                encoder.string(name);
+                encoder.string(by);
+            }
+            ServerMessage::UserUnmuted { name, by } => {
+                encoder.varint(13);
+                encoder.string(name);
+                encoder.string(by);
+            }
+            ServerMessage::Goodbye { reason } => {
+                encoder.varint(14);
+                encoder.string(reason);
+            }
+            ServerMessage::Common { common } => {
+                encoder.varint(15);
+                common.encode(encoder);
+            }
+            ServerMessage::AgreeFileUpload { id } => {
+                encoder.varint(16);
+                encoder.size(*id);
+            }
+            ServerMessage::DeclineFileUpload { id, reason } => {
+                encoder.varint(17);
+                encoder.size(*id);
+                encoder.string(reason);
+            }
+            ServerMessage::AgreeFileDownload { name, size, id } => {
+                encoder.varint(18);
+                encoder.string(name);
+                encoder.size(*size);
+                encoder.size(*id);
+            }
+            ServerMessage::DeclineFileDownload { name, reason } => {
+                encoder.varint(19);
+                encoder.string(name);
+                encoder.string(reason);
+            }
+            ServerMessage::Limits { limits } => {
+                encoder.varint(20);
+                limits.encode(encoder);
+            }
+            ServerMessage::TextFragment { text, name, time, id, index, last } => {
+                encoder.varint(21);
+                encoder.string(text);
+                encoder.string(name);
+                encode_time(encoder, time);
+                encoder.size(*id);
+                encoder.size(*index);
+                encoder.boolean(*last);
+            }
+            ServerMessage::Unknown { raw, .. } => encoder.raw(raw),
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<ServerMessage> {
+        let raw = decoder.rest();
+
+        let message = match decoder.varint()? {
+            0 => ServerMessage::Text {
+                text: decoder.string()?,
+                name: decoder.string()?,
+                time: decode_time(decoder)?,
+            },
+            1 => ServerMessage::NewUser {
+                name: decoder.string()?,
+                time: decode_time(decoder)?,
+            },
+            2 => ServerMessage::Interrupt {
+                name: decoder.string()?,
+                time: decode_time(decoder)?,
+            },
+            3 => ServerMessage::UserLeaves {
+                name: decoder.string()?,
+                time: decode_time(decoder)?,
+            },
+            4 => ServerMessage::Support {
+                text: decoder.string()?,
+            },
+            5 => ServerMessage::UserRenamed {
+                old_name: decoder.string()?,
+                new_name: decoder.string()?,
+            },
+            6 => ServerMessage::NewFile {
+                name: decoder.string()?,
+            },
+            7 => ServerMessage::ResumeToken {
+                token: decoder.string()?,
+            },
+            8 => {
+                let count = decoder.size()?;
+                let mut names = vec![];
+
+                for _ in 0..count {
+                    names.push(decoder.string()?);
+                }
+
+                ServerMessage::UserList {
+                    names: names,
+                    last: decoder.boolean()?,
+                }
+            }
+            9 => ServerMessage::UserPromoted {
+                name: decoder.string()?,
+                by: decoder.string()?,
+            },
+            10 => ServerMessage::UserKicked {
+                name: decoder.string()?,
+                by: decoder.string()?,
+            },
+            11 => ServerMessage::UserBanned {
+                target: BanTarget::decode(decoder)?,
+                by: decoder.string()?,
+                until: decoder.option(decode_time)?,
+            },
+            12 => ServerMessage::UserMuted {
+                name: decoder.string()?,
+                by: decoder.string()?,
+            },
+            13 => ServerMessage::UserUnmuted {
+                name: decoder.string()?,
+                by: decoder.string()?,
+            },
+            14 => ServerMessage::Goodbye {
+                reason: decoder.string()?,
+            },
+            15 => ServerMessage::Common {
+                common: CommonMessage::decode(decoder)?,
+            },
+            16 => ServerMessage::AgreeFileUpload {
+                id: decoder.size()?,
+            },
+            17 => ServerMessage::DeclineFileUpload {
+                id: decoder.size()?,
+                reason: decoder.string()?,
+            },
+            18 => ServerMessage::AgreeFileDownload {
+                name: decoder.string()?,
+                size: decoder.size()?,
+                id: decoder.size()?,
+            },
+            19 => ServerMessage::DeclineFileDownload {
+                name: decoder.string()?,
+                reason: decoder.string()?,
+            },
+            20 => ServerMessage::Limits {
+                limits: Limits::decode(decoder)?,
+            },
+            21 => ServerMessage::TextFragment {
+                text: decoder.string()?,
+                name: decoder.string()?,
+                time: decode_time(decoder)?,
+                id: decoder.size()?,
+                index: decoder.size()?,
+                last: decoder.boolean()?,
+            },
+            tag => ServerMessage::Unknown {
+                tag: tag.to_string(),
+                raw: raw.to_vec(),
+            },
+        };
+
+        Ok(message)
+    }
+}
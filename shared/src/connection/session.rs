@@ -0,0 +1,169 @@
+use std::fs::{File};
+use std::marker::{PhantomData};
+
+use crate::{Result};
+use crate::shared::{Shared};
+use crate::communication::{ReadMessage, WriteMessage};
+
+use super::{Connection};
+use super::messages::{CommonMessage, Envelope};
+use super::sharers::{FileSharer, FileSharers};
+use super::limits::{Limits};
+
+// What one side reads and writes, and in
+// which format. Any ReadMessage/WriteMessage
+// pair fits, the session does the rest
+pub trait Codec: 'static {
+    type Incoming;
+    type Outgoing: Envelope;
+    type Reader: ReadMessage<Self::Incoming> + Send + Sync;
+    type Writer: WriteMessage<Self::Outgoing> + Send + Sync;
+}
+
+// The format is only known at runtime,
+// e.g. once the client has told it
+pub struct BoxedCodec<In, Out> {
+    _messages: PhantomData<(In, Out)>,
+}
+
+impl<In: 'static, Out: Envelope + 'static> Codec for BoxedCodec<In, Out> {
+    type Incoming = In;
+    type Outgoing = Out;
+    type Reader = Box<dyn ReadMessage<In> + Send + Sync>;
+    type Writer = Box<dyn WriteMessage<Out> + Send + Sync>;
+}
+
+// The context knows about the connection and
+// the rest of the app, the codec knows how to
+// talk to the other side. Both halves of the
+// connection share the reader and the writer
+pub struct Session<C, K: Codec> {
+    context: Shared<C>,
+    reader: Shared<K::Reader>,
+    writer: Shared<K::Writer>,
+}
+
+impl<C, K: Codec> Session<C, K> {
+    pub fn new(
+        context: Shared<C>,
+        reader: Shared<K::Reader>,
+        writer: Shared<K::Writer>,
+    ) -> Session<C, K> {
+        Session {
+            context: context,
+            reader: reader,
+            writer: writer,
+        }
+    }
+
+    // For whatever the side
+    // adds on top of Connection
+    pub fn context(&self) -> &Shared<C> {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Shared<C> {
+        &mut self.context
+    }
+}
+
+impl<C, K: Codec> Clone for Session<C, K> {
+    fn clone(&self) -> Self {
+        Session {
+            context: self.context.clone(),
+            reader: self.reader.clone(),
+            writer: self.writer.clone(),
+        }
+    }
+}
+
+impl<C, K: Codec> ReadMessage<K::Incoming> for Session<C, K> {
+    fn read_message(&mut self) -> Result<K::Incoming> {
+        self.reader.read_message()
+    }
+}
+
+impl<C, K, O> WriteMessage<O> for Session<C, K>
+where
+    K: Codec<Outgoing = O>,
+    O: Envelope,
+{
+    fn write_message(&mut self, message: &O) -> Result<()> {
+        self.writer.write_message(message)
+    }
+}
+
+impl<C, K: Codec> WriteMessage<CommonMessage> for Session<C, K> {
+    fn write_message(&mut self, message: &CommonMessage) -> Result<()> {
+        let wrapped = K::Outgoing::wrap(message.clone());
+        self.writer.write_message(&wrapped)
+    }
+}
+
+impl<C: Connection, K: Codec> Connection for Session<C, K> {
+    fn remote_address(&self) -> Result<String> {
+        self.context.remote_address()
+    }
+
+    fn free_id(&mut self) -> Result<usize> {
+        self.context.free_id()
+    }
+
+    fn prepare_sharer(
+        &mut self,
+        path: &str,
+        file: File,
+        name: &str,
+    ) -> Result<()> {
+        self.context.prepare_sharer(path, file, name)
+    }
+
+    fn promote_sharer(
+        &mut self,
+        name: &str,
+        size: usize,
+        id: usize,
+    ) -> Result<()> {
+        self.context.promote_sharer(name, size, id)
+    }
+
+    fn accept_chunk(
+        &mut self,
+        data: &[u8],
+        id: usize,
+    ) -> Result<bool> {
+        self.context.accept_chunk(data, id)
+    }
+
+    fn remove_unpromoted_sharer(&mut self, name: &str) -> Result<Option<FileSharer>> {
+        self.context.remove_unpromoted_sharer(name)
+    }
+
+    fn remove_sharer(&mut self, id: usize) -> Result<Option<FileSharer>> {
+        self.context.remove_sharer(id)
+    }
+
+    fn enqueu_sending_sharer(&mut self, sharer: FileSharer) -> Result<()> {
+        self.context.enqueu_sending_sharer(sharer)
+    }
+
+    fn sending_sharers_queue(&self) -> Result<Shared<Vec<FileSharer>>> {
+        self.context.sending_sharers_queue()
+    }
+
+    fn receiving_sharers(&self) -> Result<FileSharers> {
+        self.context.receiving_sharers()
+    }
+
+    fn limits(&self) -> Result<Limits> {
+        self.context.limits()
+    }
+
+    fn set_limits(&mut self, limits: Limits) -> Result<()> {
+        self.context.set_limits(limits)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.context.close()
+    }
+}
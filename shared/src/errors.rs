@@ -2,6 +2,7 @@
 pub enum ErrorKind {
     NothingToRead,
     MessageSizeExceeded,
+    ReadTimeout { message: String },
     Io { source: std::io::Error },
     ParsingJson { source: serde_json::Error },
     DeserializingBson { source: bson::de::Error },
@@ -11,6 +12,28 @@ pub enum ErrorKind {
     PoisonedLock { message: String },
     SendError { message: String },
     SystemTime { source: std::time::SystemTimeError },
+    Tls { source: rustls::Error },
+}
+
+impl ErrorKind {
+    // Without the details
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::NothingToRead => "NothingToRead",
+            ErrorKind::MessageSizeExceeded => "MessageSizeExceeded",
+            ErrorKind::ReadTimeout { .. } => "ReadTimeout",
+            ErrorKind::Io { .. } => "Io",
+            ErrorKind::ParsingJson { .. } => "ParsingJson",
+            ErrorKind::DeserializingBson { .. } => "DeserializingBson",
+            ErrorKind::SerializingBson { .. } => "SerializingBson",
+            ErrorKind::ConversionBson { .. } => "ConversionBson",
+            ErrorKind::MalformedMessage { .. } => "MalformedMessage",
+            ErrorKind::PoisonedLock { .. } => "PoisonedLock",
+            ErrorKind::SendError { .. } => "SendError",
+            ErrorKind::SystemTime { .. } => "SystemTime",
+            ErrorKind::Tls { .. } => "Tls",
+        }
+    }
 }
 
 impl std::fmt::Display for ErrorKind {
@@ -22,6 +45,9 @@ impl std::fmt::Display for ErrorKind {
             ErrorKind::MessageSizeExceeded => {
                 write!(formatter, "Message maximum size exceeded")
             }
+            ErrorKind::ReadTimeout { message } => {
+                write!(formatter, "Read deadline exceeded > {}", message)
+            }
             ErrorKind::Io { source } => {
                 write!(formatter, "Io > {}", source)
             }
@@ -49,6 +75,9 @@ impl std::fmt::Display for ErrorKind {
             ErrorKind::SystemTime { source } => {
                 write!(formatter, "System time > {}", source)
             }
+            ErrorKind::Tls { source } => {
+                write!(formatter, "TLS > {}", source)
+            }
         }
     }
 }
@@ -100,10 +129,7 @@ impl From<std::io::Error> for Error {
 
 impl From<ErrorKind> for std::io::Error {
     fn from(kind: ErrorKind) -> Self {
-        std::io::Error::new(
-            std::io::ErrorKind::Other,
-            Error { kind: kind }
-        )
+        std::io::Error::other(Error { kind: kind })
     }
 }
 
@@ -197,25 +223,41 @@ impl From<std::time::SystemTimeError> for Error {
     }
 }
 
+impl From<rustls::Error> for Error {
+    fn from(source: rustls::Error) -> Self {
+        Error {
+            kind: ErrorKind::Tls {
+                source: source,
+            }
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub fn with_error_report<F: FnOnce() -> Result<()>>(run: F) {
-    let result = run();
-
-    match &result {
-        Err(error) => {
-            println!("Error > {}", error);
+    if let Err(error) = run() {
+        // Failed before reading the config,
+        // so the defaults will have to do
+        if !crate::logging::is_initialized() {
+            let _ = crate::logging::init(&crate::logging::LoggingConfig::default());
         }
-        _ => {}
-    };
+
+        log::error!("{}", error);
+    }
 }
 
 pub fn is_would_block_error(error: &Error) -> bool {
     match &error.kind {
-        ErrorKind::Io { source } => match source.kind() {
-            std::io::ErrorKind::WouldBlock => true,
-            _ => false
+        ErrorKind::Io { source } => {
+            matches!(source.kind(), std::io::ErrorKind::WouldBlock)
         }
         _ => false
     }
 }
+
+// Nothing has been sent then,
+// the connection is fine
+pub fn is_size_exceeded_error(error: &Error) -> bool {
+    matches!(&error.kind, ErrorKind::MessageSizeExceeded)
+}
@@ -6,10 +6,27 @@ pub mod shared;
 use std::str::{from_utf8, from_utf8_unchecked};
 
 pub fn from_utf8_forced(buffer: &[u8]) -> &str {
-    match from_utf8(&buffer) {
+    match from_utf8(buffer) {
         Ok(content) => content,
         Err(error) => unsafe {
             from_utf8_unchecked(&buffer[..error.valid_up_to()])
         }
     }
 }
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|it| format!("{:02x}", it)).collect()
+}
+
+// Looks at every byte no matter where
+// the first difference is, so that the
+// timing doesn't reveal the secrets
+pub fn equals_in_constant_time(first: &[u8], second: &[u8]) -> bool {
+    if first.len() != second.len() {
+        return false
+    }
+
+    first.iter()
+        .zip(second.iter())
+        .fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
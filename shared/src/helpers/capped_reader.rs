@@ -1,4 +1,6 @@
 use std::io::{Read};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub trait CappedRead: Read {
     fn clear(&mut self);
@@ -6,20 +8,51 @@ pub trait CappedRead: Read {
     fn space_left(&self) -> usize;
 }
 
+// Can be changed from the outside
+// while the reader is in use, e.g.
+// once the other side has told
+// its limits
+#[derive(Clone)]
+pub struct Capacity {
+    inner: Arc<AtomicUsize>,
+}
+
+impl Capacity {
+    pub fn new(value: usize) -> Capacity {
+        Capacity {
+            inner: Arc::new(AtomicUsize::new(value)),
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.inner.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, value: usize) {
+        self.inner.store(value, Ordering::Relaxed);
+    }
+}
+
+impl From<usize> for Capacity {
+    fn from(value: usize) -> Capacity {
+        Capacity::new(value)
+    }
+}
+
 pub struct CappedReader<R> {
-    capacity: usize,
+    capacity: Capacity,
     stream: R,
     offset: usize,
 }
 
 pub trait IntoCappedReader<R: Read> {
-    fn to_capped(self, capacity: usize) -> CappedReader<R>;
+    fn to_capped(self, capacity: impl Into<Capacity>) -> CappedReader<R>;
 }
 
 impl<R: Read> IntoCappedReader<R> for R {
-    fn to_capped(self, capacity: usize) -> CappedReader<R> {
+    fn to_capped(self, capacity: impl Into<Capacity>) -> CappedReader<R> {
         CappedReader {
-            capacity: capacity,
+            capacity: capacity.into(),
             stream: self,
             offset: 0,
         }
@@ -29,7 +62,7 @@ impl<R: Read> IntoCappedReader<R> for R {
 impl<R: Read> Read for CappedReader<R> {
     fn read(&mut self, buffer: &mut [u8]) -> Result<usize, std::io::Error> {
         let max_allowed_count = std::cmp::min(
-            self.capacity - self.offset,
+            self.space_left(),
             buffer.len()
         );
 
@@ -49,7 +82,9 @@ impl<R: Read> CappedRead for CappedReader<R> {
         self.offset = 0;
     }
 
+    // The capacity may have shrunk
+    // after the bytes were read
     fn space_left(&self) -> usize {
-        self.capacity - self.offset
+        self.capacity.get().saturating_sub(self.offset)
     }
 }
@@ -31,10 +31,10 @@ where
         }
     }
 
-    pub fn remove<Q: ?Sized>(&self, key: &Q) -> Result<Option<V>>
+    pub fn remove<Q>(&self, key: &Q) -> Result<Option<V>>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq
+        Q: Hash + Eq + ?Sized,
     {
         Ok(self.inner.write()?.remove(key))
     }
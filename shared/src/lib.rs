@@ -2,6 +2,8 @@ pub mod helpers;
 pub mod errors;
 pub mod communication;
 pub mod connection;
+pub mod logging;
+pub mod transport;
 
 pub use errors::*;
 pub use helpers::shared;
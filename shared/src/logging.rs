@@ -0,0 +1,216 @@
+use std::collections::{HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Write};
+use std::sync::{Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use serde::{Deserialize};
+
+use chrono::{SecondsFormat};
+
+use crate::{Result};
+
+static IS_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    // error, warn, info, debug, trace or off
+    pub level: String,
+    // Module path prefix -> level,
+    // the longest prefix wins
+    pub modules: HashMap<String, String>,
+    pub json: bool,
+    // Stdout if None
+    pub file: Option<String>,
+    pub max_file_size: u64,
+    // Including the current one
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: "info".to_owned(),
+            modules: HashMap::new(),
+            json: false,
+            file: None,
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+fn parse_level(level: &str) -> LevelFilter {
+    level.parse().unwrap_or(LevelFilter::Info)
+}
+
+struct RotatingFile {
+    path: String,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &str, max_size: u64, max_files: usize) -> Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path.to_owned(),
+            file: file,
+            size: size,
+            max_size: max_size,
+            max_files: max_files,
+        })
+    }
+
+    // server.log -> server.log.1 -> server.log.2 ...
+    fn rotate(&mut self) -> Result<()> {
+        for index in (1..self.max_files).rev() {
+            let older = if index == 1 {
+                self.path.clone()
+            } else {
+                format!("{}.{}", &self.path, index - 1)
+            };
+
+            if std::path::Path::new(&older).exists() {
+                std::fs::rename(&older, format!("{}.{}", &self.path, index))?;
+            }
+        }
+
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+enum Output {
+    Stdout,
+    File(RotatingFile),
+}
+
+struct Logger {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+    json: bool,
+    output: Mutex<Output>,
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        // Sorted by the prefix length
+        for (prefix, level) in &self.modules {
+            if target.starts_with(prefix.as_str()) {
+                return *level
+            }
+        }
+
+        self.default
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let time = chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        if self.json {
+            let line = serde_json::json!({
+                "time": time,
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+
+            format!("{}\n", line)
+        } else {
+            format!("<{}> {} {} > {}\n", time, record.level(), record.target(), record.args())
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+
+        let line = self.format(record);
+
+        // There's nowhere to report
+        // the logging failures to
+        if let Ok(mut output) = self.output.lock() {
+            let _ = match &mut *output {
+                Output::Stdout => std::io::stdout().write_all(line.as_bytes()).map_err(crate::Error::from),
+                Output::File(file) => file.write_line(&line),
+            };
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut output) = self.output.lock() {
+            let _ = match &mut *output {
+                Output::Stdout => std::io::stdout().flush(),
+                Output::File(it) => it.file.flush(),
+            };
+        }
+    }
+}
+
+// Only the first call counts, the
+// logger can't be replaced later
+pub fn init(config: &LoggingConfig) -> Result<()> {
+    let mut modules: Vec<(String, LevelFilter)> = config.modules
+        .iter()
+        .map(|(prefix, level)| (prefix.clone(), parse_level(level)))
+        .collect();
+
+    modules.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+
+    let default = parse_level(&config.level);
+
+    let maximum = modules.iter()
+        .map(|(_, it)| *it)
+        .fold(default, std::cmp::max);
+
+    let output = match &config.file {
+        Some(path) => Output::File(RotatingFile::open(path, config.max_file_size, config.max_files)?),
+        None => Output::Stdout,
+    };
+
+    let logger = Logger {
+        default: default,
+        modules: modules,
+        json: config.json,
+        output: Mutex::new(output),
+    };
+
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(maximum);
+        IS_INITIALIZED.store(true, Ordering::SeqCst);
+    }
+
+    Ok(())
+}
+
+// Errors that happen before the config
+// is read still need to be seen
+pub fn is_initialized() -> bool {
+    IS_INITIALIZED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,85 @@
+#[cfg(unix)]
+pub mod unix;
+pub mod memory;
+pub mod tls;
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, TcpListener, Shutdown};
+use std::time::{Duration};
+
+use crate::{Result};
+use crate::shared::{Shared, IntoShared};
+
+// Anything that carries bytes
+// between the two sides
+pub trait Transport: Read + Write + Send + Sync {
+    // Another handle to the same connection,
+    // so that the reading and the writing
+    // may happen in different threads
+    fn try_clone(&self) -> Result<BoxedTransport>;
+
+    // Identifies the other side among the
+    // clients of the same server, stays the
+    // same for all the clones
+    fn peer(&self) -> Result<String>;
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()>;
+
+    // None means waiting forever
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()>;
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()>;
+
+    // Breaks the connection for both
+    // sides, so the reading thread wakes up
+    fn shutdown(&self) -> Result<()>;
+}
+
+pub type BoxedTransport = Box<dyn Transport>;
+
+pub trait Listener: Send {
+    fn accept(&self) -> Result<BoxedTransport>;
+}
+
+// The reading and the writing halves
+pub fn split(transport: BoxedTransport) -> Result<(Shared<BoxedTransport>, Shared<BoxedTransport>)> {
+    let reading = transport.try_clone()?.to_shared();
+    Ok((reading, transport.to_shared()))
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> Result<BoxedTransport> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn peer(&self) -> Result<String> {
+        Ok(self.peer_addr()?.to_string())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        Ok(TcpStream::set_nonblocking(self, nonblocking)?)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(TcpStream::set_read_timeout(self, timeout)?)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(TcpStream::set_write_timeout(self, timeout)?)
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        match TcpStream::shutdown(self, Shutdown::Both) {
+            // Already closed by the other side
+            Err(error) if error.kind() == std::io::ErrorKind::NotConnected => Ok(()),
+            other => Ok(other?),
+        }
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> Result<BoxedTransport> {
+        let (stream, _) = TcpListener::accept(self)?;
+        Ok(Box::new(stream))
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::{VecDeque};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard, Condvar};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::{Duration, Instant};
+
+use crate::{Result, ErrorKind};
+
+use super::{Transport, BoxedTransport, Listener};
+
+static NEXT_PEER: AtomicUsize = AtomicUsize::new(0);
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    is_closed: bool,
+    // The reading side options,
+    // same as for the sockets
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+}
+
+// Bytes flowing in one direction
+struct Pipe {
+    state: Mutex<PipeState>,
+    arrived: Condvar,
+}
+
+impl Pipe {
+    fn new() -> Pipe {
+        let state = PipeState {
+            buffer: VecDeque::new(),
+            is_closed: false,
+            nonblocking: false,
+            read_timeout: None,
+        };
+
+        Pipe {
+            state: Mutex::new(state),
+            arrived: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> std::io::Result<MutexGuard<'_, PipeState>> {
+        match self.state.lock() {
+            Ok(it) => Ok(it),
+            Err(_) => Err(std::io::ErrorKind::Interrupted.into()),
+        }
+    }
+
+    fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.is_closed = true;
+        }
+
+        self.arrived.notify_all();
+    }
+}
+
+// One side of the connection, shared
+// by all the clones of the transport
+struct End {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    peer: String,
+}
+
+// Once nobody holds this side, the
+// other one sees the end of the stream
+impl Drop for End {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+pub struct MemoryTransport {
+    end: Arc<End>,
+}
+
+// Two connected transports
+pub fn pipe() -> (MemoryTransport, MemoryTransport) {
+    let there = Arc::new(Pipe::new());
+    let back = Arc::new(Pipe::new());
+
+    let first = End {
+        incoming: back.clone(),
+        outgoing: there.clone(),
+        peer: format!("memory:{}", NEXT_PEER.fetch_add(1, Ordering::SeqCst)),
+    };
+
+    let second = End {
+        incoming: there,
+        outgoing: back,
+        peer: format!("memory:{}", NEXT_PEER.fetch_add(1, Ordering::SeqCst)),
+    };
+
+    (
+        MemoryTransport { end: Arc::new(first) },
+        MemoryTransport { end: Arc::new(second) },
+    )
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let pipe = &self.end.incoming;
+        let started = Instant::now();
+        let mut state = pipe.lock()?;
+
+        loop {
+            if !state.buffer.is_empty() {
+                let count = std::cmp::min(buffer.len(), state.buffer.len());
+
+                for (slot, it) in buffer.iter_mut().zip(state.buffer.drain(..count)) {
+                    *slot = it;
+                }
+
+                return Ok(count)
+            }
+
+            if state.is_closed {
+                return Ok(0)
+            }
+
+            if state.nonblocking {
+                return Err(std::io::ErrorKind::WouldBlock.into())
+            }
+
+            state = match state.read_timeout {
+                Some(timeout) => {
+                    let elapsed = started.elapsed();
+
+                    // Same as the sockets
+                    // do on Linux
+                    if elapsed >= timeout {
+                        return Err(std::io::ErrorKind::WouldBlock.into())
+                    }
+
+                    match pipe.arrived.wait_timeout(state, timeout - elapsed) {
+                        Ok((it, _)) => it,
+                        Err(_) => return Err(std::io::ErrorKind::Interrupted.into()),
+                    }
+                }
+                None => match pipe.arrived.wait(state) {
+                    Ok(it) => it,
+                    Err(_) => return Err(std::io::ErrorKind::Interrupted.into()),
+                }
+            };
+        }
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let pipe = &self.end.outgoing;
+        let mut state = pipe.lock()?;
+
+        if state.is_closed {
+            return Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        state.buffer.extend(buffer);
+        pipe.arrived.notify_all();
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn try_clone(&self) -> Result<BoxedTransport> {
+        Ok(Box::new(MemoryTransport { end: self.end.clone() }))
+    }
+
+    fn peer(&self) -> Result<String> {
+        Ok(self.end.peer.clone())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.end.incoming.lock()?.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.end.incoming.lock()?.read_timeout = timeout;
+        Ok(())
+    }
+
+    // Writing never blocks
+    fn set_write_timeout(&self, _: Option<Duration>) -> Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.end.incoming.close();
+        self.end.outgoing.close();
+        Ok(())
+    }
+}
+
+pub struct MemoryListener {
+    incoming: Receiver<MemoryTransport>,
+}
+
+#[derive(Clone)]
+pub struct MemoryConnector {
+    outgoing: Sender<MemoryTransport>,
+}
+
+impl MemoryConnector {
+    pub fn connect(&self) -> Result<MemoryTransport> {
+        let (ours, theirs) = pipe();
+
+        if self.outgoing.send(theirs).is_err() {
+            return Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into())
+        }
+
+        Ok(ours)
+    }
+}
+
+// Connections made with the connector
+// are accepted by the listener
+pub fn listener() -> (MemoryListener, MemoryConnector) {
+    let (sender, receiver) = channel();
+
+    (
+        MemoryListener { incoming: receiver },
+        MemoryConnector { outgoing: sender },
+    )
+}
+
+impl Listener for MemoryListener {
+    fn accept(&self) -> Result<BoxedTransport> {
+        match self.incoming.recv() {
+            Ok(it) => Ok(Box::new(it)),
+            // Nobody can connect anymore
+            Err(_) => Err(ErrorKind::NothingToRead.into()),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::convert::{TryFrom};
+use std::io::{Read, Write};
+use std::net::{TcpStream, TcpListener, Shutdown};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rustls::{ClientConnection, ServerConnection, Connection, DigitallySignedStruct, SignatureScheme};
+use rustls::client::danger::{ServerCertVerifier, ServerCertVerified, HandshakeSignatureValid};
+use rustls::crypto::{WebPkiSupportedAlgorithms, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::{PemObject};
+
+use sha2::{Sha256, Digest};
+
+use crate::{Result};
+use crate::helpers::{to_hex};
+
+use super::{Transport, BoxedTransport, Listener};
+
+// How long a write may wait for the
+// socket before giving up, since the
+// TLS records can't be half-sent
+const WRITE_PATIENCE: Duration = Duration::from_secs(5);
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+pub fn fingerprint(certificate: &[u8]) -> String {
+    to_hex(&Sha256::digest(certificate))
+}
+
+fn tls_error(message: String) -> rustls::Error {
+    rustls::Error::General(message)
+}
+
+fn load_chain(certificate: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let chain = CertificateDer::pem_file_iter(certificate)
+        .and_then(|it| it.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|error| tls_error(format!("Couldn't read {} > {}", certificate, error)))?;
+
+    Ok(chain)
+}
+
+// What the clients are going to see,
+// so that it may be checked by hand
+pub fn certificate_fingerprint(certificate: &str) -> Result<String> {
+    match load_chain(certificate)?.first() {
+        Some(it) => Ok(fingerprint(it)),
+        None => Err(tls_error(format!("No certificate in {}", certificate)).into()),
+    }
+}
+
+pub fn load_server_config(certificate: &str, key: &str) -> Result<Arc<rustls::ServerConfig>> {
+    let chain = load_chain(certificate)?;
+
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|error| tls_error(format!("Couldn't read {} > {}", key, error)))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?;
+
+    Ok(Arc::new(config))
+}
+
+// Self-signed certificates can't be checked
+// against any authority, so the client only
+// makes sure it's the same server as before
+#[derive(Debug)]
+struct TrustOnFirstUse {
+    expected: Option<String>,
+    seen: Mutex<Option<String>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for TrustOnFirstUse {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let actual = fingerprint(end_entity);
+
+        if let Some(expected) = &self.expected {
+            if expected != &actual {
+                let message = format!(
+                    "The server certificate has changed: expected {}, but got {}. Remove it from the known hosts if that's expected",
+                    expected,
+                    actual,
+                );
+
+                return Err(tls_error(message))
+            }
+        }
+
+        if let Ok(mut seen) = self.seen.lock() {
+            *seen = Some(actual);
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, certificate, signature, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, certificate, signature, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+// The session is shared by the clones, but
+// each one has its own handle to the socket,
+// so that a blocked reader doesn't keep the
+// writer waiting
+pub struct TlsTransport {
+    session: Arc<Mutex<Connection>>,
+    stream: TcpStream,
+}
+
+impl TlsTransport {
+    // The handshake happens along with
+    // the first reads
+    pub fn accept(stream: TcpStream, config: Arc<rustls::ServerConfig>) -> Result<TlsTransport> {
+        let session = ServerConnection::new(config)?;
+
+        Ok(TlsTransport {
+            session: Arc::new(Mutex::new(session.into())),
+            stream: stream,
+        })
+    }
+
+    // Performs the handshake right away, and returns
+    // the fingerprint of the server certificate.
+    // If `expected` is given, no other certificate
+    // is accepted
+    pub fn connect(
+        mut stream: TcpStream,
+        host: &str,
+        expected: Option<String>,
+    ) -> Result<(TlsTransport, String)> {
+        let verifier = Arc::new(TrustOnFirstUse {
+            expected: expected,
+            seen: Mutex::new(None),
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        });
+
+        let config = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(verifier.clone())
+            .with_no_client_auth();
+
+        let name = ServerName::try_from(host.to_owned())
+            .map_err(|error| tls_error(format!("Bad server name > {}", error)))?;
+
+        let mut session = ClientConnection::new(Arc::new(config), name)?;
+
+        while session.is_handshaking() {
+            session.complete_io(&mut stream)?;
+        }
+
+        let fingerprint = match verifier.seen.lock()?.clone() {
+            Some(it) => it,
+            None => return Err(tls_error("The server has sent no certificate".to_owned()).into())
+        };
+
+        let transport = TlsTransport {
+            session: Arc::new(Mutex::new(session.into())),
+            stream: stream,
+        };
+
+        Ok((transport, fingerprint))
+    }
+
+    fn lock(&self) -> std::io::Result<MutexGuard<'_, Connection>> {
+        match self.session.lock() {
+            Ok(it) => Ok(it),
+            Err(_) => Err(std::io::ErrorKind::Interrupted.into()),
+        }
+    }
+
+    // Whatever TLS wants to say, be it
+    // the data, the handshake or an alert
+    fn send_records(&self, session: &mut Connection) -> std::io::Result<()> {
+        let started = Instant::now();
+        let mut stream = &self.stream;
+
+        while session.wants_write() {
+            match session.write_tls(&mut stream) {
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                    if started.elapsed() > WRITE_PATIENCE {
+                        return Err(error)
+                    }
+
+                    std::thread::sleep(WRITE_RETRY_DELAY);
+                }
+                other => {
+                    other?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn accept_records(&self, data: &[u8]) -> std::io::Result<()> {
+        let mut session = self.lock()?;
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            session.read_tls(&mut rest)?;
+
+            if let Err(error) = session.process_new_packets() {
+                // The other side deserves
+                // to know what went wrong
+                let _ = self.send_records(&mut session);
+                return Err(std::io::Error::other(error))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for TlsTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            {
+                let mut session = self.lock()?;
+                self.send_records(&mut session)?;
+
+                match session.reader().read(buffer) {
+                    Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {}
+                    // Closed without saying goodbye,
+                    // same as a closed socket
+                    Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(0),
+                    other => return other,
+                }
+            }
+
+            // Doesn't hold the session, the
+            // timeouts apply here
+            let mut incoming = [0u8; 4096];
+            let count = (&self.stream).read(&mut incoming)?;
+
+            if count == 0 {
+                return Ok(0)
+            }
+
+            self.accept_records(&incoming[..count])?;
+        }
+    }
+}
+
+impl Write for TlsTransport {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let mut session = self.lock()?;
+        let count = session.writer().write(buffer)?;
+        self.send_records(&mut session)?;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut session = self.lock()?;
+        session.writer().flush()?;
+        self.send_records(&mut session)
+    }
+}
+
+impl Transport for TlsTransport {
+    fn try_clone(&self) -> Result<BoxedTransport> {
+        let clone = TlsTransport {
+            session: self.session.clone(),
+            stream: self.stream.try_clone()?,
+        };
+
+        Ok(Box::new(clone))
+    }
+
+    fn peer(&self) -> Result<String> {
+        Ok(self.stream.peer_addr()?.to_string())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        Ok(self.stream.set_nonblocking(nonblocking)?)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.stream.set_read_timeout(timeout)?)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.stream.set_write_timeout(timeout)?)
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        if let Ok(mut session) = self.lock() {
+            session.send_close_notify();
+            // It's fine if the other
+            // side is already gone
+            let _ = self.send_records(&mut session);
+        }
+
+        match self.stream.shutdown(Shutdown::Both) {
+            Err(error) if error.kind() == std::io::ErrorKind::NotConnected => Ok(()),
+            other => Ok(other?),
+        }
+    }
+}
+
+pub struct TlsListener {
+    listener: TcpListener,
+    config: Arc<rustls::ServerConfig>,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, config: Arc<rustls::ServerConfig>) -> TlsListener {
+        TlsListener {
+            listener: listener,
+            config: config,
+        }
+    }
+}
+
+impl Listener for TlsListener {
+    fn accept(&self) -> Result<BoxedTransport> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(Box::new(TlsTransport::accept(stream, self.config.clone())?))
+    }
+}
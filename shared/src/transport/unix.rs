@@ -0,0 +1,111 @@
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixStream, UnixListener};
+use std::net::{Shutdown};
+use std::path::{Path};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration};
+
+use crate::{Result};
+
+use super::{Transport, BoxedTransport, Listener};
+
+// The client sockets are usually
+// unnamed, so the peers are simply
+// numbered
+static NEXT_PEER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct UnixTransport {
+    stream: UnixStream,
+    peer: String,
+}
+
+impl UnixTransport {
+    pub fn new(stream: UnixStream) -> UnixTransport {
+        let number = NEXT_PEER.fetch_add(1, Ordering::SeqCst);
+
+        UnixTransport {
+            stream: stream,
+            peer: format!("unix:{}", number),
+        }
+    }
+
+    pub fn connect(path: &str) -> Result<UnixTransport> {
+        Ok(UnixTransport::new(UnixStream::connect(path)?))
+    }
+}
+
+impl Read for UnixTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.read(buffer)
+    }
+}
+
+impl Write for UnixTransport {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for UnixTransport {
+    fn try_clone(&self) -> Result<BoxedTransport> {
+        let clone = UnixTransport {
+            stream: self.stream.try_clone()?,
+            peer: self.peer.clone(),
+        };
+
+        Ok(Box::new(clone))
+    }
+
+    fn peer(&self) -> Result<String> {
+        Ok(self.peer.clone())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        Ok(self.stream.set_nonblocking(nonblocking)?)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.stream.set_read_timeout(timeout)?)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.stream.set_write_timeout(timeout)?)
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        match self.stream.shutdown(Shutdown::Both) {
+            // Already closed by the other side
+            Err(error) if error.kind() == std::io::ErrorKind::NotConnected => Ok(()),
+            other => Ok(other?),
+        }
+    }
+}
+
+pub struct UnixSocketListener {
+    listener: UnixListener,
+}
+
+impl UnixSocketListener {
+    // The file left by the previous
+    // run would prevent binding
+    pub fn bind(path: &str) -> Result<UnixSocketListener> {
+        if Path::new(path).exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(UnixSocketListener {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+}
+
+impl Listener for UnixSocketListener {
+    fn accept(&self) -> Result<BoxedTransport> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(Box::new(UnixTransport::new(stream)))
+    }
+}
@@ -0,0 +1,213 @@
+// The exact bytes of the binary format, so
+// that it doesn't change by accident
+
+use std::fmt::{Debug};
+
+use bson::{DateTime};
+
+use shared::{ErrorKind};
+use shared::communication::{ReadMessage, WriteMessage, WireFormat};
+use shared::communication::binary::{BinaryMessage, BinaryReader, BinaryWriter};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+    CommonMessage,
+    BanTarget,
+};
+
+use shared::connection::limits::{Limits, DEFAULT_MESSAGE_SIZE};
+
+fn encode<M: BinaryMessage>(message: &M) -> Vec<u8> {
+    let mut buffer = vec![];
+    BinaryWriter::new(&mut buffer, DEFAULT_MESSAGE_SIZE).write_message(message).unwrap();
+    buffer
+}
+
+fn decode<M: BinaryMessage>(bytes: &[u8]) -> shared::Result<M> {
+    BinaryReader::new(bytes, DEFAULT_MESSAGE_SIZE).read_message()
+}
+
+// Both ways
+fn check<M: BinaryMessage + Debug>(message: M, golden: &[u8]) {
+    assert_eq!(encode(&message), golden);
+
+    let decoded: M = decode(golden).unwrap();
+    assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+}
+
+#[test]
+fn client_messages() {
+    check(ClientMessage::Join, &[0x01, 0x00]);
+
+    check(
+        ClientMessage::Text { text: "Hi".to_owned() },
+        &[0x04, 0x03, 0x02, b'H', b'i'],
+    );
+
+    check(
+        ClientMessage::Authenticate { name: "al".to_owned(), password: "pw".to_owned() },
+        &[0x07, 0x01, 0x02, b'a', b'l', 0x02, b'p', b'w'],
+    );
+
+    check(
+        ClientMessage::Ban { target: BanTarget::Ip("1.2.3.4".to_owned()), duration_seconds: Some(60) },
+        &[0x0C, 0x0A, 0x02, 0x07, b'1', b'.', b'2', b'.', b'3', b'.', b'4', 0x01, 0x3C],
+    );
+
+    check(
+        ClientMessage::Ban { target: BanTarget::Name("x".to_owned()), duration_seconds: None },
+        &[0x05, 0x0A, 0x00, 0x01, b'x', 0x00],
+    );
+
+    check(
+        ClientMessage::TextFragment { text: "Hi".to_owned(), id: 3, index: 0, last: false },
+        &[0x07, 0x12, 0x02, b'H', b'i', 0x03, 0x00, 0x00],
+    );
+}
+
+#[test]
+fn server_messages() {
+    // Zigzag: -1 is 1
+    check(
+        ServerMessage::Text { text: "Hi".to_owned(), name: "bob".to_owned(), time: DateTime::from_millis(-1) },
+        &[0x09, 0x00, 0x02, b'H', b'i', 0x03, b'b', b'o', b'b', 0x01],
+    );
+
+    // 1000 is 2000 after zigzag,
+    // which takes two bytes
+    check(
+        ServerMessage::NewUser { name: "al".to_owned(), time: DateTime::from_millis(1000) },
+        &[0x06, 0x01, 0x02, b'a', b'l', 0xD0, 0x0F],
+    );
+
+    check(
+        ServerMessage::UserList { names: vec!["a".to_owned(), "b".to_owned()], last: true },
+        &[0x07, 0x08, 0x02, 0x01, b'a', 0x01, b'b', 0x01],
+    );
+
+    check(
+        ServerMessage::UserBanned { target: BanTarget::Name("x".to_owned()), by: "op".to_owned(), until: None },
+        &[0x08, 0x0B, 0x00, 0x01, b'x', 0x02, b'o', b'p', 0x00],
+    );
+
+    let limits = Limits {
+        format: WireFormat::Binary,
+        message_size: 1024,
+        chunk_size: 1,
+        text_size: 2,
+        name_size: 3,
+        password_size: 4,
+        file_name_size: 5,
+        fragment_size: 6,
+        long_text_size: 7,
+        text_fragments: 8,
+    };
+
+    check(
+        ServerMessage::Limits { limits },
+        &[0x0C, 0x14, 0x02, 0x80, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+    );
+
+    check(
+        ServerMessage::TextFragment {
+            text: "Hi".to_owned(),
+            name: "al".to_owned(),
+            time: DateTime::from_millis(0),
+            id: 3,
+            index: 1,
+            last: true,
+        },
+        &[0x0B, 0x15, 0x02, b'H', b'i', 0x02, b'a', b'l', 0x00, 0x03, 0x01, 0x01],
+    );
+}
+
+#[test]
+fn common_messages() {
+    check(CommonMessage::Ping, &[0x01, 0x01]);
+    check(CommonMessage::Pong, &[0x01, 0x02]);
+
+    // 300 is a two-byte varint
+    check(
+        ClientMessage::Common { common: CommonMessage::Chunk { data: vec![1, 2, 3], id: 300 } },
+        &[0x08, 0x0D, 0x00, 0x03, 0x01, 0x02, 0x03, 0xAC, 0x02],
+    );
+
+    check(
+        ServerMessage::Common { common: CommonMessage::Ping },
+        &[0x02, 0x0F, 0x01],
+    );
+}
+
+#[test]
+fn long_messages_have_longer_prefixes() {
+    let text = "a".repeat(200);
+    let encoded = encode(&ClientMessage::Text { text: text.clone() });
+
+    // 203 bytes of the body
+    assert_eq!(&encoded[..5], &[0xCB, 0x01, 0x03, 0xC8, 0x01]);
+    assert_eq!(encoded.len(), 205);
+
+    let decoded: ClientMessage = decode(&encoded).unwrap();
+    assert!(matches!(decoded, ClientMessage::Text { text: it } if it == text));
+}
+
+#[test]
+fn messages_are_read_one_by_one() {
+    let mut bytes = encode(&ClientMessage::Join);
+    bytes.extend(encode(&ClientMessage::Leave));
+
+    let mut reader = BinaryReader::new(bytes.as_slice(), DEFAULT_MESSAGE_SIZE);
+
+    let first: ClientMessage = reader.read_message().unwrap();
+    let second: ClientMessage = reader.read_message().unwrap();
+
+    assert!(matches!(first, ClientMessage::Join));
+    assert!(matches!(second, ClientMessage::Leave));
+}
+
+#[test]
+fn trailing_fields_are_skipped() {
+    // Join with something the
+    // newer versions might add
+    let decoded: ClientMessage = decode(&[0x03, 0x00, 0x01, b'?']).unwrap();
+    assert!(matches!(decoded, ClientMessage::Join));
+}
+
+#[test]
+fn truncated_messages_are_malformed() {
+    // The text claims 5 bytes, but
+    // the message ends right there
+    let error = decode::<ClientMessage>(&[0x02, 0x03, 0x05]).unwrap_err();
+    assert!(matches!(error.kind, ErrorKind::MalformedMessage { .. }));
+}
+
+#[test]
+fn oversized_messages_are_refused_early() {
+    // Only the prefix has arrived
+    let error = decode::<ClientMessage>(&[0x80, 0x10]).unwrap_err();
+
+    match error.kind {
+        ErrorKind::Io { source } => assert_eq!(source.kind(), std::io::ErrorKind::InvalidData),
+        other => panic!("Expected too much data, but got {}", other),
+    }
+}
+
+#[test]
+fn unknown_tags_are_kept() {
+    // Whatever a newer server
+    // might've added as 99
+    let golden = [0x03, 0x63, 0x01, b'?'];
+    let decoded: ServerMessage = decode(&golden).unwrap();
+
+    match &decoded {
+        ServerMessage::Unknown { tag, raw } => {
+            assert_eq!(tag, "99");
+            assert_eq!(raw, &[0x63, 0x01, b'?']);
+        }
+        other => panic!("Expected an unknown message, but got {:?}", other),
+    }
+
+    // And passed on untouched
+    assert_eq!(encode(&decoded), golden);
+}
@@ -0,0 +1,60 @@
+// Long texts are cut into pieces on
+// one side and glued on the other
+
+use shared::{ErrorKind, is_size_exceeded_error};
+use shared::connection::fragments::{Assembler, split_text};
+
+#[test]
+fn pieces_are_cut_between_characters() {
+    // Two bytes each
+    let text = "ыыыы";
+    let pieces = split_text(text, 3);
+
+    assert_eq!(pieces, vec!["ы", "ы", "ы", "ы"]);
+    assert_eq!(pieces.concat(), text);
+
+    assert_eq!(split_text("abcde", 2), vec!["ab", "cd", "e"]);
+    assert!(split_text("", 2).is_empty());
+}
+
+#[test]
+fn pieces_are_measured_escaped() {
+    // Two bytes each in JSON
+    assert_eq!(split_text("\n\n\na", 5), vec!["\n\n", "\na"]);
+}
+
+#[test]
+fn pieces_are_glued_back() {
+    let mut texts = Assembler::new(100, 10);
+
+    assert_eq!(texts.push(1, 0, "Hello, ", false).unwrap(), None);
+    // Another text in between
+    assert_eq!(texts.push(2, 0, "Hi", true).unwrap(), Some("Hi".to_owned()));
+    assert_eq!(texts.push(1, 1, "world", true).unwrap(), Some("Hello, world".to_owned()));
+}
+
+#[test]
+fn pieces_out_of_order_drop_the_text() {
+    let mut texts = Assembler::new(100, 10);
+
+    texts.push(1, 0, "a", false).unwrap();
+    let error = texts.push(1, 2, "c", true).unwrap_err();
+
+    assert!(matches!(error.kind, ErrorKind::MalformedMessage { .. }));
+    assert!(texts.push(1, 1, "b", true).is_err());
+}
+
+#[test]
+fn unfinished_texts_are_limited_together() {
+    let mut texts = Assembler::new(10, 3);
+
+    texts.push(1, 0, "12345", false).unwrap();
+    texts.push(2, 0, "1234", false).unwrap();
+    assert!(is_size_exceeded_error(&texts.push(3, 0, "12", false).unwrap_err()));
+
+    // Finished texts don't count anymore
+    texts.push(1, 1, "", true).unwrap();
+    texts.push(3, 0, "12", false).unwrap();
+    texts.push(3, 1, "", false).unwrap();
+    assert!(is_size_exceeded_error(&texts.push(3, 2, "", false).unwrap_err()));
+}
@@ -0,0 +1,18 @@
+// The secrets are compared without
+// stopping at the first difference
+
+use shared::helpers::{equals_in_constant_time};
+
+#[test]
+fn equal_secrets_match() {
+    assert!(equals_in_constant_time(b"", b""));
+    assert!(equals_in_constant_time(b"0a1b2c", b"0a1b2c"));
+}
+
+#[test]
+fn different_secrets_do_not_match() {
+    assert!(!equals_in_constant_time(b"0a1b2c", b"0a1b2d"));
+    assert!(!equals_in_constant_time(b"xa1b2c", b"0a1b2c"));
+    assert!(!equals_in_constant_time(b"0a1b2c", b"0a1b2"));
+    assert!(!equals_in_constant_time(b"", b"0"));
+}
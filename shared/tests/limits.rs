@@ -0,0 +1,139 @@
+// The limits are only as good as the
+// samples they are measured with
+
+use bson::{DateTime};
+
+use shared::{is_size_exceeded_error};
+use shared::communication::{WriteMessage, WireFormat};
+use shared::communication::arson::{ArsonWriter};
+use shared::communication::json::{JsonWriter};
+use shared::communication::binary::{BinaryWriter};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+    CommonMessage,
+};
+
+use shared::connection::limits::{Limits, DEFAULT_MESSAGE_SIZE, field_size};
+
+fn text(size: usize) -> ServerMessage {
+    ServerMessage::Text {
+        text: "ы".repeat(size / 2),
+        name: "x".repeat(size),
+        time: DateTime::now(),
+    }
+}
+
+fn chunk(size: usize) -> ServerMessage {
+    ServerMessage::Common {
+        common: CommonMessage::Chunk {
+            data: vec![255; size],
+            // The widest id there is
+            id: i64::MAX as usize,
+        },
+    }
+}
+
+#[test]
+fn texts_fit_into_every_format() {
+    let limits = Limits::default();
+
+    for format in WireFormat::ALL.iter() {
+        let it = Limits::new(*format, DEFAULT_MESSAGE_SIZE);
+
+        assert_eq!(it.text_size, limits.text_size);
+        assert!(it.fits(&text(limits.text_size)), "{:?}", format);
+        assert!(it.fits(&ClientMessage::Authenticate {
+            name: "x".repeat(it.name_size),
+            password: "x".repeat(it.password_size),
+        }));
+        assert!(it.fits(&ServerMessage::TextFragment {
+            text: "x".repeat(it.fragment_size),
+            name: "x".repeat(it.name_size),
+            time: DateTime::now(),
+            id: i64::MAX as usize,
+            index: i64::MAX as usize,
+            last: true,
+        }));
+    }
+}
+
+#[test]
+fn chunks_fill_the_message() {
+    for format in WireFormat::ALL.iter() {
+        let it = Limits::new(*format, DEFAULT_MESSAGE_SIZE);
+
+        assert!(it.fits(&chunk(it.chunk_size)), "{:?}", format);
+        assert!(!it.fits(&chunk(it.chunk_size + 1)), "{:?}", format);
+    }
+}
+
+#[test]
+fn limits_follow_the_message_size() {
+    let small = Limits::new(WireFormat::Binary, DEFAULT_MESSAGE_SIZE);
+    let large = Limits::new(WireFormat::Binary, DEFAULT_MESSAGE_SIZE * 4);
+
+    assert!(large.chunk_size > small.chunk_size * 3);
+    assert!(large.text_size > small.text_size * 3);
+}
+
+fn write(format: WireFormat, buffer: &mut Vec<u8>, message: &ClientMessage) -> shared::Result<()> {
+    let cap = DEFAULT_MESSAGE_SIZE;
+
+    match format {
+        WireFormat::Arson => ArsonWriter::new(buffer, cap).write_message(message),
+        WireFormat::Json => JsonWriter::new(buffer, cap).write_message(message),
+        WireFormat::Binary => BinaryWriter::new(buffer, cap).write_message(message),
+    }
+}
+
+#[test]
+fn oversized_messages_are_not_written() {
+    let message = ClientMessage::Text { text: "x".repeat(DEFAULT_MESSAGE_SIZE) };
+
+    for format in WireFormat::ALL.iter() {
+        let mut buffer = vec![];
+        let error = write(*format, &mut buffer, &message).unwrap_err();
+
+        assert!(is_size_exceeded_error(&error), "{:?}", format);
+        assert!(buffer.is_empty(), "{:?}", format);
+    }
+}
+
+#[test]
+fn fields_are_checked_one_by_one() {
+    let limits = Limits::default();
+
+    // Fits into a message, but
+    // not into the text field
+    let text = ClientMessage::Text { text: "x".repeat(limits.text_size + 1) };
+
+    assert!(limits.fits(&text));
+    assert!(is_size_exceeded_error(&limits.check(&text).unwrap_err()));
+
+    let name = ClientMessage::Rename { new_name: "x".repeat(limits.name_size) };
+    assert!(limits.check(&name).is_ok());
+}
+
+// JSON takes 2 bytes for a line break
+// and 6 for the other control characters
+#[test]
+fn escaped_texts_fit_into_json() {
+    let limits = Limits::default();
+    let json = Limits::new(WireFormat::Json, DEFAULT_MESSAGE_SIZE);
+
+    for unit in ["\n", "\"", "\u{1}"].iter() {
+        let text = unit.repeat(limits.text_size / field_size(unit));
+        let name = unit.repeat(limits.name_size / field_size(unit));
+
+        assert!(limits.check(&ClientMessage::Text { text: text.clone() }).is_ok(), "{:?}", unit);
+        assert!(json.fits(&ServerMessage::Text { text: text, name: name, time: DateTime::now() }), "{:?}", unit);
+    }
+
+    // Short enough in bytes, but
+    // not once it's escaped
+    let text = "\n".repeat(limits.text_size / 2 + 1);
+    assert!(text.len() <= limits.text_size);
+    assert!(is_size_exceeded_error(&limits.check(&ClientMessage::Text { text: text }).unwrap_err()));
+}
@@ -0,0 +1,123 @@
+// The messages from the newer versions
+// must not break the older ones
+
+use bson::{doc};
+
+use shared::communication::{ReadMessage, WriteMessage};
+use shared::communication::arson::{ArsonReader, ArsonWriter};
+use shared::communication::json::{JsonReader};
+use shared::communication::binary::{BinaryReader};
+
+use shared::connection::messages::{
+    ClientMessage,
+    ServerMessage,
+    CommonMessage,
+};
+
+use shared::connection::limits::{DEFAULT_MESSAGE_SIZE};
+
+#[test]
+fn unknown_arson_variants() {
+    let mut bytes = vec![];
+    doc! { "Wave": { "hand": "left" } }.to_writer(&mut bytes).unwrap();
+    doc! { "Join": bson::Bson::Null }.to_writer(&mut bytes).unwrap();
+
+    let mut reader = ArsonReader::new(bytes.as_slice(), DEFAULT_MESSAGE_SIZE);
+
+    let first: ClientMessage = reader.read_message().unwrap();
+    assert!(matches!(first, ClientMessage::Unknown { tag, .. } if tag == "Wave"));
+
+    // The stream goes on
+    let second: ClientMessage = reader.read_message().unwrap();
+    assert!(matches!(second, ClientMessage::Join));
+}
+
+#[test]
+fn unknown_json_variants() {
+    let bytes = b"\"Shrug\"\n{\"Wave\":{\"hand\":\"left\"}}\n{\"Support\":{\"text\":\"Hi\"}}\n";
+    let mut reader = JsonReader::new(&bytes[..], DEFAULT_MESSAGE_SIZE);
+
+    let first: ServerMessage = reader.read_message().unwrap();
+    assert!(matches!(first, ServerMessage::Unknown { tag, .. } if tag == "Shrug"));
+
+    let second: ServerMessage = reader.read_message().unwrap();
+
+    match second {
+        ServerMessage::Unknown { tag, raw } => {
+            assert_eq!(tag, "Wave");
+            assert_eq!(raw, b"{\"Wave\":{\"hand\":\"left\"}}\n");
+        }
+        other => panic!("Expected an unknown message, but got {:?}", other),
+    }
+
+    let third: ServerMessage = reader.read_message().unwrap();
+    assert!(matches!(third, ServerMessage::Support { text } if text == "Hi"));
+}
+
+#[test]
+fn unknown_fields_are_skipped() {
+    let bytes = b"{\"Support\":{\"text\":\"Hi\",\"color\":\"red\"}}\n";
+    let mut reader = JsonReader::new(&bytes[..], DEFAULT_MESSAGE_SIZE);
+
+    let message: ServerMessage = reader.read_message().unwrap();
+    assert!(matches!(message, ServerMessage::Support { text } if text == "Hi"));
+}
+
+fn is_unknown_common(message: &ServerMessage, expected: &str) -> bool {
+    matches!(message, ServerMessage::Common { common: CommonMessage::Unknown { tag } } if tag == expected)
+}
+
+#[test]
+fn unknown_common_variants_in_json() {
+    let bytes = b"{\"Common\":{\"common\":\"Shrug\"}}\n{\"Common\":{\"common\":{\"Wave\":{\"hand\":\"left\"}}}}\n{\"Common\":{\"common\":\"Ping\"}}\n";
+    let mut reader = JsonReader::new(&bytes[..], DEFAULT_MESSAGE_SIZE);
+
+    let first: ServerMessage = reader.read_message().unwrap();
+    assert!(is_unknown_common(&first, "Shrug"), "Got {:?}", first);
+
+    let second: ServerMessage = reader.read_message().unwrap();
+    assert!(is_unknown_common(&second, "Wave"), "Got {:?}", second);
+
+    let third: ServerMessage = reader.read_message().unwrap();
+    assert!(matches!(third, ServerMessage::Common { common: CommonMessage::Ping }));
+}
+
+#[test]
+fn unknown_common_variants_in_arson() {
+    let mut bytes = vec![];
+    doc! { "Common": { "common": { "Wave": { "hand": "left" } } } }.to_writer(&mut bytes).unwrap();
+
+    // The known ones still arrive the
+    // same way they're written
+    let chunk = ServerMessage::Common { common: CommonMessage::Chunk { data: vec![1, 2, 3], id: 7 } };
+    ArsonWriter::new(&mut bytes, DEFAULT_MESSAGE_SIZE).write_message(&chunk).unwrap();
+
+    let mut reader = ArsonReader::new(bytes.as_slice(), DEFAULT_MESSAGE_SIZE);
+
+    let first: ServerMessage = reader.read_message().unwrap();
+    assert!(is_unknown_common(&first, "Wave"), "Got {:?}", first);
+
+    let second: ServerMessage = reader.read_message().unwrap();
+
+    match second {
+        ServerMessage::Common { common: CommonMessage::Chunk { data, id } } => {
+            assert_eq!(data, vec![1, 2, 3]);
+            assert_eq!(id, 7);
+        }
+        other => panic!("Expected a chunk, but got {:?}", other),
+    }
+}
+
+#[test]
+fn unknown_common_variants_in_binary() {
+    // Common is 15, the unknown one is 7 with
+    // a byte of its own, then a Ping
+    let bytes = [0x03, 0x0F, 0x07, 0x05, 0x02, 0x0F, 0x01];
+    let mut reader = BinaryReader::new(&bytes[..], DEFAULT_MESSAGE_SIZE);
+
+    let first: ServerMessage = reader.read_message().unwrap();
+    assert!(is_unknown_common(&first, "7"), "Got {:?}", first);
+
+    let second: ServerMessage = reader.read_message().unwrap();
+    assert!(matches!(second, ServerMessage::Common { common: CommonMessage::Ping }));
+}